@@ -0,0 +1,18 @@
+ALTER TABLE users ADD COLUMN totp_secret_base32 VARCHAR(32); -- 20 random bytes, base32 encoded => 32 characters.
+ALTER TABLE users ADD COLUMN totp_enabled_at BIGINT; -- seconds since the unix epoch, null if totp is not in use
+ALTER TABLE users ADD COLUMN totp_last_step BIGINT; -- the last accepted time step, to prevent code reuse
+
+CREATE TABLE IF NOT EXISTS totp_recovery_codes (
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    code_hash_base64 VARCHAR(44) NOT NULL, -- SHA256 of the code, base64 encoded => 44 characters.
+    PRIMARY KEY (user_id, code_hash_base64)
+);
+
+CREATE TABLE IF NOT EXISTS pending_logins (
+    uuid VARCHAR(36) PRIMARY KEY NOT NULL,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    created_at BIGINT NOT NULL, -- seconds since the unix epoch
+    -- The TOTP or recovery codes tried for the pending login, which is deleted
+    -- after too many wrong ones.
+    attempts INTEGER NOT NULL DEFAULT 0
+);
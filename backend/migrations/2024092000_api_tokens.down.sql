@@ -0,0 +1,2 @@
+DROP INDEX api_token_hash_index;
+DROP TABLE api_tokens;
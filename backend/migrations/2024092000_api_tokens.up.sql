@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS api_tokens (
+    uuid VARCHAR(36) PRIMARY KEY NOT NULL,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    name TEXT NOT NULL,
+    token_hash_base64 VARCHAR(44) UNIQUE NOT NULL, -- SHA256 of the token, base64 encoded => 44 characters.
+    scopes INTEGER NOT NULL, -- bitflags, see ApiTokenScope in src/data/user.rs
+    created_at BIGINT NOT NULL, -- seconds since the unix epoch
+    expires_at BIGINT, -- seconds since the unix epoch, null if the token does not expire
+    last_used_at BIGINT -- seconds since the unix epoch
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS api_token_hash_index ON api_tokens ( token_hash_base64 );
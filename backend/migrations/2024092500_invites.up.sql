@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS invites (
+    code VARCHAR(16) PRIMARY KEY NOT NULL, -- 10 random bytes, base32 encoded => 16 characters.
+    created_by INTEGER REFERENCES users (id) ON DELETE SET NULL ON UPDATE CASCADE,
+    created_at BIGINT NOT NULL, -- seconds since the unix epoch
+    expires_at BIGINT, -- seconds since the unix epoch, null if the invite does not expire
+    used_at BIGINT, -- seconds since the unix epoch
+    used_by INTEGER REFERENCES users (id) ON DELETE SET NULL ON UPDATE CASCADE
+);
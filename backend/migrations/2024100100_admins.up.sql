@@ -0,0 +1,9 @@
+ALTER TABLE users ADD COLUMN is_admin INTEGER NOT NULL DEFAULT 0; -- 1 for administrators, 0 for everyone else
+ALTER TABLE users ADD COLUMN disabled_at BIGINT; -- seconds since the unix epoch, null if the account is usable
+
+CREATE TABLE IF NOT EXISTS password_resets (
+    token_hash_base64 VARCHAR(44) PRIMARY KEY NOT NULL, -- SHA256 of the token, base64 encoded => 44 characters.
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    created_at BIGINT NOT NULL, -- seconds since the unix epoch
+    expires_at BIGINT NOT NULL -- seconds since the unix epoch
+);
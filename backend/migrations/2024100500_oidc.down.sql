@@ -0,0 +1,3 @@
+DROP TABLE oidc_logins;
+DROP INDEX oidc_identities_user_index;
+DROP TABLE oidc_identities;
@@ -0,0 +1,17 @@
+CREATE TABLE IF NOT EXISTS oidc_identities (
+    issuer VARCHAR(255) NOT NULL,
+    subject VARCHAR(255) NOT NULL, -- The "sub" claim, which is at most 255 ASCII characters per the OpenID Connect spec.
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    created_at BIGINT NOT NULL, -- seconds since the unix epoch
+    PRIMARY KEY (issuer, subject)
+);
+
+CREATE INDEX IF NOT EXISTS oidc_identities_user_index ON oidc_identities ( user_id );
+
+CREATE TABLE IF NOT EXISTS oidc_logins (
+    state VARCHAR(43) PRIMARY KEY NOT NULL, -- 32 random bytes, base64url encoded => 43 characters.
+    nonce VARCHAR(43) NOT NULL, -- 32 random bytes, base64url encoded => 43 characters.
+    code_verifier VARCHAR(43) NOT NULL, -- 32 random bytes, base64url encoded => 43 characters.
+    link_user_id INTEGER REFERENCES users (id) ON DELETE CASCADE ON UPDATE CASCADE, -- set if a logged in user is linking an identity to their account
+    created_at BIGINT NOT NULL -- seconds since the unix epoch
+);
@@ -0,0 +1,2 @@
+DROP TABLE user_profile_links;
+DROP TABLE user_profiles;
@@ -0,0 +1,17 @@
+CREATE TABLE IF NOT EXISTS user_profiles (
+    user_id INTEGER PRIMARY KEY NOT NULL REFERENCES users (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    display_name TEXT NOT NULL,
+    bio TEXT NOT NULL,
+    avatar_content_type VARCHAR(60), -- null if the user has no avatar
+    avatar_bytes_base64 TEXT, -- null if the user has no avatar
+    default_author INTEGER NOT NULL DEFAULT 0, -- 1 if the display name should be the default author of new portfolios
+    updated_at BIGINT NOT NULL -- seconds since the unix epoch
+);
+
+CREATE TABLE IF NOT EXISTS user_profile_links (
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    order_number INTEGER NOT NULL,
+    title TEXT NOT NULL,
+    href TEXT NOT NULL,
+    PRIMARY KEY (user_id, order_number)
+);
@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS username_history (
+    username VARCHAR(30) PRIMARY KEY NOT NULL, -- a previous username, reserved for the user for a while after changing it
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    changed_at BIGINT NOT NULL -- seconds since the unix epoch
+);
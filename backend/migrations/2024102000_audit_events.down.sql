@@ -0,0 +1,3 @@
+DROP INDEX audit_events_created_at_index;
+DROP INDEX audit_events_actor_index;
+DROP TABLE audit_events;
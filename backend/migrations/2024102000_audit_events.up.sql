@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS audit_events (
+    id INTEGER PRIMARY KEY NOT NULL,
+    created_at BIGINT NOT NULL, -- seconds since the unix epoch
+    -- Not a foreign key, so that the log stays untouched even if the user is deleted.
+    -- For logins, this is the user whose account was being logged into.
+    actor_user_id INTEGER,
+    action INTEGER NOT NULL,
+    target TEXT NOT NULL, -- e.g. the username, or the slug of the portfolio or work
+    ip VARCHAR(45) NOT NULL, -- long enough for ipv6 addresses
+    user_agent TEXT
+);
+
+CREATE INDEX IF NOT EXISTS audit_events_actor_index ON audit_events ( actor_user_id, id );
+CREATE INDEX IF NOT EXISTS audit_events_created_at_index ON audit_events ( created_at );
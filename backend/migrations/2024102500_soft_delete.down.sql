@@ -0,0 +1,5 @@
+DROP INDEX portfolios_deleted_at_index;
+DROP INDEX works_deleted_at_index;
+
+ALTER TABLE portfolios DROP COLUMN deleted_at;
+ALTER TABLE works DROP COLUMN deleted_at;
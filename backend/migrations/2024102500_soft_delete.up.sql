@@ -0,0 +1,5 @@
+ALTER TABLE works ADD COLUMN deleted_at BIGINT; -- seconds since the unix epoch
+ALTER TABLE portfolios ADD COLUMN deleted_at BIGINT; -- seconds since the unix epoch
+
+CREATE INDEX IF NOT EXISTS works_deleted_at_index ON works ( deleted_at );
+CREATE INDEX IF NOT EXISTS portfolios_deleted_at_index ON portfolios ( deleted_at );
@@ -0,0 +1,2 @@
+ALTER TABLE portfolio_rights DROP COLUMN role;
+ALTER TABLE work_rights DROP COLUMN role;
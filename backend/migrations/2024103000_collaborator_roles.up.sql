@@ -0,0 +1,3 @@
+-- 1 = owner, 2 = editor, 3 = viewer, see CollaboratorRole
+ALTER TABLE work_rights ADD COLUMN role INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE portfolio_rights ADD COLUMN role INTEGER NOT NULL DEFAULT 1;
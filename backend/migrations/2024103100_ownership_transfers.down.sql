@@ -0,0 +1,5 @@
+DROP INDEX ownership_transfers_to_user_index;
+DROP INDEX ownership_transfers_portfolio_index;
+DROP INDEX ownership_transfers_work_index;
+
+DROP TABLE ownership_transfers;
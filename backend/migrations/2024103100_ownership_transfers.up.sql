@@ -0,0 +1,15 @@
+-- Pending transfers of ownership, which the recipient can accept. Exactly one
+-- of work_id and portfolio_id is set.
+CREATE TABLE IF NOT EXISTS ownership_transfers (
+    id INTEGER PRIMARY KEY NOT NULL,
+    created_at BIGINT NOT NULL, -- seconds since the unix epoch
+    work_id INTEGER REFERENCES works (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    portfolio_id INTEGER REFERENCES portfolios (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    from_user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    to_user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+-- Only one pending transfer per work or portfolio
+CREATE UNIQUE INDEX IF NOT EXISTS ownership_transfers_work_index ON ownership_transfers ( work_id );
+CREATE UNIQUE INDEX IF NOT EXISTS ownership_transfers_portfolio_index ON ownership_transfers ( portfolio_id );
+CREATE INDEX IF NOT EXISTS ownership_transfers_to_user_index ON ownership_transfers ( to_user_id );
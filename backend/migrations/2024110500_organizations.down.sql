@@ -0,0 +1,28 @@
+DROP VIEW effective_portfolio_rights;
+DROP VIEW effective_work_rights;
+
+CREATE TABLE IF NOT EXISTS portfolio_rights_old (
+    portfolio_id INTEGER NOT NULL REFERENCES portfolios (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    role INTEGER NOT NULL DEFAULT 1,
+    PRIMARY KEY (portfolio_id, user_id)
+);
+INSERT INTO portfolio_rights_old (portfolio_id, user_id, role)
+    SELECT portfolio_id, user_id, role FROM portfolio_rights WHERE user_id IS NOT NULL;
+DROP TABLE portfolio_rights;
+ALTER TABLE portfolio_rights_old RENAME TO portfolio_rights;
+
+CREATE TABLE IF NOT EXISTS work_rights_old (
+    work_id INTEGER NOT NULL REFERENCES works (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    role INTEGER NOT NULL DEFAULT 1,
+    PRIMARY KEY (work_id, user_id)
+);
+INSERT INTO work_rights_old (work_id, user_id, role)
+    SELECT work_id, user_id, role FROM work_rights WHERE user_id IS NOT NULL;
+DROP TABLE work_rights;
+ALTER TABLE work_rights_old RENAME TO work_rights;
+
+DROP INDEX organization_members_user_index;
+DROP TABLE organization_members;
+DROP TABLE organizations;
@@ -0,0 +1,58 @@
+CREATE TABLE IF NOT EXISTS organizations (
+    id INTEGER PRIMARY KEY NOT NULL,
+    created_at BIGINT NOT NULL, -- seconds since the unix epoch
+    slug VARCHAR(60) UNIQUE NOT NULL,
+    name TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS organization_members (
+    organization_id INTEGER NOT NULL REFERENCES organizations (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    role INTEGER NOT NULL, -- 1 = admin, 2 = member, see OrganizationRole
+    PRIMARY KEY (organization_id, user_id)
+);
+
+CREATE INDEX IF NOT EXISTS organization_members_user_index ON organization_members ( user_id );
+
+-- The rights tables are recreated, since the user_id column can't be made
+-- nullable in SQLite without that. Each row now refers to either a user or an
+-- organization, whose members all get the row's role.
+CREATE TABLE IF NOT EXISTS work_rights_new (
+    work_id INTEGER NOT NULL REFERENCES works (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    user_id INTEGER REFERENCES users (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    organization_id INTEGER REFERENCES organizations (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    role INTEGER NOT NULL DEFAULT 1, -- 1 = owner, 2 = editor, 3 = viewer, see CollaboratorRole
+    CHECK ((user_id IS NULL) <> (organization_id IS NULL))
+);
+INSERT INTO work_rights_new (work_id, user_id, role) SELECT work_id, user_id, role FROM work_rights;
+DROP TABLE work_rights;
+ALTER TABLE work_rights_new RENAME TO work_rights;
+CREATE UNIQUE INDEX IF NOT EXISTS work_rights_user_index ON work_rights ( work_id, user_id );
+CREATE UNIQUE INDEX IF NOT EXISTS work_rights_organization_index ON work_rights ( work_id, organization_id );
+
+CREATE TABLE IF NOT EXISTS portfolio_rights_new (
+    portfolio_id INTEGER NOT NULL REFERENCES portfolios (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    user_id INTEGER REFERENCES users (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    organization_id INTEGER REFERENCES organizations (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    role INTEGER NOT NULL DEFAULT 1, -- 1 = owner, 2 = editor, 3 = viewer, see CollaboratorRole
+    CHECK ((user_id IS NULL) <> (organization_id IS NULL))
+);
+INSERT INTO portfolio_rights_new (portfolio_id, user_id, role) SELECT portfolio_id, user_id, role FROM portfolio_rights;
+DROP TABLE portfolio_rights;
+ALTER TABLE portfolio_rights_new RENAME TO portfolio_rights;
+CREATE UNIQUE INDEX IF NOT EXISTS portfolio_rights_user_index ON portfolio_rights ( portfolio_id, user_id );
+CREATE UNIQUE INDEX IF NOT EXISTS portfolio_rights_organization_index ON portfolio_rights ( portfolio_id, organization_id );
+
+-- The rights of each user, whether given to them directly or through an
+-- organization. A user can have multiple rows for the same work or portfolio.
+CREATE VIEW effective_work_rights AS
+    SELECT work_id, user_id, role FROM work_rights WHERE user_id IS NOT NULL
+    UNION ALL
+    SELECT work_rights.work_id, organization_members.user_id, work_rights.role FROM work_rights
+        JOIN organization_members ON (organization_members.organization_id = work_rights.organization_id);
+
+CREATE VIEW effective_portfolio_rights AS
+    SELECT portfolio_id, user_id, role FROM portfolio_rights WHERE user_id IS NOT NULL
+    UNION ALL
+    SELECT portfolio_rights.portfolio_id, organization_members.user_id, portfolio_rights.role FROM portfolio_rights
+        JOIN organization_members ON (organization_members.organization_id = portfolio_rights.organization_id);
@@ -0,0 +1,5 @@
+DROP INDEX portfolios_unpublish_at_index;
+DROP INDEX portfolios_publish_at_index;
+
+ALTER TABLE portfolios DROP COLUMN unpublish_at;
+ALTER TABLE portfolios DROP COLUMN publish_at;
@@ -0,0 +1,5 @@
+ALTER TABLE portfolios ADD COLUMN publish_at BIGINT; -- seconds since the unix epoch
+ALTER TABLE portfolios ADD COLUMN unpublish_at BIGINT; -- seconds since the unix epoch
+
+CREATE INDEX IF NOT EXISTS portfolios_publish_at_index ON portfolios ( publish_at );
+CREATE INDEX IF NOT EXISTS portfolios_unpublish_at_index ON portfolios ( unpublish_at );
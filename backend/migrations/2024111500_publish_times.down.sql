@@ -0,0 +1,3 @@
+ALTER TABLE works DROP COLUMN updated_at;
+ALTER TABLE portfolios DROP COLUMN updated_at;
+ALTER TABLE portfolios DROP COLUMN first_published_at;
@@ -0,0 +1,12 @@
+ALTER TABLE portfolios ADD COLUMN first_published_at BIGINT; -- seconds since the unix epoch
+ALTER TABLE portfolios ADD COLUMN updated_at BIGINT NOT NULL DEFAULT 0; -- seconds since the unix epoch
+ALTER TABLE works ADD COLUMN updated_at BIGINT NOT NULL DEFAULT 0; -- seconds since the unix epoch
+
+UPDATE portfolios SET first_published_at = published_at, updated_at = created_at;
+-- Works don't have a creation time, so the audit log is the best guess there is.
+-- 30 and 31 are AuditAction::WorkCreated and AuditAction::WorkUpdated, which
+-- keep their values since they're stored in audit_events.
+UPDATE works SET updated_at = COALESCE((
+    SELECT MAX(audit_events.created_at) FROM audit_events
+    WHERE audit_events.target = works.slug AND audit_events.action IN (30, 31)
+), 0);
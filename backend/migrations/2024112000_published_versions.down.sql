@@ -0,0 +1,10 @@
+DELETE FROM work_attachments WHERE detached = 1;
+ALTER TABLE work_attachments DROP COLUMN detached;
+
+DROP INDEX published_work_files_uuid_index;
+DROP INDEX published_works_work_index;
+DROP INDEX published_works_slug_index;
+
+DROP TABLE published_work_files;
+DROP TABLE published_works;
+DROP TABLE published_portfolios;
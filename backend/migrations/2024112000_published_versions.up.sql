@@ -0,0 +1,37 @@
+-- The published versions of portfolios and the works in them, as json. The
+-- portfolios, works and related tables hold the drafts, which anonymous
+-- readers don't see.
+CREATE TABLE IF NOT EXISTS published_portfolios (
+    portfolio_id INTEGER PRIMARY KEY NOT NULL REFERENCES portfolios (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    created_at BIGINT NOT NULL, -- seconds since the unix epoch
+    portfolio TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS published_works (
+    portfolio_id INTEGER NOT NULL REFERENCES portfolios (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    work_id INTEGER NOT NULL REFERENCES works (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    slug TEXT NOT NULL, -- the slug of the work when it was published
+    work TEXT NOT NULL,
+    PRIMARY KEY (portfolio_id, work_id)
+);
+
+CREATE INDEX IF NOT EXISTS published_works_slug_index ON published_works ( slug );
+CREATE INDEX IF NOT EXISTS published_works_work_index ON published_works ( work_id );
+
+-- The big files used by the published versions of works. The files aren't
+-- copied when publishing, so these keep them around when the draft stops
+-- using them.
+CREATE TABLE IF NOT EXISTS published_work_files (
+    portfolio_id INTEGER NOT NULL REFERENCES portfolios (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    work_id INTEGER NOT NULL REFERENCES works (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    big_file_uuid VARCHAR(36) NOT NULL,
+    PRIMARY KEY (portfolio_id, work_id, big_file_uuid)
+);
+
+CREATE INDEX IF NOT EXISTS published_work_files_uuid_index ON published_work_files ( big_file_uuid );
+
+-- 1 for attachments which have been removed from their work, or whose file has
+-- been replaced, but are kept since their big file parts are still used by
+-- something else. These aren't part of the work anymore, they just hold on to
+-- the file until it's unused.
+ALTER TABLE work_attachments ADD COLUMN detached INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,18 @@
+DROP INDEX work_revision_files_uuid_index;
+
+DROP TABLE work_revision_files;
+
+-- Copy the contents back into the attachments before dropping the reference
+UPDATE work_attachments SET bytes_base64 = (
+    SELECT bytes_base64 FROM attachment_contents WHERE sha256 = work_attachments.content_sha256
+) WHERE content_sha256 IS NOT NULL;
+
+DROP INDEX work_attachments_content_index;
+ALTER TABLE work_attachments DROP COLUMN content_sha256;
+
+DROP INDEX work_revision_contents_sha256_index;
+DROP INDEX work_revisions_work_index;
+
+DROP TABLE work_revision_contents;
+DROP TABLE attachment_contents;
+DROP TABLE work_revisions;
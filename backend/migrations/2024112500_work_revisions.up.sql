@@ -0,0 +1,53 @@
+-- Every version of every work, as json. The attachments' bytes are stored in
+-- attachment_contents instead, once per distinct content, so that attachments
+-- which don't change between edits aren't stored again.
+CREATE TABLE IF NOT EXISTS work_revisions (
+    id INTEGER PRIMARY KEY NOT NULL,
+    work_id INTEGER NOT NULL REFERENCES works (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    created_at BIGINT NOT NULL, -- seconds since the unix epoch
+    user_id INTEGER REFERENCES users (id) ON DELETE SET NULL ON UPDATE CASCADE,
+    work TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS work_revisions_work_index ON work_revisions ( work_id, id );
+
+CREATE TABLE IF NOT EXISTS attachment_contents (
+    -- of the base64, in lowercase hex, or "attachment-" and the id of the
+    -- attachment for the contents moved here from work_attachments below
+    sha256 VARCHAR(64) PRIMARY KEY NOT NULL,
+    bytes_base64 TEXT NOT NULL
+);
+
+-- For finding the contents that aren't used by any revision anymore
+CREATE TABLE IF NOT EXISTS work_revision_contents (
+    revision_id INTEGER NOT NULL REFERENCES work_revisions (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    sha256 VARCHAR(64) NOT NULL REFERENCES attachment_contents (sha256) ON DELETE CASCADE ON UPDATE CASCADE,
+    PRIMARY KEY (revision_id, sha256)
+);
+
+CREATE INDEX IF NOT EXISTS work_revision_contents_sha256_index ON work_revision_contents ( sha256 );
+
+-- The attachments' contents are stored in attachment_contents too, shared with
+-- the revisions and any other attachments with the same contents.
+ALTER TABLE work_attachments ADD COLUMN content_sha256 VARCHAR(64) REFERENCES attachment_contents (sha256) ON UPDATE CASCADE;
+
+CREATE INDEX IF NOT EXISTS work_attachments_content_index ON work_attachments ( content_sha256 );
+
+-- The hashes can't be calculated here, so the existing contents are keyed by
+-- the attachment instead. They just won't be shared with identical contents
+-- saved later.
+INSERT INTO attachment_contents (sha256, bytes_base64)
+SELECT 'attachment-' || id, bytes_base64 FROM work_attachments WHERE bytes_base64 <> '';
+
+UPDATE work_attachments SET content_sha256 = 'attachment-' || id, bytes_base64 = ''
+WHERE bytes_base64 <> '';
+
+-- The big files used by revisions, which keep them around like
+-- published_work_files do
+CREATE TABLE IF NOT EXISTS work_revision_files (
+    revision_id INTEGER NOT NULL REFERENCES work_revisions (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    big_file_uuid VARCHAR(36) NOT NULL,
+    PRIMARY KEY (revision_id, big_file_uuid)
+);
+
+CREATE INDEX IF NOT EXISTS work_revision_files_uuid_index ON work_revision_files ( big_file_uuid );
@@ -0,0 +1,3 @@
+DROP INDEX portfolio_previews_portfolio_index;
+
+DROP TABLE portfolio_previews;
@@ -0,0 +1,10 @@
+-- Secret links for showing the draft of a portfolio, and the works in it, to
+-- people who aren't collaborators.
+CREATE TABLE IF NOT EXISTS portfolio_previews (
+    token VARCHAR(36) PRIMARY KEY NOT NULL,
+    portfolio_id INTEGER NOT NULL REFERENCES portfolios (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    created_at BIGINT NOT NULL, -- seconds since the unix epoch
+    expires_at BIGINT -- seconds since the unix epoch, null if the preview does not expire
+);
+
+CREATE INDEX IF NOT EXISTS portfolio_previews_portfolio_index ON portfolio_previews ( portfolio_id );
@@ -0,0 +1,7 @@
+DROP INDEX portfolio_viewer_tokens_portfolio_index;
+
+DROP TABLE portfolio_viewer_tokens;
+
+ALTER TABLE portfolios DROP COLUMN access_pbkdf2_iterations;
+ALTER TABLE portfolios DROP COLUMN access_salt_base64;
+ALTER TABLE portfolios DROP COLUMN access_password_key_base64;
@@ -0,0 +1,16 @@
+-- An optional password that readers need to see a published portfolio and the
+-- works in it, derived the same way as the users' passwords. The key is null
+-- if the portfolio doesn't have a password.
+ALTER TABLE portfolios ADD COLUMN access_password_key_base64 VARCHAR(44);
+ALTER TABLE portfolios ADD COLUMN access_salt_base64 VARCHAR(16);
+ALTER TABLE portfolios ADD COLUMN access_pbkdf2_iterations INTEGER;
+
+-- Given out for the correct password, so that readers don't need to send the
+-- password along with every request.
+CREATE TABLE IF NOT EXISTS portfolio_viewer_tokens (
+    token VARCHAR(36) PRIMARY KEY NOT NULL,
+    portfolio_id INTEGER NOT NULL REFERENCES portfolios (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    expires_at BIGINT NOT NULL -- seconds since the unix epoch
+);
+
+CREATE INDEX IF NOT EXISTS portfolio_viewer_tokens_portfolio_index ON portfolio_viewer_tokens ( portfolio_id );
@@ -0,0 +1,3 @@
+DROP INDEX slug_history_slug_index;
+
+DROP TABLE slug_history;
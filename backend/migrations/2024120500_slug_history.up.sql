@@ -0,0 +1,11 @@
+-- The previous slugs of works and portfolios. Requests using them are
+-- redirected to the current slug, so they stay reserved for the work or
+-- portfolio that had them. Exactly one of work_id and portfolio_id is set.
+CREATE TABLE IF NOT EXISTS slug_history (
+    slug VARCHAR(60) NOT NULL,
+    work_id INTEGER REFERENCES works (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    portfolio_id INTEGER REFERENCES portfolios (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    changed_at BIGINT NOT NULL -- seconds since the unix epoch
+);
+
+CREATE INDEX IF NOT EXISTS slug_history_slug_index ON slug_history ( slug );
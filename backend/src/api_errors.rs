@@ -1,6 +1,9 @@
 //! All errors returned by the API should one the enums in this module, so that
 //! clients can easily translate every possible error message.
 
+use core::time::Duration;
+
+use axum::http::header::RETRY_AFTER;
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
 use axum::Json;
@@ -10,6 +13,14 @@ struct ErrorResponse {
     error: ApiError,
 }
 
+/// [ApiError::TooManyRequests] carries the retry time, so it can't be
+/// serialized as just a string like the other errors.
+#[derive(serde::Serialize)]
+struct TooManyRequestsResponse {
+    error: &'static str,
+    retry_after_seconds: u64,
+}
+
 #[derive(serde::Serialize)]
 pub enum ApiError {
     // Internal server errors, which do not necessarily require translations
@@ -21,21 +32,104 @@ pub enum ApiError {
 
     // User-facing errors which require translations client-side
     UsernameTooShort,
+    /// The username has characters other than ASCII letters, digits, and
+    /// `_-.`, or is reserved.
+    InvalidUsername,
     PasswordTooShort,
     PasswordsDontMatch,
+    /// The slug has characters other than lowercase ASCII letters, digits,
+    /// and hyphens, doesn't start and end with a letter or digit, or is
+    /// reserved.
+    InvalidSlug,
     InvalidCredentials,
     UsernameTaken,
+    RegistrationClosed,
+    /// Missing, used, expired, or nonexistent invite code.
+    InvalidInviteCode,
+    /// Nonexistent or expired password reset token.
+    InvalidPasswordReset,
+    CannotDisableSelf,
+    /// The avatar is not an image, or is not valid base64.
+    InvalidAvatar,
+    AvatarTooLarge,
+    /// The display name, bio, or a link title is longer than allowed.
+    ProfileTextTooLong,
+    TooManyProfileLinks,
+    /// A profile link isn't an http or https url, or is too long.
+    InvalidProfileLink,
+    /// Logging in with the OpenID Connect identity provider didn't work out,
+    /// e.g. the login expired, or the identity provider rejected the code.
+    OidcLoginFailed,
+    /// The identity provider didn't suggest a username for the new account,
+    /// so the user needs to pick one and log in again.
+    OidcUsernameRequired,
+    /// The identity is already linked to some user.
+    OidcIdentityInUse,
+    /// The identity is the only way left to log in to the account.
+    CannotUnlinkLastLogin,
+    /// Works and portfolios need at least one collaborator with the owner role.
+    CannotRemoveLastOwner,
+    CannotTransferToSelf,
+    /// The preview's expiration time has already passed.
+    PreviewAlreadyExpired,
+    /// The portfolio is scheduled to be unpublished before it's published.
+    InvalidPublishSchedule,
+    /// The file is also used by another work, a published version or a
+    /// revision, so it can't be appended to. It can be replaced instead.
+    FileShared,
+    /// Organizations need at least one member with the admin role.
+    CannotRemoveLastAdmin,
     /// No or malformed session token.
     MissingSession,
     /// Very probably an expired session token, or just a spoofed one.
     InvalidSession,
+    /// The session is from an API token, and the token doesn't have the scope
+    /// required for the request.
+    InsufficientScope,
+    /// The session is valid, but the user doesn't have the rights to do this,
+    /// e.g. a non-administrator trying to use the administration endpoints.
+    Forbidden,
+    AccountDisabled,
+    /// The portfolio, or every published portfolio the work or file is in,
+    /// is password-protected, and the request didn't have a valid viewer
+    /// token.
+    PortfolioLocked,
     NoSuchSlug,
     SlugTaken,
     NoSuchFile,
+    NoSuchApiToken,
+    NoSuchInvite,
+    NoSuchUser,
+    /// No pending ownership transfer, or it's no longer valid.
+    NoSuchTransfer,
+    /// The organization doesn't exist, or the user isn't one of its members.
+    NoSuchOrganization,
+    /// The work doesn't exist, or doesn't have a revision with the id.
+    NoSuchRevision,
+    /// The portfolio doesn't have a preview with the token.
+    NoSuchPreview,
+    NoSuchOidcIdentity,
+    /// Logging in with OpenID Connect is not configured on this server.
+    OidcDisabled,
+    TotpAlreadyEnabled,
+    /// Wrong or reused TOTP code, wrong recovery code, or an expired login attempt.
+    InvalidTotpCode,
+    /// Too many failed login attempts, the client should wait for the given
+    /// amount of seconds before trying again.
+    TooManyRequests {
+        retry_after_seconds: u64,
+    },
     // NOTE: When changing these (not recommended) or adding new ones, remember
     // to update the localization strings on the frontend as well!
 }
 
+impl ApiError {
+    pub fn too_many_requests(retry_after: Duration) -> ApiError {
+        let retry_after_seconds = retry_after.as_millis().div_ceil(1000) as u64;
+        ApiError::TooManyRequests { retry_after_seconds }
+    }
+}
+
 impl IntoResponse for ApiError {
     fn into_response(self) -> Response {
         let status = match self {
@@ -45,13 +139,57 @@ impl IntoResponse for ApiError {
             }
             ApiError::DbError => StatusCode::INTERNAL_SERVER_ERROR,
             ApiError::UsernameTooShort
+            | ApiError::InvalidUsername
             | ApiError::PasswordTooShort
             | ApiError::PasswordsDontMatch
+            | ApiError::InvalidSlug
             | ApiError::InvalidCredentials
             | ApiError::UsernameTaken
-            | ApiError::SlugTaken => StatusCode::BAD_REQUEST,
-            ApiError::MissingSession | ApiError::InvalidSession => StatusCode::FORBIDDEN,
-            ApiError::NoSuchSlug | ApiError::NoSuchFile => StatusCode::NOT_FOUND,
+            | ApiError::RegistrationClosed
+            | ApiError::InvalidInviteCode
+            | ApiError::InvalidPasswordReset
+            | ApiError::CannotDisableSelf
+            | ApiError::InvalidAvatar
+            | ApiError::AvatarTooLarge
+            | ApiError::ProfileTextTooLong
+            | ApiError::TooManyProfileLinks
+            | ApiError::InvalidProfileLink
+            | ApiError::OidcLoginFailed
+            | ApiError::OidcUsernameRequired
+            | ApiError::OidcIdentityInUse
+            | ApiError::CannotUnlinkLastLogin
+            | ApiError::CannotRemoveLastOwner
+            | ApiError::CannotTransferToSelf
+            | ApiError::PreviewAlreadyExpired
+            | ApiError::InvalidPublishSchedule
+            | ApiError::FileShared
+            | ApiError::CannotRemoveLastAdmin
+            | ApiError::SlugTaken
+            | ApiError::TotpAlreadyEnabled
+            | ApiError::InvalidTotpCode => StatusCode::BAD_REQUEST,
+            ApiError::MissingSession
+            | ApiError::InvalidSession
+            | ApiError::InsufficientScope
+            | ApiError::Forbidden
+            | ApiError::AccountDisabled
+            | ApiError::PortfolioLocked => StatusCode::FORBIDDEN,
+            ApiError::NoSuchSlug
+            | ApiError::NoSuchFile
+            | ApiError::NoSuchApiToken
+            | ApiError::NoSuchInvite
+            | ApiError::NoSuchUser
+            | ApiError::NoSuchTransfer
+            | ApiError::NoSuchOrganization
+            | ApiError::NoSuchRevision
+            | ApiError::NoSuchPreview
+            | ApiError::NoSuchOidcIdentity
+            | ApiError::OidcDisabled => StatusCode::NOT_FOUND,
+            ApiError::TooManyRequests { retry_after_seconds } => {
+                let body =
+                    TooManyRequestsResponse { error: "TooManyRequests", retry_after_seconds };
+                let retry_after = [(RETRY_AFTER, retry_after_seconds.to_string())];
+                return (StatusCode::TOO_MANY_REQUESTS, retry_after, Json(body)).into_response();
+            }
         };
         (status, Json(ErrorResponse { error: self })).into_response()
     }
@@ -31,3 +31,243 @@ pub fn session_expiration_seconds() -> u64 {
         })
         .unwrap_or(DEFAULT)
 }
+
+pub fn password_reset_expiration_seconds() -> u64 {
+    const DEFAULT: u64 = 60 * 60 * 24; // 1 day
+    env::var("PASSWORD_RESET_EXPIRATION_SECONDS")
+        .map(|n| {
+            n.parse::<u64>()
+                .expect("PASSWORD_RESET_EXPIRATION_SECONDS must be a non-negative integer")
+        })
+        .unwrap_or(DEFAULT)
+}
+
+/// Usernames of existing users who are made administrators when the server
+/// starts. Users registering with these usernames later are not.
+pub fn admin_usernames() -> Vec<String> {
+    env::var("ADMIN_USERNAMES")
+        .map(|names| {
+            names
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// How long a username stays reserved for its previous owner after they've
+/// changed their username, during which requests for it are redirected to the
+/// new one.
+pub fn username_reservation_seconds() -> u64 {
+    const DEFAULT: u64 = 60 * 60 * 24 * 30; // 30 days
+    env::var("USERNAME_RESERVATION_SECONDS")
+        .map(|n| {
+            n.parse::<u64>().expect("USERNAME_RESERVATION_SECONDS must be a non-negative integer")
+        })
+        .unwrap_or(DEFAULT)
+}
+
+pub fn totp_issuer() -> String {
+    env::var("TOTP_ISSUER").unwrap_or_else(|_| "Portfolio".into())
+}
+
+pub fn pending_login_expiration_seconds() -> u64 {
+    const DEFAULT: u64 = 60 * 5; // 5 minutes
+    env::var("PENDING_LOGIN_EXPIRATION_SECONDS")
+        .map(|n| {
+            n.parse::<u64>()
+                .expect("PENDING_LOGIN_EXPIRATION_SECONDS must be a non-negative integer")
+        })
+        .unwrap_or(DEFAULT)
+}
+
+/// The name of a header set by a trusted reverse proxy containing the client's
+/// ip address, e.g. `Fly-Client-IP`. If not set, the address of the peer
+/// connecting to the server is used.
+pub fn client_ip_header() -> Option<String> {
+    env::var("CLIENT_IP_HEADER").ok().filter(|header| !header.is_empty())
+}
+
+pub fn login_attempts_per_username() -> u32 {
+    const DEFAULT: u32 = 5;
+    env::var("LOGIN_ATTEMPTS_PER_USERNAME")
+        .map(|n| {
+            n.parse::<u32>().expect("LOGIN_ATTEMPTS_PER_USERNAME must be a non-negative integer")
+        })
+        .unwrap_or(DEFAULT)
+}
+
+pub fn login_attempts_per_ip() -> u32 {
+    const DEFAULT: u32 = 20;
+    env::var("LOGIN_ATTEMPTS_PER_IP")
+        .map(|n| n.parse::<u32>().expect("LOGIN_ATTEMPTS_PER_IP must be a non-negative integer"))
+        .unwrap_or(DEFAULT)
+}
+
+pub fn unlock_attempts_per_portfolio() -> u32 {
+    const DEFAULT: u32 = 10;
+    env::var("UNLOCK_ATTEMPTS_PER_PORTFOLIO")
+        .map(|n| {
+            n.parse::<u32>().expect("UNLOCK_ATTEMPTS_PER_PORTFOLIO must be a non-negative integer")
+        })
+        .unwrap_or(DEFAULT)
+}
+
+pub fn login_lockout_base_seconds() -> u64 {
+    const DEFAULT: u64 = 30;
+    env::var("LOGIN_LOCKOUT_BASE_SECONDS")
+        .map(|n| {
+            n.parse::<u64>().expect("LOGIN_LOCKOUT_BASE_SECONDS must be a non-negative integer")
+        })
+        .unwrap_or(DEFAULT)
+}
+
+pub fn login_lockout_max_seconds() -> u64 {
+    const DEFAULT: u64 = 60 * 60; // 1 hour
+    env::var("LOGIN_LOCKOUT_MAX_SECONDS")
+        .map(|n| {
+            n.parse::<u64>().expect("LOGIN_LOCKOUT_MAX_SECONDS must be a non-negative integer")
+        })
+        .unwrap_or(DEFAULT)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegistrationResponse {
+    /// Successful registrations log the user in, and taken usernames are
+    /// reported as such.
+    Login,
+    /// Every registration attempt gets the same empty response, regardless of
+    /// whether the account was created or the username was already taken. The
+    /// user needs to log in separately afterwards.
+    Opaque,
+}
+
+pub fn registration_response() -> RegistrationResponse {
+    match env::var("REGISTRATION_RESPONSE").as_deref() {
+        Err(_) | Ok("login") => RegistrationResponse::Login,
+        Ok("opaque") => RegistrationResponse::Opaque,
+        Ok(_) => panic!("REGISTRATION_RESPONSE must be either \"login\" or \"opaque\""),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub enum RegistrationMode {
+    /// Anyone can register.
+    Open,
+    /// Registering requires an unused invite code, created by an existing user.
+    InviteOnly,
+    /// Nobody can register.
+    Closed,
+}
+
+pub fn registration_mode() -> RegistrationMode {
+    match env::var("REGISTRATION_MODE").as_deref() {
+        Err(_) | Ok("open") => RegistrationMode::Open,
+        Ok("invite-only") => RegistrationMode::InviteOnly,
+        Ok("closed") => RegistrationMode::Closed,
+        Ok(_) => panic!("REGISTRATION_MODE must be \"open\", \"invite-only\", or \"closed\""),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OidcConfig {
+    /// The URL of the identity provider's discovery document, e.g.
+    /// `https://idp.example.com/.well-known/openid-configuration`.
+    pub discovery_url: String,
+    pub client_id: String,
+    /// Not needed if the client is registered as a public client at the
+    /// identity provider, since PKCE is used in any case.
+    pub client_secret: Option<String>,
+    /// Where the identity provider sends the user back to after logging in.
+    /// This is a frontend page, which passes the code and state on to
+    /// `/user/oidc/callback`.
+    pub redirect_url: String,
+    /// Space-separated scopes requested from the identity provider.
+    pub scopes: String,
+}
+
+/// The OpenID Connect identity provider users can log in with, or None if
+/// OIDC_DISCOVERY_URL is not set.
+pub fn oidc() -> Option<OidcConfig> {
+    let discovery_url = env::var("OIDC_DISCOVERY_URL").ok().filter(|url| !url.is_empty())?;
+    Some(OidcConfig {
+        discovery_url,
+        client_id: env::var("OIDC_CLIENT_ID")
+            .expect("OIDC_CLIENT_ID must be defined if OIDC_DISCOVERY_URL is"),
+        client_secret: env::var("OIDC_CLIENT_SECRET").ok().filter(|secret| !secret.is_empty()),
+        redirect_url: env::var("OIDC_REDIRECT_URL")
+            .expect("OIDC_REDIRECT_URL must be defined if OIDC_DISCOVERY_URL is"),
+        scopes: env::var("OIDC_SCOPES").unwrap_or_else(|_| "openid profile".into()),
+    })
+}
+
+pub fn oidc_login_expiration_seconds() -> u64 {
+    const DEFAULT: u64 = 60 * 10; // 10 minutes
+    env::var("OIDC_LOGIN_EXPIRATION_SECONDS")
+        .map(|n| {
+            n.parse::<u64>().expect("OIDC_LOGIN_EXPIRATION_SECONDS must be a non-negative integer")
+        })
+        .unwrap_or(DEFAULT)
+}
+
+pub fn avatar_max_bytes() -> usize {
+    const DEFAULT: usize = 512 * 1024; // 512 KiB
+    env::var("AVATAR_MAX_BYTES")
+        .map(|n| n.parse::<usize>().expect("AVATAR_MAX_BYTES must be a non-negative integer"))
+        .unwrap_or(DEFAULT)
+}
+
+/// How long audit events are kept, or None if they're kept forever.
+pub fn audit_retention_seconds() -> Option<u64> {
+    const DEFAULT: u64 = 60 * 60 * 24 * 365; // 1 year
+    match env::var("AUDIT_RETENTION_SECONDS").as_deref() {
+        Err(_) => Some(DEFAULT),
+        Ok("forever") => None,
+        Ok(n) => Some(
+            n.parse::<u64>()
+                .expect("AUDIT_RETENTION_SECONDS must be a non-negative integer or \"forever\""),
+        ),
+    }
+}
+
+/// How long deleted works and portfolios stay in the trash before they're
+/// purged, or None if they're kept until restored.
+pub fn trash_retention_seconds() -> Option<u64> {
+    const DEFAULT: u64 = 60 * 60 * 24 * 30; // 30 days
+    match env::var("TRASH_RETENTION_SECONDS").as_deref() {
+        Err(_) => Some(DEFAULT),
+        Ok("forever") => None,
+        Ok(n) => Some(
+            n.parse::<u64>()
+                .expect("TRASH_RETENTION_SECONDS must be a non-negative integer or \"forever\""),
+        ),
+    }
+}
+
+/// How many of each work's newest revisions are kept, or None if they're all
+/// kept.
+pub fn work_revisions_kept() -> Option<u64> {
+    const DEFAULT: u64 = 100;
+    match env::var("WORK_REVISIONS_KEPT").as_deref() {
+        Err(_) => Some(DEFAULT),
+        Ok("all") => None,
+        Ok(n) => Some(
+            n.parse::<u64>()
+                .expect("WORK_REVISIONS_KEPT must be a non-negative integer or \"all\""),
+        ),
+    }
+}
+
+/// How long the viewer token given for a password-protected portfolio's
+/// password lasts.
+pub fn portfolio_viewer_token_expiration_seconds() -> u64 {
+    const DEFAULT: u64 = 60 * 60 * 24; // 1 day
+    env::var("PORTFOLIO_VIEWER_TOKEN_EXPIRATION_SECONDS")
+        .map(|n| {
+            n.parse::<u64>()
+                .expect("PORTFOLIO_VIEWER_TOKEN_EXPIRATION_SECONDS must be a non-negative integer")
+        })
+        .unwrap_or(DEFAULT)
+}
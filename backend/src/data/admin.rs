@@ -0,0 +1,12 @@
+use crate::array_string_types::UsernameString;
+
+#[derive(Debug, serde::Serialize)]
+pub struct UserSummary {
+    pub id: i32,
+    pub username: UsernameString,
+    pub is_admin: bool,
+    /// The time this account was disabled, in seconds since the unix epoch.
+    pub disabled_at: Option<i64>,
+    pub totp_enabled: bool,
+    pub session_count: i64,
+}
@@ -0,0 +1,119 @@
+use crate::array_string_types::UsernameString;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type, serde::Serialize, serde::Deserialize)]
+#[repr(i32)] // for integer representation in the db, serde will still convert to/from string
+pub enum AuditAction {
+    Login = 1,
+    /// A wrong password or TOTP code, or a login to a disabled account.
+    LoginFailed = 2,
+    Register = 3,
+    UsernameChanged = 4,
+    PasswordReset = 5,
+    TotpEnabled = 6,
+    TotpDisabled = 7,
+    ApiTokenCreated = 8,
+    ApiTokenRevoked = 9,
+    OidcIdentityLinked = 10,
+    OidcIdentityUnlinked = 11,
+    PortfolioCreated = 20,
+    PortfolioUpdated = 21,
+    PortfolioPublished = 22,
+    PortfolioUnpublished = 23,
+    PortfolioDeleted = 24,
+    PortfolioRestored = 25,
+    /// A collaborator was added or their role changed, the target is
+    /// `<slug>/<username>` or `<slug>/organization:<organization slug>`.
+    PortfolioCollaboratorSet = 26,
+    PortfolioCollaboratorRemoved = 27,
+    /// The target is `<slug>/<recipient's username>`.
+    PortfolioTransferProposed = 28,
+    /// The transfer was accepted, or an administrator transferred the
+    /// portfolio. The target is `<slug>/<new owner's username>`.
+    PortfolioTransferred = 29,
+    /// Also referred to by its value in a migration, along with
+    /// [AuditAction::WorkUpdated].
+    WorkCreated = 30,
+    WorkUpdated = 31,
+    /// The first part of a new attachment file, which replaces the previous
+    /// file of the attachment, if any.
+    FileUploaded = 32,
+    WorkDeleted = 33,
+    WorkRestored = 34,
+    /// A collaborator was added or their role changed, the target is
+    /// `<slug>/<username>` or `<slug>/organization:<organization slug>`.
+    WorkCollaboratorSet = 35,
+    WorkCollaboratorRemoved = 36,
+    /// The target is `<slug>/<recipient's username>`.
+    WorkTransferProposed = 37,
+    /// The transfer was accepted, or an administrator transferred the work.
+    /// The target is `<slug>/<new owner's username>`.
+    WorkTransferred = 38,
+    /// The target is `<slug>/<revision id>`.
+    WorkRevisionRestored = 39,
+    UserDisabled = 40,
+    UserEnabled = 41,
+    PasswordResetCreated = 42,
+    TotpReset = 43,
+    OrganizationCreated = 50,
+    OrganizationDeleted = 51,
+    /// A member was added or their role changed, the target is
+    /// `<organization slug>/<username>`.
+    OrganizationMemberSet = 52,
+    OrganizationMemberRemoved = 53,
+    /// The target is the portfolio's slug, the preview's token isn't logged.
+    PortfolioPreviewCreated = 60,
+    PortfolioPreviewRevoked = 61,
+    /// The portfolio's access password was set, changed or removed.
+    PortfolioPasswordChanged = 62,
+    /// An owner cancelled the proposed transfer, or the recipient declined
+    /// it. The target is `<slug>/<recipient's username>`.
+    PortfolioTransferCancelled = 63,
+    /// An owner cancelled the proposed transfer, or the recipient declined
+    /// it. The target is `<slug>/<recipient's username>`.
+    WorkTransferCancelled = 64,
+}
+
+/// Where a request came from, recorded along with the audit events caused by
+/// the request.
+#[derive(Debug, Clone)]
+pub struct RequestOrigin {
+    pub ip: String,
+    pub user_agent: Option<String>,
+}
+
+impl RequestOrigin {
+    /// The origin of the events the server causes on its own, like publishing
+    /// scheduled portfolios. The ip is left empty.
+    pub fn server() -> RequestOrigin {
+        RequestOrigin { ip: String::new(), user_agent: None }
+    }
+}
+
+#[derive(Debug, sqlx::FromRow, serde::Serialize)]
+pub struct AuditEvent {
+    pub id: i32,
+    /// The time of the event, in seconds since the unix epoch.
+    pub created_at: i64,
+    pub actor_user_id: Option<i32>,
+    /// The actor's current username, if they still exist.
+    pub actor_username: Option<UsernameString>,
+    pub action: AuditAction,
+    pub target: String,
+    pub ip: String,
+    pub user_agent: Option<String>,
+}
+
+/// Conditions for the events to return, all of which are optional.
+#[derive(Debug, Default)]
+pub struct AuditEventFilter {
+    pub actor_user_id: Option<i32>,
+    pub action: Option<AuditAction>,
+    pub target: Option<String>,
+    pub ip: Option<String>,
+    /// In seconds since the unix epoch, inclusive.
+    pub since: Option<i64>,
+    /// In seconds since the unix epoch, exclusive.
+    pub until: Option<i64>,
+    /// For paging: only events older than the event with this id are returned.
+    pub before_id: Option<i32>,
+}
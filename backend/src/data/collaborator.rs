@@ -0,0 +1,61 @@
+use crate::array_string_types::{SlugString, UsernameString};
+
+/// Which kind of thing is being shared with collaborators. Also used for
+/// telling apart the slugs of works and portfolios, which are separate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+pub enum CollaborationTarget {
+    Work,
+    Portfolio,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type, serde::Serialize, serde::Deserialize)]
+#[repr(i32)] // for integer representation in the db, serde will still convert to/from string
+pub enum CollaboratorRole {
+    /// Can do everything, including deleting and managing collaborators.
+    Owner = 1,
+    /// Can edit (and publish, for portfolios), but not delete.
+    Editor = 2,
+    /// Can see the work or portfolio even when it's not published.
+    Viewer = 3,
+}
+
+/// A user or an organization with rights to a work or portfolio. Exactly one
+/// of `username` and `organization` is set.
+#[derive(Debug, sqlx::FromRow, serde::Serialize)]
+pub struct Collaborator {
+    pub username: Option<UsernameString>,
+    /// The slug of the organization, whose members all have the role.
+    pub organization: Option<SlugString>,
+    pub role: CollaboratorRole,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CollaboratorChangeOutcome {
+    Changed,
+    /// The work or portfolio doesn't exist, or the user has no rights to it.
+    NoSuchSlug,
+    NoSuchUser,
+    /// The organization doesn't exist, or the user isn't one of its members.
+    NoSuchOrganization,
+    /// Only owners can manage the other collaborators.
+    NotOwner,
+    /// Works and portfolios always need at least one owner.
+    LastOwner,
+    /// Ownership can't be transferred to the current owner.
+    SameUser,
+}
+
+/// A proposed transfer of a work's or a portfolio's ownership, waiting for
+/// the recipient to accept it.
+#[derive(Debug, sqlx::FromRow, serde::Serialize)]
+pub struct OwnershipTransfer {
+    pub id: i32,
+    /// In seconds since the unix epoch.
+    pub created_at: i64,
+    /// Set if this is a transfer of a work.
+    pub work_slug: Option<SlugString>,
+    /// Set if this is a transfer of a portfolio.
+    pub portfolio_slug: Option<SlugString>,
+    pub from_username: UsernameString,
+    pub to_username: UsernameString,
+}
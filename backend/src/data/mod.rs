@@ -1,3 +1,8 @@
+pub mod admin;
+pub mod audit;
+pub mod collaborator;
+pub mod organization;
 pub mod portfolio;
+pub mod trash;
 pub mod user;
 pub mod work;
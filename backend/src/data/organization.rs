@@ -0,0 +1,47 @@
+use crate::array_string_types::{SlugString, UsernameString};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type, serde::Serialize, serde::Deserialize)]
+#[repr(i32)] // for integer representation in the db, serde will still convert to/from string
+pub enum OrganizationRole {
+    /// Can manage the members, and delete the organization.
+    Admin = 1,
+    Member = 2,
+}
+
+/// An organization the user is a member of.
+#[derive(Debug, sqlx::FromRow, serde::Serialize)]
+pub struct OrganizationMembership {
+    pub slug: SlugString,
+    pub name: String,
+    /// The user's role in the organization.
+    pub role: OrganizationRole,
+}
+
+#[derive(Debug, sqlx::FromRow, serde::Serialize)]
+pub struct OrganizationMember {
+    pub username: UsernameString,
+    pub role: OrganizationRole,
+}
+
+#[derive(Debug, sqlx::FromRow, serde::Serialize)]
+pub struct Organization {
+    pub id: i32,
+    pub slug: SlugString,
+    pub name: String,
+    /// In seconds since the unix epoch.
+    pub created_at: i64,
+    #[sqlx(skip)]
+    pub members: Vec<OrganizationMember>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum OrganizationChangeOutcome {
+    Changed,
+    /// The organization doesn't exist, or the user isn't one of its members.
+    NoSuchOrganization,
+    NoSuchUser,
+    /// Only admins can manage the other members.
+    NotAdmin,
+    /// Organizations always need at least one admin.
+    LastAdmin,
+}
@@ -1,4 +1,4 @@
-use crate::array_string_types::SlugString;
+use crate::array_string_types::{SlugString, UuidString};
 
 #[derive(Debug, sqlx::FromRow, serde::Serialize, serde::Deserialize)]
 pub struct PortfolioRow {
@@ -8,12 +8,32 @@ pub struct PortfolioRow {
     /// The creation time of this portfolio, in seconds since the unix epoch.
     pub created_at: i64,
     #[serde(default)]
-    /// The publication time of this portfolio, in seconds since the unix epoch.
+    /// The time this portfolio was last published, in seconds since the unix
+    /// epoch. Edits don't change this while the portfolio stays published.
     pub published_at: Option<i64>,
+    #[serde(default)]
+    /// The time this portfolio was published for the first time, in seconds
+    /// since the unix epoch. Kept even if the portfolio is unpublished.
+    pub first_published_at: Option<i64>,
+    #[serde(default)]
+    /// The time this portfolio was last edited, in seconds since the unix
+    /// epoch.
+    pub updated_at: i64,
     pub slug: SlugString,
     pub title: String,
     pub subtitle: String,
+    /// If left empty when creating a portfolio, the creator's display name is
+    /// used, if they've chosen so in their profile.
+    #[serde(default)]
     pub author: String,
+    /// The time this portfolio will be published automatically, in seconds
+    /// since the unix epoch.
+    #[serde(default)]
+    pub publish_at: Option<i64>,
+    /// The time this portfolio will be unpublished automatically, in seconds
+    /// since the unix epoch.
+    #[serde(default)]
+    pub unpublish_at: Option<i64>,
 }
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
@@ -38,3 +58,24 @@ pub struct PortfolioCategory {
     pub row: PortfolioCategoryRow,
     pub work_slugs: Vec<SlugString>,
 }
+
+/// A secret link to the draft of a portfolio. Anyone with the token can see
+/// the portfolio and the works in it, until it expires or is revoked.
+#[derive(Debug, sqlx::FromRow, serde::Serialize)]
+pub struct PortfolioPreview {
+    pub token: UuidString,
+    /// In seconds since the unix epoch.
+    pub created_at: i64,
+    /// In seconds since the unix epoch, None if the preview doesn't expire.
+    pub expires_at: Option<i64>,
+}
+
+/// Given for the password of a password-protected portfolio. Passed as the
+/// `viewer_token` query parameter to see the portfolio, the works in it, and
+/// their files.
+#[derive(Debug, sqlx::FromRow, serde::Serialize)]
+pub struct PortfolioViewerToken {
+    pub token: UuidString,
+    /// In seconds since the unix epoch.
+    pub expires_at: i64,
+}
@@ -0,0 +1,19 @@
+use crate::array_string_types::SlugString;
+
+/// A deleted work or portfolio, which can still be restored until it's purged.
+#[derive(Debug, sqlx::FromRow, serde::Serialize)]
+pub struct TrashedItem {
+    pub slug: SlugString,
+    pub title: String,
+    /// In seconds since the unix epoch.
+    pub deleted_at: i64,
+    /// The time this will be permanently deleted, in seconds since the unix
+    /// epoch. None if the trash is never emptied automatically.
+    pub purge_at: Option<i64>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct Trash {
+    pub works: Vec<TrashedItem>,
+    pub portfolios: Vec<TrashedItem>,
+}
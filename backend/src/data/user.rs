@@ -1,14 +1,24 @@
-use crate::array_string_types::{PasswordKeyString, SaltString, UsernameString, UuidString};
+use crate::array_string_types::{
+    ContentType, PasswordKeyString, SaltString, UsernameString, UuidString,
+};
+use crate::data::work::BytesBase64;
 
 #[derive(Debug, sqlx::FromRow)]
 pub struct User {
     pub id: i32,
-    #[allow(dead_code)]
     pub username: UsernameString,
     #[sqlx(default)]
     pub password_key_base64: Option<PasswordKeyString>,
     pub pbkdf2_iterations: i32,
     pub salt_base64: SaltString,
+    /// The time two-factor authentication was enabled, in seconds since the
+    /// unix epoch. If this is None, logging in only requires the password.
+    #[sqlx(default)]
+    pub totp_enabled_at: Option<i64>,
+    /// The time an administrator disabled this account, in seconds since the
+    /// unix epoch. Disabled accounts can't log in.
+    #[sqlx(default)]
+    pub disabled_at: Option<i64>,
 }
 
 #[derive(Debug, sqlx::FromRow)]
@@ -17,4 +27,230 @@ pub struct Session {
     pub user_id: i32,
     /// The creation time of this session, in seconds since the unix epoch.
     pub created_at: i64,
+    /// The scopes of the API token used to authenticate, or None if this is a
+    /// regular session from logging in, which is allowed to do everything.
+    #[sqlx(skip)]
+    pub api_token_scopes: Option<ApiTokenScopes>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub enum UserRole {
+    Admin,
+}
+
+/// Details about the logged in user and their current session.
+#[derive(Debug, serde::Serialize)]
+pub struct UserInfo {
+    pub user_id: i32,
+    pub username: UsernameString,
+    pub roles: Vec<UserRole>,
+    pub session_id: UuidString,
+    /// The creation time of the session (or API token), in seconds since the
+    /// unix epoch.
+    pub session_created_at: i64,
+    /// The time the session (or API token) stops working, in seconds since the
+    /// unix epoch. None for API tokens which don't expire.
+    pub session_expires_at: Option<i64>,
+    /// Roughly how many bytes the files of the works the user owns and their
+    /// avatar take up, estimated from the length of the base64 encoded data.
+    /// Works owned by organizations aren't counted for any of their members.
+    /// Files shared between works, like those of cloned works, are only
+    /// counted for the owner of the work which had them first.
+    pub storage_bytes: i64,
+    /// How many works the user owns, not counting ones they collaborate on.
+    pub work_count: i64,
+    /// How many portfolios the user owns, like `work_count`.
+    pub portfolio_count: i64,
+}
+
+/// A login which has passed the password check, but still needs a TOTP code
+/// (or a recovery code) before it can be turned into a [Session].
+#[derive(Debug, sqlx::FromRow)]
+pub struct PendingLogin {
+    pub uuid: UuidString,
+    pub user_id: i32,
+    /// The creation time of this pending login, in seconds since the unix epoch.
+    pub created_at: i64,
+}
+
+#[derive(Debug)]
+pub enum LoginOutcome {
+    Session(Session),
+    TotpRequired(PendingLogin),
+    /// The credentials were correct, but the account has been disabled.
+    AccountDisabled,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum UsernameChangeOutcome {
+    Changed,
+    WrongPassword,
+    /// Someone else has the username, or has had it recently.
+    UsernameTaken,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct TotpEnrollment {
+    pub secret_base32: String,
+    /// The `otpauth://` URI meant to be shown to the user as a QR code.
+    pub otpauth_uri: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum ApiTokenScope {
+    /// Reading one's own works and portfolios, including unpublished ones.
+    Read,
+    EditWorks,
+    UploadFiles,
+    /// Creating, editing, and publishing portfolios.
+    PublishPortfolios,
+}
+
+impl ApiTokenScope {
+    const ALL: [ApiTokenScope; 4] = [
+        ApiTokenScope::Read,
+        ApiTokenScope::EditWorks,
+        ApiTokenScope::UploadFiles,
+        ApiTokenScope::PublishPortfolios,
+    ];
+
+    fn bit(self) -> i32 {
+        1 << self as i32
+    }
+}
+
+/// A set of [ApiTokenScope]s, stored as bitflags in the database, and as a
+/// list of scope names in the API.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type, serde::Serialize, serde::Deserialize)]
+#[sqlx(transparent)]
+#[serde(into = "Vec<ApiTokenScope>", from = "Vec<ApiTokenScope>")]
+pub struct ApiTokenScopes(i32);
+
+impl ApiTokenScopes {
+    pub fn contains(self, scope: ApiTokenScope) -> bool {
+        self.0 & scope.bit() != 0
+    }
+}
+
+impl From<Vec<ApiTokenScope>> for ApiTokenScopes {
+    fn from(scopes: Vec<ApiTokenScope>) -> Self {
+        ApiTokenScopes(scopes.into_iter().fold(0, |bits, scope| bits | scope.bit()))
+    }
+}
+
+impl From<ApiTokenScopes> for Vec<ApiTokenScope> {
+    fn from(scopes: ApiTokenScopes) -> Self {
+        ApiTokenScope::ALL.into_iter().filter(|&scope| scopes.contains(scope)).collect()
+    }
+}
+
+#[derive(Debug, sqlx::FromRow, serde::Serialize)]
+pub struct ApiToken {
+    pub uuid: UuidString,
+    #[serde(skip)]
+    pub user_id: i32,
+    pub name: String,
+    pub scopes: ApiTokenScopes,
+    /// The creation time of this token, in seconds since the unix epoch.
+    pub created_at: i64,
+    /// The time after which this token can't be used anymore, in seconds since
+    /// the unix epoch.
+    pub expires_at: Option<i64>,
+    /// The last time this token was used, in seconds since the unix epoch.
+    pub last_used_at: Option<i64>,
+}
+
+#[derive(Debug, sqlx::FromRow, serde::Serialize)]
+pub struct Invite {
+    pub code: String,
+    #[serde(skip)]
+    #[allow(dead_code)]
+    pub created_by: Option<i32>,
+    /// The creation time of this invite, in seconds since the unix epoch.
+    pub created_at: i64,
+    /// The time after which this invite can't be used anymore, in seconds
+    /// since the unix epoch.
+    pub expires_at: Option<i64>,
+    /// The time this invite was used to register, in seconds since the unix
+    /// epoch.
+    pub used_at: Option<i64>,
+    /// The username of the user who registered with this invite.
+    #[sqlx(default)]
+    pub used_by_username: Option<String>,
+}
+
+/// An account at the OpenID Connect identity provider, which can be used to
+/// log in as the linked user.
+#[derive(Debug, sqlx::FromRow, serde::Serialize)]
+pub struct OidcIdentity {
+    pub issuer: String,
+    pub subject: String,
+    /// The time this identity was linked to the user, in seconds since the
+    /// unix epoch.
+    pub created_at: i64,
+}
+
+/// The state of an OpenID Connect login between redirecting the user to the
+/// identity provider and them coming back with an authorization code.
+#[derive(Debug, sqlx::FromRow)]
+pub struct OidcLogin {
+    pub state: String,
+    pub nonce: String,
+    /// The PKCE code verifier, sent to the identity provider along with the
+    /// authorization code.
+    pub code_verifier: String,
+    /// The user whose account the identity should be linked to, or None if
+    /// this is a regular login.
+    pub link_user_id: Option<i32>,
+    /// The creation time of this login, in seconds since the unix epoch.
+    pub created_at: i64,
+}
+
+/// The publicly visible information about a user, in addition to their
+/// username.
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct UserProfile {
+    pub display_name: String,
+    pub bio: String,
+    pub avatar: Option<Avatar>,
+    pub links: Vec<ProfileLink>,
+    /// If true, new portfolios created without an author get the display name
+    /// as their author.
+    #[serde(default)]
+    pub default_author: bool,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct Avatar {
+    pub content_type: ContentType,
+    pub bytes_base64: BytesBase64,
+}
+
+#[derive(Debug, sqlx::FromRow, serde::Serialize, serde::Deserialize)]
+pub struct ProfileLink {
+    pub title: String,
+    pub href: String,
+}
+
+/// The account details included in the data export from `/user/me/export`.
+#[derive(Debug, serde::Serialize)]
+pub struct ExportedUser {
+    pub id: i32,
+    pub username: UsernameString,
+    pub roles: Vec<UserRole>,
+    pub totp_enabled_at: Option<i64>,
+    pub disabled_at: Option<i64>,
+    pub profile: UserProfile,
+    pub oidc_identities: Vec<OidcIdentity>,
+    pub api_tokens: Vec<ApiToken>,
+}
+
+/// A session included in the data export. The session id is left out, since
+/// anyone with the export could use it to log in as the user.
+#[derive(Debug, sqlx::FromRow, serde::Serialize)]
+pub struct ExportedSession {
+    /// In seconds since the unix epoch.
+    pub created_at: i64,
+    /// In seconds since the unix epoch.
+    pub expires_at: i64,
 }
@@ -1,8 +1,8 @@
 use core::fmt;
 
-use crate::array_string_types::{ContentType, SlugString, UuidString};
+use crate::array_string_types::{ContentType, SlugString, UsernameString, UuidString};
 
-#[derive(Debug, sqlx::Type, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, Copy, sqlx::Type, serde::Serialize, serde::Deserialize)]
 #[repr(i32)] // for integer representation in the db, serde will still convert to/from string
 pub enum AttachmentKind {
     DownloadWindows = 1,
@@ -21,6 +21,9 @@ pub struct WorkRow {
     pub title: String,
     pub short_description: String,
     pub long_description: String,
+    /// The time this work was last edited, in seconds since the unix epoch.
+    #[serde(default)]
+    pub updated_at: i64,
 }
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
@@ -65,6 +68,54 @@ pub struct WorkTag {
     pub tag: String,
 }
 
+#[derive(Debug, sqlx::FromRow, serde::Serialize)]
+pub struct WorkRevision {
+    pub id: i32,
+    /// The time of the edit, in seconds since the unix epoch.
+    pub created_at: i64,
+    /// The user who made the edit, if they still exist.
+    pub username: Option<UsernameString>,
+}
+
+/// The contents of a work in a revision. The ids are left out, since they
+/// change on every edit.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct WorkRevisionContents {
+    pub slug: SlugString,
+    pub title: String,
+    pub short_description: String,
+    pub long_description: String,
+    pub attachments: Vec<WorkRevisionAttachment>,
+    pub links: Vec<WorkRevisionLink>,
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct WorkRevisionAttachment {
+    pub attachment_kind: AttachmentKind,
+    pub content_type: ContentType,
+    pub filename: String,
+    pub title: Option<String>,
+    /// The hash of the bytes, which are stored separately. None if the
+    /// attachment's bytes are empty.
+    pub bytes_sha256: Option<String>,
+    pub big_file_uuid: Option<UuidString>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct WorkRevisionLink {
+    pub title: String,
+    pub href: String,
+}
+
+/// A field which differs between two revisions, with its values in both.
+#[derive(Debug, serde::Serialize)]
+pub struct WorkRevisionChange {
+    pub field: String,
+    pub from: serde_json::Value,
+    pub to: serde_json::Value,
+}
+
 #[derive(Debug, sqlx::FromRow)]
 pub struct BigFilePart {
     pub uuid: UuidString,
@@ -1,4 +1,5 @@
 use core::time::Duration;
+use std::net::SocketAddr;
 use std::sync::Arc;
 use std::time::SystemTime;
 
@@ -12,12 +13,14 @@ use tower_http::cors::{self, CorsLayer};
 use tower_http::trace::TraceLayer;
 use tracing_subscriber::EnvFilter;
 
+use crate::rate_limiter::AttemptRateLimiter;
 use crate::request_state::SharedState;
 
 mod api_errors;
 mod array_string_types;
 mod config;
 mod data;
+mod rate_limiter;
 mod request_state;
 mod routes;
 mod services;
@@ -46,7 +49,44 @@ async fn main() {
 
     services::patch_postgres_primary_keys(&mut db_pool).await;
 
-    let shared_state = Arc::new(SharedState { db_pool });
+    let mut conn = db_pool.acquire().await.expect("database should be reachable");
+    for username in config::admin_usernames() {
+        match services::user::make_admin(&mut *conn, &username).await {
+            Ok(true) => tracing::info!("User {username} is an admin."),
+            Ok(false) => tracing::warn!("There is no user {username} to make an admin."),
+            Err(err) => tracing::warn!("Failed to make {username} an admin: {err:?}"),
+        }
+    }
+
+    let mut args = std::env::args().skip(1);
+    match args.next().as_deref() {
+        None => {}
+        Some("make-admin") => {
+            let username = args.next().expect("make-admin requires a username argument");
+            let found = services::user::make_admin(&mut *conn, &username)
+                .await
+                .expect("making the user an admin should succeed");
+            if found {
+                tracing::info!("User {username} is now an admin.");
+            } else {
+                tracing::error!("There is no user with the username {username}.");
+            }
+            return;
+        }
+        Some(command) => panic!("unrecognized command: {command}"),
+    }
+    drop(conn);
+
+    let http_client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .expect("http client should be buildable");
+    let shared_state = Arc::new(SharedState {
+        db_pool,
+        login_rate_limiter: AttemptRateLimiter::default(),
+        unlock_rate_limiter: AttemptRateLimiter::default(),
+        http_client,
+    });
 
     tokio::spawn({
         let state = shared_state.clone();
@@ -61,19 +101,106 @@ async fn main() {
                         {
                             tracing::warn!("Failed to remove old sessions: {:?}", err);
                         }
+
+                        let before_timestamp = SystemTime::now()
+                            - Duration::from_secs(config::pending_login_expiration_seconds());
+                        if let Err(err) = services::user::totp::remove_pending_logins(
+                            &mut *conn,
+                            before_timestamp,
+                        )
+                        .await
+                        {
+                            tracing::warn!("Failed to remove old pending logins: {:?}", err);
+                        }
+
+                        let before_timestamp = SystemTime::now()
+                            - Duration::from_secs(config::oidc_login_expiration_seconds());
+                        if let Err(err) =
+                            services::user::oidc::remove_logins(&mut *conn, before_timestamp).await
+                        {
+                            tracing::warn!("Failed to remove old oidc logins: {:?}", err);
+                        }
+
+                        let before_timestamp = SystemTime::now()
+                            - Duration::from_secs(config::username_reservation_seconds());
+                        if let Err(err) =
+                            services::user::remove_old_usernames(&mut *conn, before_timestamp).await
+                        {
+                            tracing::warn!("Failed to remove old usernames: {:?}", err);
+                        }
+                        if let Some(retention_seconds) = config::audit_retention_seconds() {
+                            let before_timestamp =
+                                SystemTime::now() - Duration::from_secs(retention_seconds);
+                            if let Err(err) =
+                                services::audit::remove_events(&mut *conn, before_timestamp).await
+                            {
+                                tracing::warn!("Failed to remove old audit events: {:?}", err);
+                            }
+                        }
+                        if let Some(retention_seconds) = config::trash_retention_seconds() {
+                            let before_timestamp =
+                                SystemTime::now() - Duration::from_secs(retention_seconds);
+                            if let Err(err) = services::work::purge_deleted_works(
+                                &state.db_pool,
+                                before_timestamp,
+                            )
+                            .await
+                            {
+                                tracing::warn!("Failed to purge deleted works: {:?}", err);
+                            }
+                            if let Err(err) = services::portfolio::purge_deleted_portfolios(
+                                &state.db_pool,
+                                before_timestamp,
+                            )
+                            .await
+                            {
+                                tracing::warn!("Failed to purge deleted portfolios: {:?}", err);
+                            }
+                        }
+                        if let Err(err) =
+                            services::work::big_files::remove_unused_files(&state.db_pool).await
+                        {
+                            tracing::warn!("Failed to remove unused files: {:?}", err);
+                        }
+                        if let Err(err) =
+                            services::work::revisions::remove_unused_contents(&mut *conn).await
+                        {
+                            tracing::warn!(
+                                "Failed to remove unused attachment contents: {:?}",
+                                err
+                            );
+                        }
+                        if let Err(err) =
+                            services::portfolio::apply_publish_schedules(&mut *conn).await
+                        {
+                            tracing::warn!("Failed to apply publish schedules: {:?}", err);
+                        }
+                        if let Err(err) =
+                            services::portfolio::passwords::remove_expired_viewer_tokens(&mut *conn)
+                                .await
+                        {
+                            tracing::warn!("Failed to remove old viewer tokens: {:?}", err);
+                        }
+                        if let Err(err) =
+                            services::portfolio::previews::remove_expired_previews(&mut *conn).await
+                        {
+                            tracing::warn!("Failed to remove expired previews: {:?}", err);
+                        }
                     }
                     Err(err) => tracing::warn!(
                         "Failed to acquire db connection to remove old sessions: {:?}",
                         err
                     ),
                 }
+                state.login_rate_limiter.remove_stale();
+                state.unlock_rate_limiter.remove_stale();
                 tokio::time::sleep(Duration::from_secs(60)).await;
             }
         }
     });
 
     let cors_layer = CorsLayer::new()
-        .allow_methods([Method::GET, Method::POST, Method::PUT])
+        .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
         .allow_origin(cors::Any)
         .allow_headers([AUTHORIZATION, CONTENT_TYPE]);
     let app = router.with_state(shared_state).layer(TraceLayer::new_for_http()).layer(cors_layer);
@@ -83,7 +210,8 @@ async fn main() {
     let listener = TcpListener::bind(&addr).await.unwrap();
     tracing::info!("Now serving the HTTP API at: http://{addr}{base_path}");
 
-    axum::serve(listener, app).with_graceful_shutdown(shutdown_signal()).await.unwrap();
+    let service = app.into_make_service_with_connect_info::<SocketAddr>();
+    axum::serve(listener, service).with_graceful_shutdown(shutdown_signal()).await.unwrap();
 
     tracing::info!("Bye!");
 }
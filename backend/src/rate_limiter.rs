@@ -0,0 +1,145 @@
+//! In-memory bookkeeping of failed login and portfolio unlocking attempts,
+//! used to slow down password guessing. Since this is not stored in the
+//! database, the counts reset when the server restarts, and are not shared
+//! between multiple server instances.
+
+use core::time::Duration;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use arrayvec::ArrayString;
+
+use crate::array_string_types::{SlugString, UsernameString};
+use crate::config;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum LimitKey {
+    Ip(IpAddr),
+    Username(ArrayString<30>),
+    Portfolio(ArrayString<60>),
+}
+
+#[derive(Debug)]
+struct FailedAttempts {
+    count: u32,
+    last_failure: Instant,
+    locked_until: Option<Instant>,
+}
+
+/// Logins and portfolio unlocking each use their own instance, so that the
+/// failures of one don't lock the ip address out of the other.
+#[derive(Debug, Default)]
+pub struct AttemptRateLimiter {
+    attempts: Mutex<HashMap<LimitKey, FailedAttempts>>,
+}
+
+impl AttemptRateLimiter {
+    /// Starts an attempt, counting it as failed until [Self::record_success]
+    /// is called for it. The check and the counting happen atomically, so
+    /// that concurrent attempts can't all get in before the first ones fail.
+    /// Returns how long the client needs to wait before trying again, if the
+    /// ip address or the username are currently locked out.
+    ///
+    /// After the free attempts have been used up, every further failure locks
+    /// the ip address and/or username out for twice as long as the previous
+    /// one, up to a configured maximum.
+    pub fn begin_attempt(
+        &self,
+        ip: IpAddr,
+        username: Option<UsernameString>,
+    ) -> Result<(), Duration> {
+        self.begin(ip, username.map(|username| LimitKey::Username(username.0)))
+    }
+
+    /// Takes back the failure counted for the ip address by
+    /// [Self::begin_attempt], and clears the failures counted for the
+    /// username. The ip address' earlier failures are not cleared, so that
+    /// logging into one's own account does not allow guessing other accounts'
+    /// passwords indefinitely.
+    pub fn record_success(&self, ip: IpAddr, username: Option<UsernameString>) {
+        self.succeed(ip, username.map(|username| LimitKey::Username(username.0)));
+    }
+
+    /// Like [Self::begin_attempt], but counts the attempt for the portfolio
+    /// being unlocked instead of a username.
+    pub fn begin_unlock_attempt(&self, ip: IpAddr, portfolio: SlugString) -> Result<(), Duration> {
+        self.begin(ip, Some(LimitKey::Portfolio(portfolio.0)))
+    }
+
+    /// Like [Self::record_success], but for [Self::begin_unlock_attempt].
+    pub fn record_unlock_success(&self, ip: IpAddr, portfolio: SlugString) {
+        self.succeed(ip, Some(LimitKey::Portfolio(portfolio.0)));
+    }
+
+    fn begin(&self, ip: IpAddr, target: Option<LimitKey>) -> Result<(), Duration> {
+        let mut attempts = self.attempts.lock().unwrap();
+        let now = Instant::now();
+        let retry_after = keys(ip, target)
+            .filter_map(|key| attempts.get(&key)?.locked_until)
+            .filter(|&locked_until| locked_until > now)
+            .map(|locked_until| locked_until - now)
+            .max();
+        if let Some(retry_after) = retry_after {
+            return Err(retry_after);
+        }
+
+        for key in keys(ip, target) {
+            let attempt = attempts.entry(key).or_insert(FailedAttempts {
+                count: 0,
+                last_failure: now,
+                locked_until: None,
+            });
+            attempt.count = attempt.count.saturating_add(1);
+            attempt.last_failure = now;
+            if attempt.count > free_attempts(key) {
+                let exponent = (attempt.count - free_attempts(key) - 1).min(32);
+                let lockout_seconds = config::login_lockout_base_seconds()
+                    .saturating_mul(1 << exponent)
+                    .min(config::login_lockout_max_seconds());
+                attempt.locked_until = Some(now + Duration::from_secs(lockout_seconds));
+                tracing::info!("Locking out {key:?} for {lockout_seconds} seconds.");
+            }
+        }
+        Ok(())
+    }
+
+    fn succeed(&self, ip: IpAddr, target: Option<LimitKey>) {
+        let mut attempts = self.attempts.lock().unwrap();
+        let key = LimitKey::Ip(ip);
+        if let Some(attempt) = attempts.get_mut(&key) {
+            attempt.count = attempt.count.saturating_sub(1);
+            if attempt.count <= free_attempts(key) {
+                attempt.locked_until = None;
+            }
+        }
+        if let Some(target) = target {
+            attempts.remove(&target);
+        }
+    }
+
+    /// Forgets the failures which happened so long ago that they would not
+    /// cause a lockout anymore, so that the map doesn't grow indefinitely.
+    pub fn remove_stale(&self) {
+        let mut attempts = self.attempts.lock().unwrap();
+        let forget_after = Duration::from_secs(config::login_lockout_max_seconds());
+        let now = Instant::now();
+        attempts.retain(|_, attempt| {
+            let locked = attempt.locked_until.is_some_and(|locked_until| locked_until > now);
+            locked || now.duration_since(attempt.last_failure) < forget_after
+        });
+    }
+}
+
+fn free_attempts(key: LimitKey) -> u32 {
+    match key {
+        LimitKey::Ip(_) => config::login_attempts_per_ip(),
+        LimitKey::Username(_) => config::login_attempts_per_username(),
+        LimitKey::Portfolio(_) => config::unlock_attempts_per_portfolio(),
+    }
+}
+
+fn keys(ip: IpAddr, target: Option<LimitKey>) -> impl Iterator<Item = LimitKey> {
+    [Some(LimitKey::Ip(ip)), target].into_iter().flatten()
+}
@@ -1,20 +1,63 @@
+use core::convert::Infallible;
 use core::str::FromStr;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::sync::Arc;
 
 use arrayvec::ArrayString;
-use axum::extract::FromRequestParts;
+use axum::extract::{ConnectInfo, FromRequestParts};
 use axum::http::request::Parts;
 use axum::http::{HeaderMap, HeaderValue};
 use sqlx::AnyPool;
 
 use crate::api_errors::ApiError;
 use crate::array_string_types::UuidString;
-use crate::data::user::Session;
-use crate::services;
+use crate::data::audit::RequestOrigin;
+use crate::data::user::{ApiTokenScope, Session};
+use crate::rate_limiter::AttemptRateLimiter;
+use crate::{config, services};
 
 #[derive(Debug)]
 pub struct SharedState {
     pub db_pool: AnyPool,
+    pub login_rate_limiter: AttemptRateLimiter,
+    pub unlock_rate_limiter: AttemptRateLimiter,
+    /// Used for requests to the OpenID Connect identity provider.
+    pub http_client: reqwest::Client,
+}
+
+/// The ip address of the client, either from the header configured with
+/// `CLIENT_IP_HEADER`, or the address of the peer connecting to the server.
+#[derive(Debug, Clone, Copy)]
+pub struct ClientIp(pub IpAddr);
+
+#[axum::async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ClientIp {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        if let Some(header) = config::client_ip_header() {
+            let ip = parts.headers.get(&header).and_then(|value| value.to_str().ok());
+            // X-Forwarded-For style headers may contain a list of addresses, the first one being the client
+            let ip = ip.and_then(|ip| ip.split(',').next()).map(str::trim);
+            if let Some(ip) = ip.and_then(|ip| IpAddr::from_str(ip).ok()) {
+                return Ok(ClientIp(ip));
+            }
+            tracing::warn!("The {header} header is missing or malformed, using the peer address.");
+        }
+        let peer = parts.extensions.get::<ConnectInfo<SocketAddr>>();
+        Ok(ClientIp(peer.map_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED), |info| info.0.ip())))
+    }
+}
+
+#[axum::async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for RequestOrigin {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let ClientIp(ip) = ClientIp::from_request_parts(parts, state).await?;
+        let user_agent = parts.headers.get("user-agent").and_then(|value| value.to_str().ok());
+        Ok(RequestOrigin { ip: ip.to_string(), user_agent: user_agent.map(str::to_owned) })
+    }
 }
 
 #[axum::async_trait]
@@ -34,6 +77,18 @@ impl FromRequestParts<Arc<SharedState>> for Session {
             let Some(session_id) = auth.strip_prefix("Bearer ") else {
                 continue;
             };
+
+            if session_id.starts_with(services::user::api_tokens::TOKEN_PREFIX) {
+                let session =
+                    services::user::api_tokens::get_session_for_token(&state.db_pool, session_id)
+                        .await
+                        .map_err(|err| {
+                            tracing::error!("Fetching API token failed: {err:?}");
+                            ApiError::DbError
+                        })?;
+                return session.ok_or(ApiError::InvalidSession);
+            }
+
             let Ok(session_id) = ArrayString::from_str(session_id) else {
                 continue;
             };
@@ -54,3 +109,55 @@ impl FromRequestParts<Arc<SharedState>> for Session {
         Err(ApiError::MissingSession)
     }
 }
+
+/// A [Session] of a user with administrator rights. API tokens are never
+/// accepted for administration.
+#[derive(Debug)]
+pub struct AdminSession(pub Session);
+
+#[axum::async_trait]
+impl FromRequestParts<Arc<SharedState>> for AdminSession {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<SharedState>,
+    ) -> Result<Self, Self::Rejection> {
+        let session = Session::from_request_parts(parts, state).await?;
+        session.require_login_session()?;
+        let is_admin =
+            services::user::is_admin(&state.db_pool, session.user_id).await.map_err(|err| {
+                tracing::error!("Checking admin rights failed: {err:?}");
+                ApiError::DbError
+            })?;
+        if is_admin { Ok(AdminSession(session)) } else { Err(ApiError::Forbidden) }
+    }
+}
+
+impl Session {
+    /// Checks that the session is allowed to do things requiring the scope.
+    /// Sessions from logging in can do everything, sessions authenticated
+    /// with an API token only what the token's scopes allow.
+    pub fn require_scope(&self, scope: ApiTokenScope) -> Result<(), ApiError> {
+        if self.has_scope(scope) { Ok(()) } else { Err(ApiError::InsufficientScope) }
+    }
+
+    /// Like [Session::require_scope], for routes which are also available
+    /// without a session, where a session without the scope should be
+    /// treated as no session at all.
+    pub fn has_scope(&self, scope: ApiTokenScope) -> bool {
+        match self.api_token_scopes {
+            Some(scopes) => scopes.contains(scope),
+            None => true,
+        }
+    }
+
+    /// Checks that the session is from logging in, for account management
+    /// endpoints that API tokens should never have access to.
+    pub fn require_login_session(&self) -> Result<(), ApiError> {
+        match self.api_token_scopes {
+            Some(_) => Err(ApiError::InsufficientScope),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,284 @@
+use std::sync::Arc;
+
+use axum::extract::{Path, Query, State};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+
+use crate::api_errors::ApiError;
+use crate::data::admin::UserSummary;
+use crate::data::audit::{AuditAction, AuditEvent, AuditEventFilter, RequestOrigin};
+use crate::data::collaborator::CollaborationTarget;
+use crate::request_state::{AdminSession, SharedState};
+use crate::services;
+
+pub fn create_router() -> Router<Arc<SharedState>> {
+    Router::new()
+        .route("/users", get(users))
+        .route("/users/:username/disable", post(disable_user))
+        .route("/users/:username/enable", post(enable_user))
+        .route("/users/:username/logout", post(logout_user))
+        .route("/users/:username/password-reset", post(create_password_reset))
+        .route("/users/:username/totp-reset", post(reset_totp))
+        .route("/portfolios/:slug/unpublish", post(unpublish_portfolio))
+        .route("/portfolios/:slug/transfer", post(transfer_portfolio))
+        .route("/works/:slug/transfer", post(transfer_work))
+        .route("/audit", get(audit_events))
+}
+
+async fn users(
+    State(state): State<Arc<SharedState>>,
+    _: AdminSession,
+) -> Result<Json<Vec<UserSummary>>, ApiError> {
+    let users = services::admin::get_users(&state.db_pool).await.map_err(|err| {
+        tracing::error!("Getting all users failed: {err:?}");
+        ApiError::DbError
+    })?;
+    Ok(Json(users))
+}
+
+async fn get_user_id(state: &SharedState, username: &str) -> Result<i32, ApiError> {
+    services::user::get_user_id(&state.db_pool, username)
+        .await
+        .map_err(|err| {
+            tracing::error!("Getting user id for {username} failed: {err:?}");
+            ApiError::DbError
+        })?
+        .ok_or(ApiError::NoSuchUser)
+}
+
+async fn disable_user(
+    State(state): State<Arc<SharedState>>,
+    AdminSession(session): AdminSession,
+    origin: RequestOrigin,
+    Path(username): Path<String>,
+) -> Result<(), ApiError> {
+    let user_id = get_user_id(&state, &username).await?;
+    if user_id == session.user_id {
+        return Err(ApiError::CannotDisableSelf);
+    }
+    set_user_disabled(&state, &origin, session.user_id, user_id, true).await
+}
+
+async fn enable_user(
+    State(state): State<Arc<SharedState>>,
+    AdminSession(session): AdminSession,
+    origin: RequestOrigin,
+    Path(username): Path<String>,
+) -> Result<(), ApiError> {
+    let user_id = get_user_id(&state, &username).await?;
+    set_user_disabled(&state, &origin, session.user_id, user_id, false).await
+}
+
+async fn set_user_disabled(
+    state: &SharedState,
+    origin: &RequestOrigin,
+    admin_user_id: i32,
+    user_id: i32,
+    disabled: bool,
+) -> Result<(), ApiError> {
+    let mut conn = state.db_pool.begin().await.map_err(|_| ApiError::DbTransactionBegin)?;
+
+    let found =
+        services::admin::set_user_disabled(&mut *conn, origin, admin_user_id, user_id, disabled)
+            .await
+            .map_err(|err| {
+                tracing::error!("Setting user {user_id} disabled={disabled} failed: {err:?}");
+                ApiError::DbError
+            })?;
+    if !found {
+        return Err(ApiError::NoSuchUser);
+    }
+
+    conn.commit().await.map_err(|_| ApiError::DbTransactionCommit)?;
+
+    Ok(())
+}
+
+async fn logout_user(
+    State(state): State<Arc<SharedState>>,
+    _: AdminSession,
+    Path(username): Path<String>,
+) -> Result<(), ApiError> {
+    let user_id = get_user_id(&state, &username).await?;
+    let mut conn = state.db_pool.begin().await.map_err(|_| ApiError::DbTransactionBegin)?;
+
+    services::user::remove_user_sessions(&mut *conn, user_id).await.map_err(|err| {
+        tracing::error!("Logging out {username} failed: {err:?}");
+        ApiError::DbError
+    })?;
+
+    conn.commit().await.map_err(|_| ApiError::DbTransactionCommit)?;
+
+    Ok(())
+}
+
+#[derive(serde::Serialize)]
+struct PasswordReset {
+    /// The token to be passed to `/user/password-reset` along with the new
+    /// password. Meant to be sent to the user as a part of a link.
+    token: String,
+    /// In seconds since the unix epoch.
+    expires_at: i64,
+}
+async fn create_password_reset(
+    State(state): State<Arc<SharedState>>,
+    AdminSession(session): AdminSession,
+    origin: RequestOrigin,
+    Path(username): Path<String>,
+) -> Result<Json<PasswordReset>, ApiError> {
+    let user_id = get_user_id(&state, &username).await?;
+    let mut conn = state.db_pool.begin().await.map_err(|_| ApiError::DbTransactionBegin)?;
+
+    let (token, expires_at) = services::user::password_resets::create_password_reset(
+        &mut *conn,
+        &origin,
+        session.user_id,
+        user_id,
+    )
+    .await
+    .map_err(|err| {
+        tracing::error!("Creating a password reset for {username} failed: {err:?}");
+        ApiError::DbError
+    })?;
+
+    conn.commit().await.map_err(|_| ApiError::DbTransactionCommit)?;
+
+    Ok(Json(PasswordReset { token, expires_at }))
+}
+
+async fn reset_totp(
+    State(state): State<Arc<SharedState>>,
+    AdminSession(session): AdminSession,
+    origin: RequestOrigin,
+    Path(username): Path<String>,
+) -> Result<(), ApiError> {
+    let user_id = get_user_id(&state, &username).await?;
+    let mut conn = state.db_pool.begin().await.map_err(|_| ApiError::DbTransactionBegin)?;
+
+    services::admin::reset_totp(&mut *conn, &origin, session.user_id, user_id).await.map_err(
+        |err| {
+            tracing::error!("Resetting TOTP for {username} failed: {err:?}");
+            ApiError::DbError
+        },
+    )?;
+
+    conn.commit().await.map_err(|_| ApiError::DbTransactionCommit)?;
+
+    Ok(())
+}
+
+async fn unpublish_portfolio(
+    State(state): State<Arc<SharedState>>,
+    AdminSession(session): AdminSession,
+    origin: RequestOrigin,
+    Path(slug): Path<String>,
+) -> Result<(), ApiError> {
+    let mut conn = state.db_pool.begin().await.map_err(|_| ApiError::DbTransactionBegin)?;
+
+    let found = services::admin::unpublish_portfolio(&mut *conn, &origin, session.user_id, &slug)
+        .await
+        .map_err(|err| {
+            tracing::error!("Unpublishing the {slug} portfolio failed: {err:?}");
+            ApiError::DbError
+        })?;
+    if !found {
+        return Err(ApiError::NoSuchSlug);
+    }
+
+    conn.commit().await.map_err(|_| ApiError::DbTransactionCommit)?;
+
+    Ok(())
+}
+
+#[derive(serde::Deserialize)]
+struct TransferRequest {
+    /// The new owner.
+    username: String,
+}
+
+async fn transfer_portfolio(
+    State(state): State<Arc<SharedState>>,
+    AdminSession(session): AdminSession,
+    origin: RequestOrigin,
+    Path(slug): Path<String>,
+    Json(TransferRequest { username }): Json<TransferRequest>,
+) -> Result<(), ApiError> {
+    let target = CollaborationTarget::Portfolio;
+    force_transfer(&state, &origin, session.user_id, target, &slug, &username).await
+}
+
+async fn transfer_work(
+    State(state): State<Arc<SharedState>>,
+    AdminSession(session): AdminSession,
+    origin: RequestOrigin,
+    Path(slug): Path<String>,
+    Json(TransferRequest { username }): Json<TransferRequest>,
+) -> Result<(), ApiError> {
+    let target = CollaborationTarget::Work;
+    force_transfer(&state, &origin, session.user_id, target, &slug, &username).await
+}
+
+async fn force_transfer(
+    state: &SharedState,
+    origin: &RequestOrigin,
+    admin_user_id: i32,
+    target: CollaborationTarget,
+    slug: &str,
+    username: &str,
+) -> Result<(), ApiError> {
+    let user_id = get_user_id(state, username).await?;
+    let mut conn = state.db_pool.begin().await.map_err(|_| ApiError::DbTransactionBegin)?;
+
+    let found = services::collaborator::transfer::force_transfer(
+        &mut *conn,
+        origin,
+        admin_user_id,
+        target,
+        slug,
+        user_id,
+    )
+    .await
+    .map_err(|err| {
+        tracing::error!("Transferring {slug} to {username} failed: {err:?}");
+        ApiError::DbError
+    })?;
+    if !found {
+        return Err(ApiError::NoSuchSlug);
+    }
+
+    conn.commit().await.map_err(|_| ApiError::DbTransactionCommit)?;
+
+    Ok(())
+}
+
+#[derive(serde::Deserialize)]
+struct AuditQuery {
+    /// The username of the user who caused the events.
+    username: Option<String>,
+    action: Option<AuditAction>,
+    target: Option<String>,
+    ip: Option<String>,
+    since: Option<i64>,
+    until: Option<i64>,
+    before_id: Option<i32>,
+    limit: Option<i64>,
+}
+async fn audit_events(
+    State(state): State<Arc<SharedState>>,
+    _: AdminSession,
+    Query(query): Query<AuditQuery>,
+) -> Result<Json<Vec<AuditEvent>>, ApiError> {
+    let AuditQuery { username, action, target, ip, since, until, before_id, limit } = query;
+    let actor_user_id = match username {
+        Some(username) => Some(get_user_id(&state, &username).await?),
+        None => None,
+    };
+    let filter = AuditEventFilter { actor_user_id, action, target, ip, since, until, before_id };
+    let limit = limit.unwrap_or(100).clamp(1, 1000);
+    let events =
+        services::audit::get_events(&state.db_pool, &filter, limit).await.map_err(|err| {
+            tracing::error!("Getting audit events failed: {err:?}");
+            ApiError::DbError
+        })?;
+    Ok(Json(events))
+}
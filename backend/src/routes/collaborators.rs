@@ -0,0 +1,291 @@
+//! The collaborator management routes, which are the same for works and
+//! portfolios. Nested under `/work/:slug/collaborators` and
+//! `/portfolio/:slug/collaborators`, and the ownership transfer routes under
+//! `/work/:slug/transfer` and `/portfolio/:slug/transfer`.
+
+use std::sync::Arc;
+
+use axum::extract::{Path, State};
+use axum::routing::{delete, get, post, put};
+use axum::{Json, Router};
+
+use crate::api_errors::ApiError;
+use crate::data::audit::RequestOrigin;
+use crate::data::collaborator::{
+    CollaborationTarget, Collaborator, CollaboratorChangeOutcome, CollaboratorRole,
+};
+use crate::data::user::{ApiTokenScope, Session};
+use crate::routes::SharedState;
+use crate::services;
+
+pub fn create_router(target: CollaborationTarget) -> Router<Arc<SharedState>> {
+    Router::new()
+        .route("/", get(move |state, session, path| all(target, state, session, path)))
+        .route(
+            "/:username",
+            put(move |state, session, origin, path, json| {
+                set(target, state, session, origin, path, json)
+            }),
+        )
+        .route(
+            "/:username",
+            delete(move |state, session, origin, path| {
+                remove(target, state, session, origin, path)
+            }),
+        )
+        .route(
+            "/organizations/:organization",
+            put(move |state, session, origin, path, json| {
+                set_organization(target, state, session, origin, path, json)
+            })
+            .delete(move |state, session, origin, path| {
+                remove_organization(target, state, session, origin, path)
+            }),
+        )
+}
+
+pub fn create_transfer_router(target: CollaborationTarget) -> Router<Arc<SharedState>> {
+    Router::new().route(
+        "/",
+        post(move |state, session, origin, path, json| {
+            propose_transfer(target, state, session, origin, path, json)
+        })
+        .delete(move |state, session, origin, path| {
+            cancel_transfer(target, state, session, origin, path)
+        }),
+    )
+}
+
+/// Managing collaborators requires the same scope as editing the target.
+fn edit_scope(target: CollaborationTarget) -> ApiTokenScope {
+    match target {
+        CollaborationTarget::Work => ApiTokenScope::EditWorks,
+        CollaborationTarget::Portfolio => ApiTokenScope::PublishPortfolios,
+    }
+}
+
+fn outcome_to_result(outcome: CollaboratorChangeOutcome) -> Result<(), ApiError> {
+    match outcome {
+        CollaboratorChangeOutcome::Changed => Ok(()),
+        CollaboratorChangeOutcome::NoSuchSlug => Err(ApiError::NoSuchSlug),
+        CollaboratorChangeOutcome::NoSuchUser => Err(ApiError::NoSuchUser),
+        CollaboratorChangeOutcome::NoSuchOrganization => Err(ApiError::NoSuchOrganization),
+        CollaboratorChangeOutcome::NotOwner => Err(ApiError::Forbidden),
+        CollaboratorChangeOutcome::LastOwner => Err(ApiError::CannotRemoveLastOwner),
+        CollaboratorChangeOutcome::SameUser => Err(ApiError::CannotTransferToSelf),
+    }
+}
+
+async fn all(
+    target: CollaborationTarget,
+    State(state): State<Arc<SharedState>>,
+    session: Session,
+    Path(slug): Path<String>,
+) -> Result<Json<Vec<Collaborator>>, ApiError> {
+    session.require_scope(ApiTokenScope::Read)?;
+    let collaborators =
+        services::collaborator::get_collaborators(&state.db_pool, target, &slug, session.user_id)
+            .await
+            .map_err(|err| {
+                tracing::error!("Getting the collaborators of {slug} failed: {err:?}");
+                ApiError::DbError
+            })?
+            .ok_or(ApiError::NoSuchSlug)?;
+    Ok(Json(collaborators))
+}
+
+#[derive(serde::Deserialize)]
+struct SetCollaboratorRequest {
+    role: CollaboratorRole,
+}
+
+async fn set(
+    target: CollaborationTarget,
+    State(state): State<Arc<SharedState>>,
+    session: Session,
+    origin: RequestOrigin,
+    Path((slug, username)): Path<(String, String)>,
+    Json(SetCollaboratorRequest { role }): Json<SetCollaboratorRequest>,
+) -> Result<(), ApiError> {
+    session.require_scope(edit_scope(target))?;
+    let mut conn = state.db_pool.begin().await.map_err(|_| ApiError::DbTransactionBegin)?;
+
+    let outcome = services::collaborator::set_collaborator(
+        &mut *conn,
+        &origin,
+        target,
+        &slug,
+        session.user_id,
+        &username,
+        role,
+    )
+    .await
+    .map_err(|err| {
+        tracing::error!("Setting {username} as a collaborator of {slug} failed: {err:?}");
+        ApiError::DbError
+    })?;
+    outcome_to_result(outcome)?;
+
+    conn.commit().await.map_err(|_| ApiError::DbTransactionCommit)?;
+
+    Ok(())
+}
+
+async fn remove(
+    target: CollaborationTarget,
+    State(state): State<Arc<SharedState>>,
+    session: Session,
+    origin: RequestOrigin,
+    Path((slug, username)): Path<(String, String)>,
+) -> Result<(), ApiError> {
+    session.require_scope(edit_scope(target))?;
+    let mut conn = state.db_pool.begin().await.map_err(|_| ApiError::DbTransactionBegin)?;
+
+    let outcome = services::collaborator::remove_collaborator(
+        &mut *conn,
+        &origin,
+        target,
+        &slug,
+        session.user_id,
+        &username,
+    )
+    .await
+    .map_err(|err| {
+        tracing::error!("Removing {username} from the collaborators of {slug} failed: {err:?}");
+        ApiError::DbError
+    })?;
+    outcome_to_result(outcome)?;
+
+    conn.commit().await.map_err(|_| ApiError::DbTransactionCommit)?;
+
+    Ok(())
+}
+
+async fn set_organization(
+    target: CollaborationTarget,
+    State(state): State<Arc<SharedState>>,
+    session: Session,
+    origin: RequestOrigin,
+    Path((slug, organization)): Path<(String, String)>,
+    Json(SetCollaboratorRequest { role }): Json<SetCollaboratorRequest>,
+) -> Result<(), ApiError> {
+    session.require_scope(edit_scope(target))?;
+    let mut conn = state.db_pool.begin().await.map_err(|_| ApiError::DbTransactionBegin)?;
+
+    let outcome = services::collaborator::set_organization_collaborator(
+        &mut *conn,
+        &origin,
+        target,
+        &slug,
+        session.user_id,
+        &organization,
+        role,
+    )
+    .await
+    .map_err(|err| {
+        tracing::error!("Setting {organization} as a collaborator of {slug} failed: {err:?}");
+        ApiError::DbError
+    })?;
+    outcome_to_result(outcome)?;
+
+    conn.commit().await.map_err(|_| ApiError::DbTransactionCommit)?;
+
+    Ok(())
+}
+
+async fn remove_organization(
+    target: CollaborationTarget,
+    State(state): State<Arc<SharedState>>,
+    session: Session,
+    origin: RequestOrigin,
+    Path((slug, organization)): Path<(String, String)>,
+) -> Result<(), ApiError> {
+    session.require_scope(edit_scope(target))?;
+    let mut conn = state.db_pool.begin().await.map_err(|_| ApiError::DbTransactionBegin)?;
+
+    let outcome = services::collaborator::remove_organization_collaborator(
+        &mut *conn,
+        &origin,
+        target,
+        &slug,
+        session.user_id,
+        &organization,
+    )
+    .await
+    .map_err(|err| {
+        tracing::error!("Removing {organization} from the collaborators of {slug} failed: {err:?}");
+        ApiError::DbError
+    })?;
+    outcome_to_result(outcome)?;
+
+    conn.commit().await.map_err(|_| ApiError::DbTransactionCommit)?;
+
+    Ok(())
+}
+
+#[derive(serde::Deserialize)]
+struct ProposeTransferRequest {
+    username: String,
+}
+
+async fn propose_transfer(
+    target: CollaborationTarget,
+    State(state): State<Arc<SharedState>>,
+    session: Session,
+    origin: RequestOrigin,
+    Path(slug): Path<String>,
+    Json(ProposeTransferRequest { username }): Json<ProposeTransferRequest>,
+) -> Result<(), ApiError> {
+    session.require_scope(edit_scope(target))?;
+    let mut conn = state.db_pool.begin().await.map_err(|_| ApiError::DbTransactionBegin)?;
+
+    let outcome = services::collaborator::transfer::propose_transfer(
+        &mut *conn,
+        &origin,
+        target,
+        &slug,
+        session.user_id,
+        &username,
+    )
+    .await
+    .map_err(|err| {
+        tracing::error!("Proposing the transfer of {slug} to {username} failed: {err:?}");
+        ApiError::DbError
+    })?;
+    outcome_to_result(outcome)?;
+
+    conn.commit().await.map_err(|_| ApiError::DbTransactionCommit)?;
+
+    Ok(())
+}
+
+async fn cancel_transfer(
+    target: CollaborationTarget,
+    State(state): State<Arc<SharedState>>,
+    session: Session,
+    origin: RequestOrigin,
+    Path(slug): Path<String>,
+) -> Result<(), ApiError> {
+    session.require_scope(edit_scope(target))?;
+    let mut conn = state.db_pool.begin().await.map_err(|_| ApiError::DbTransactionBegin)?;
+
+    let cancelled = services::collaborator::transfer::cancel_transfer(
+        &mut *conn,
+        &origin,
+        target,
+        &slug,
+        session.user_id,
+    )
+    .await
+    .map_err(|err| {
+        tracing::error!("Cancelling the transfer of {slug} failed: {err:?}");
+        ApiError::DbError
+    })?;
+    if !cancelled {
+        return Err(ApiError::NoSuchTransfer);
+    }
+
+    conn.commit().await.map_err(|_| ApiError::DbTransactionCommit)?;
+
+    Ok(())
+}
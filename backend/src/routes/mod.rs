@@ -6,24 +6,39 @@
 use std::sync::Arc;
 
 use anyhow::Context;
-use axum::extract::State;
+use arrayvec::ArrayString;
+use axum::extract::{Query, State};
 use axum::http::StatusCode;
+use axum::response::{IntoResponse, Redirect, Response};
 use axum::routing::get;
 use axum::{Json, Router};
-use sqlx::Connection;
+use sqlx::{Any, Connection, Executor};
 
+use crate::api_errors::ApiError;
+use crate::array_string_types::SlugString;
+use crate::data::collaborator::CollaborationTarget;
+use crate::data::user::{ApiTokenScope, Session};
 use crate::request_state::SharedState;
+use crate::services;
 
+mod admin;
+mod collaborators;
+mod organization;
 mod portfolio;
+mod trash;
 mod user;
 mod work;
 
 pub fn create_router() -> Router<Arc<SharedState>> {
     Router::new()
         .route("/health", get(health))
+        .route("/slug-available", get(slug_available))
         .nest("/user", user::create_router())
         .nest("/portfolio", portfolio::create_router())
         .nest("/work", work::create_router())
+        .nest("/trash", trash::create_router())
+        .nest("/organization", organization::create_router())
+        .nest("/admin", admin::create_router())
         .fallback(not_found)
 }
 
@@ -46,6 +61,99 @@ pub async fn health(State(state): State<Arc<SharedState>>) -> (StatusCode, Json<
     }
 }
 
+/// The query parameters for reading portfolios, works, and files as someone who
+/// isn't a collaborator. The tokens are for the portfolio, or for a portfolio
+/// the work or file's work is in.
+#[derive(serde::Deserialize)]
+pub struct AccessQuery {
+    /// A preview token, which shows the draft to people who aren't
+    /// collaborators.
+    pub preview: Option<String>,
+    /// Needed for seeing the published version of a password-protected
+    /// portfolio, see `/portfolio/:slug/unlock`.
+    pub viewer_token: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct SlugAvailableQuery {
+    slug: String,
+    /// Works and portfolios have separate slugs.
+    target: CollaborationTarget,
+}
+#[derive(serde::Serialize)]
+struct SlugAvailability {
+    /// The slug as it would be saved.
+    slug: String,
+    available: bool,
+}
+/// Checks whether a new work or portfolio could be created with the slug.
+/// Invalid slugs get an [ApiError::InvalidSlug] error.
+async fn slug_available(
+    State(state): State<Arc<SharedState>>,
+    session: Session,
+    Query(SlugAvailableQuery { slug, target }): Query<SlugAvailableQuery>,
+) -> Result<Json<SlugAvailability>, ApiError> {
+    session.require_scope(ApiTokenScope::Read)?;
+    let slug = services::slug::normalize(&slug);
+    if !services::slug::is_valid(&slug) {
+        return Err(ApiError::InvalidSlug);
+    }
+    let mut conn = state.db_pool.acquire().await.map_err(|_| ApiError::DbError)?;
+    let available =
+        services::slug::is_available(&mut *conn, target, &slug, None).await.map_err(|err| {
+            tracing::error!("Checking if the slug {slug} is available failed: {err:?}");
+            ApiError::DbError
+        })?;
+    Ok(Json(SlugAvailability { slug, available }))
+}
+
+/// Normalizes the slug for a new or renamed work or portfolio, and checks
+/// that it's valid and available. `own_slug` is the current slug of the work
+/// or portfolio being renamed, if any.
+pub async fn check_new_slug<E>(
+    conn: &mut E,
+    target: CollaborationTarget,
+    slug: &str,
+    own_slug: Option<&str>,
+) -> Result<SlugString, ApiError>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let slug = check_slug_format(slug)?;
+    let available = services::slug::is_available(&mut *conn, target, &slug.0, own_slug)
+        .await
+        .map_err(|err| {
+            tracing::error!("Checking if the slug {slug} is available failed: {err:?}");
+            ApiError::DbError
+        })?;
+    if !available {
+        return Err(ApiError::SlugTaken);
+    }
+    Ok(slug)
+}
+
+/// Normalizes the slug and checks that it's valid, without checking if it's
+/// available. Used as is for organizations, whose slugs are unique in the
+/// database and can't be changed.
+pub fn check_slug_format(slug: &str) -> Result<SlugString, ApiError> {
+    let slug = services::slug::normalize(slug);
+    if !services::slug::is_valid(&slug) {
+        return Err(ApiError::InvalidSlug);
+    }
+    Ok(SlugString(ArrayString::from(&slug).map_err(|_| ApiError::InvalidSlug)?))
+}
+
+/// Redirects a request for a work or portfolio to the same one under another
+/// slug, keeping the query. The location is relative to the requested slug, so
+/// the base path doesn't matter.
+fn redirect_to_slug(slug: &str, raw_query: Option<String>) -> Response {
+    let location = match raw_query {
+        Some(query) => format!("{slug}?{query}"),
+        None => slug.to_string(),
+    };
+    Redirect::temporary(&location).into_response()
+}
+
 async fn not_found() -> (StatusCode, &'static str) {
     (
         StatusCode::NOT_FOUND,
@@ -0,0 +1,186 @@
+use std::sync::Arc;
+
+use axum::extract::{Path, State};
+use axum::routing::{delete, get, put};
+use axum::{Json, Router};
+
+use crate::api_errors::ApiError;
+use crate::data::audit::RequestOrigin;
+use crate::data::organization::{
+    Organization, OrganizationChangeOutcome, OrganizationMembership, OrganizationRole,
+};
+use crate::data::user::{ApiTokenScope, Session};
+use crate::routes::{SharedState, check_slug_format};
+use crate::services;
+
+pub fn create_router() -> Router<Arc<SharedState>> {
+    Router::new()
+        .route("/", get(all))
+        .route("/:slug", get(by_slug).post(create).delete(remove))
+        .route("/:slug/members/:username", put(set_member))
+        .route("/:slug/members/:username", delete(remove_member))
+}
+
+fn outcome_to_result(outcome: OrganizationChangeOutcome) -> Result<(), ApiError> {
+    match outcome {
+        OrganizationChangeOutcome::Changed => Ok(()),
+        OrganizationChangeOutcome::NoSuchOrganization => Err(ApiError::NoSuchOrganization),
+        OrganizationChangeOutcome::NoSuchUser => Err(ApiError::NoSuchUser),
+        OrganizationChangeOutcome::NotAdmin => Err(ApiError::Forbidden),
+        OrganizationChangeOutcome::LastAdmin => Err(ApiError::CannotRemoveLastAdmin),
+    }
+}
+
+async fn all(
+    State(state): State<Arc<SharedState>>,
+    session: Session,
+) -> Result<Json<Vec<OrganizationMembership>>, ApiError> {
+    session.require_scope(ApiTokenScope::Read)?;
+    let organizations = services::organization::get_organizations(&state.db_pool, session.user_id)
+        .await
+        .map_err(|err| {
+            tracing::error!("Getting the organizations of the logged in user failed: {err:?}");
+            ApiError::DbError
+        })?;
+    Ok(Json(organizations))
+}
+
+async fn by_slug(
+    State(state): State<Arc<SharedState>>,
+    session: Session,
+    Path(slug): Path<String>,
+) -> Result<Json<Organization>, ApiError> {
+    session.require_scope(ApiTokenScope::Read)?;
+    let organization =
+        services::organization::get_organization(&state.db_pool, &slug, session.user_id)
+            .await
+            .map_err(|err| {
+                tracing::error!("Getting the {slug} organization failed: {err:?}");
+                ApiError::DbError
+            })?
+            .ok_or(ApiError::NoSuchOrganization)?;
+    Ok(Json(organization))
+}
+
+#[derive(serde::Deserialize)]
+struct CreateOrganizationRequest {
+    name: String,
+}
+
+async fn create(
+    State(state): State<Arc<SharedState>>,
+    session: Session,
+    origin: RequestOrigin,
+    Path(slug): Path<String>,
+    Json(CreateOrganizationRequest { name }): Json<CreateOrganizationRequest>,
+) -> Result<Json<Organization>, ApiError> {
+    session.require_login_session()?;
+    let slug = check_slug_format(&slug)?;
+    let mut conn = state.db_pool.begin().await.map_err(|_| ApiError::DbTransactionBegin)?;
+
+    let organization = services::organization::create_organization(
+        &mut *conn,
+        &origin,
+        &slug.0,
+        session.user_id,
+        &name,
+    )
+    .await
+    .map_err(|err| {
+        tracing::error!("Creating a new organization failed: {err:?}");
+        if services::is_unique_constraint_violation(err.root_cause()) {
+            return ApiError::SlugTaken;
+        }
+        ApiError::DbError
+    })?;
+
+    conn.commit().await.map_err(|_| ApiError::DbTransactionCommit)?;
+
+    Ok(Json(organization))
+}
+
+async fn remove(
+    State(state): State<Arc<SharedState>>,
+    session: Session,
+    origin: RequestOrigin,
+    Path(slug): Path<String>,
+) -> Result<(), ApiError> {
+    session.require_login_session()?;
+    let mut conn = state.db_pool.begin().await.map_err(|_| ApiError::DbTransactionBegin)?;
+
+    let outcome =
+        services::organization::delete_organization(&mut *conn, &origin, &slug, session.user_id)
+            .await
+            .map_err(|err| {
+                tracing::error!("Deleting the {slug} organization failed: {err:?}");
+                ApiError::DbError
+            })?;
+    outcome_to_result(outcome)?;
+
+    conn.commit().await.map_err(|_| ApiError::DbTransactionCommit)?;
+
+    Ok(())
+}
+
+#[derive(serde::Deserialize)]
+struct SetMemberRequest {
+    role: OrganizationRole,
+}
+
+async fn set_member(
+    State(state): State<Arc<SharedState>>,
+    session: Session,
+    origin: RequestOrigin,
+    Path((slug, username)): Path<(String, String)>,
+    Json(SetMemberRequest { role }): Json<SetMemberRequest>,
+) -> Result<(), ApiError> {
+    session.require_login_session()?;
+    let mut conn = state.db_pool.begin().await.map_err(|_| ApiError::DbTransactionBegin)?;
+
+    let outcome = services::organization::set_member(
+        &mut *conn,
+        &origin,
+        &slug,
+        session.user_id,
+        &username,
+        role,
+    )
+    .await
+    .map_err(|err| {
+        tracing::error!("Setting {username} as a member of {slug} failed: {err:?}");
+        ApiError::DbError
+    })?;
+    outcome_to_result(outcome)?;
+
+    conn.commit().await.map_err(|_| ApiError::DbTransactionCommit)?;
+
+    Ok(())
+}
+
+async fn remove_member(
+    State(state): State<Arc<SharedState>>,
+    session: Session,
+    origin: RequestOrigin,
+    Path((slug, username)): Path<(String, String)>,
+) -> Result<(), ApiError> {
+    session.require_login_session()?;
+    let mut conn = state.db_pool.begin().await.map_err(|_| ApiError::DbTransactionBegin)?;
+
+    let outcome = services::organization::remove_member(
+        &mut *conn,
+        &origin,
+        &slug,
+        session.user_id,
+        &username,
+    )
+    .await
+    .map_err(|err| {
+        tracing::error!("Removing {username} from the {slug} organization failed: {err:?}");
+        ApiError::DbError
+    })?;
+    outcome_to_result(outcome)?;
+
+    conn.commit().await.map_err(|_| ApiError::DbTransactionCommit)?;
+
+    Ok(())
+}
@@ -1,29 +1,50 @@
 use std::sync::Arc;
 
-use axum::extract::{Path, State};
-use axum::routing::{get, post, put};
+use arrayvec::ArrayString;
+use axum::extract::{Path, Query, RawQuery, State};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get, post, put};
 use axum::{Json, Router};
 
 use crate::api_errors::ApiError;
-use crate::data::portfolio::{Portfolio, PortfolioRow};
-use crate::data::user::Session;
-use crate::routes::SharedState;
+use crate::array_string_types::SlugString;
+use crate::data::audit::RequestOrigin;
+use crate::data::collaborator::CollaborationTarget;
+use crate::data::portfolio::{Portfolio, PortfolioRow, PortfolioViewerToken};
+use crate::data::user::{ApiTokenScope, Session};
+use crate::request_state::ClientIp;
+use crate::routes::{AccessQuery, SharedState, check_new_slug, collaborators, redirect_to_slug};
 use crate::services;
 
+mod previews;
+
 pub fn create_router() -> Router<Arc<SharedState>> {
     Router::new()
         .route("/", get(all))
         .route("/:slug", get(by_slug))
         .route("/:slug", post(create))
         .route("/:slug", put(edit))
+        .route("/:slug", delete(remove))
+        .route("/:slug/clone", post(clone))
+        .route("/:slug/publish", post(publish))
+        .route("/:slug/password", put(set_password))
+        .route("/:slug/unlock", post(unlock))
+        .nest("/:slug/previews", previews::create_router())
+        .nest("/:slug/collaborators", collaborators::create_router(CollaborationTarget::Portfolio))
+        .nest(
+            "/:slug/transfer",
+            collaborators::create_transfer_router(CollaborationTarget::Portfolio),
+        )
 }
 
 async fn all(
     State(state): State<Arc<SharedState>>,
-    Session { user_id, .. }: Session,
+    session: Session,
 ) -> Result<Json<Vec<PortfolioRow>>, ApiError> {
-    let portfolios =
-        services::portfolio::get_portfolios(&state.db_pool, user_id).await.map_err(|err| {
+    session.require_scope(ApiTokenScope::Read)?;
+    let portfolios = services::portfolio::get_portfolios(&state.db_pool, session.user_id)
+        .await
+        .map_err(|err| {
             tracing::error!("Getting all portfolios for the logged in user failed: {err:?}");
             ApiError::DbError
         })?;
@@ -34,19 +55,76 @@ async fn by_slug(
     State(state): State<Arc<SharedState>>,
     session: Option<Session>,
     Path(slug): Path<String>,
-) -> Result<Json<Portfolio>, ApiError> {
+    Query(AccessQuery { preview, viewer_token }): Query<AccessQuery>,
+    RawQuery(raw_query): RawQuery,
+) -> Result<Response, ApiError> {
+    // Tokens without the read scope see only what anonymous users do
+    let session = session.filter(|session| session.has_scope(ApiTokenScope::Read));
+    // Slugs are stored in lowercase, so links typed with other cases are
+    // pointed to the stored one
+    let normalized = services::slug::normalize(&slug);
+    if normalized != slug {
+        return Ok(redirect_to_slug(&normalized, raw_query));
+    }
+    let user_id = session.map(|Session { user_id, .. }| user_id);
     let portfolio = services::portfolio::get_portfolio(
         &state.db_pool,
         &slug,
-        session.map(|Session { user_id, .. }| user_id),
+        user_id,
+        preview.as_deref(),
+        viewer_token.as_deref(),
     )
     .await
     .map_err(|err| {
         tracing::error!("Getting portfolio by slug failed: {err:?}");
         ApiError::DbError
-    })?
-    .ok_or(ApiError::NoSuchSlug)?;
-    Ok(Json(portfolio))
+    })?;
+    let Some(portfolio) = portfolio else {
+        let renamed =
+            services::slug::get_renamed_slug(&state.db_pool, CollaborationTarget::Portfolio, &slug)
+                .await
+                .map_err(|err| {
+                    tracing::error!("Checking if the {slug} portfolio was renamed failed: {err:?}");
+                    ApiError::DbError
+                })?;
+        if let Some(renamed) = renamed {
+            // Only redirect callers who could see the portfolio under its new
+            // slug, or unlock it with its password, so the new slug doesn't
+            // leak to anyone else
+            let visible = services::portfolio::get_portfolio(
+                &state.db_pool,
+                &renamed.0,
+                user_id,
+                preview.as_deref(),
+                viewer_token.as_deref(),
+            )
+            .await
+            .map_err(|err| {
+                tracing::error!("Getting the renamed {slug} portfolio failed: {err:?}");
+                ApiError::DbError
+            })?;
+            let locked =
+                services::portfolio::passwords::is_portfolio_locked(&state.db_pool, &renamed.0)
+                    .await
+                    .map_err(|err| {
+                        tracing::error!(
+                            "Checking if the renamed {slug} portfolio is locked failed: {err:?}"
+                        );
+                        ApiError::DbError
+                    })?;
+            if visible.is_some() || locked {
+                return Ok(redirect_to_slug(&renamed.0, raw_query));
+            }
+        }
+        let locked = services::portfolio::passwords::is_portfolio_locked(&state.db_pool, &slug)
+            .await
+            .map_err(|err| {
+                tracing::error!("Checking if the {slug} portfolio is locked failed: {err:?}");
+                ApiError::DbError
+            })?;
+        return Err(if locked { ApiError::PortfolioLocked } else { ApiError::NoSuchSlug });
+    };
+    Ok(Json(portfolio).into_response())
 }
 
 #[derive(serde::Deserialize)]
@@ -56,16 +134,21 @@ struct CreatePortfolioArgs {
 }
 async fn create(
     State(state): State<Arc<SharedState>>,
-    Session { user_id, .. }: Session,
+    session: Session,
+    origin: RequestOrigin,
     Path(slug): Path<String>,
     Json(args): Json<CreatePortfolioArgs>,
 ) -> Result<Json<Portfolio>, ApiError> {
+    session.require_scope(ApiTokenScope::PublishPortfolios)?;
+    check_schedule(&args.portfolio.row)?;
     let mut conn = state.db_pool.begin().await.map_err(|_| ApiError::DbTransactionBegin)?;
+    let slug = check_new_slug(&mut *conn, CollaborationTarget::Portfolio, &slug, None).await?;
 
     let portfolio = services::portfolio::create_portfolio(
         &mut *conn,
-        &slug,
-        user_id,
+        &origin,
+        slug.0.as_str(),
+        session.user_id,
         args.portfolio,
         args.publish,
     )
@@ -76,7 +159,8 @@ async fn create(
             return ApiError::SlugTaken;
         }
         ApiError::DbError
-    })?;
+    })?
+    .ok_or(ApiError::NoSuchSlug)?;
 
     conn.commit().await.map_err(|_| ApiError::DbTransactionCommit)?;
 
@@ -90,16 +174,26 @@ struct EditPortfolioArgs {
 }
 async fn edit(
     State(state): State<Arc<SharedState>>,
-    Session { user_id, .. }: Session,
+    session: Session,
+    origin: RequestOrigin,
     Path(slug): Path<String>,
-    Json(args): Json<EditPortfolioArgs>,
+    Json(mut args): Json<EditPortfolioArgs>,
 ) -> Result<Json<Portfolio>, ApiError> {
+    session.require_scope(ApiTokenScope::PublishPortfolios)?;
+    check_schedule(&args.portfolio.row)?;
     let mut conn = state.db_pool.begin().await.map_err(|_| ApiError::DbTransactionBegin)?;
+    if args.portfolio.row.slug.0.as_str() != slug {
+        let new_slug = args.portfolio.row.slug.0.as_str();
+        args.portfolio.row.slug =
+            check_new_slug(&mut *conn, CollaborationTarget::Portfolio, new_slug, Some(&slug))
+                .await?;
+    }
 
     let portfolio = services::portfolio::update_portfolio(
         &mut *conn,
+        &origin,
         &slug,
-        user_id,
+        session.user_id,
         args.portfolio,
         args.publish,
     )
@@ -110,9 +204,185 @@ async fn edit(
             return ApiError::SlugTaken;
         }
         ApiError::DbError
-    })?;
+    })?
+    .ok_or(ApiError::NoSuchSlug)?;
+
+    conn.commit().await.map_err(|_| ApiError::DbTransactionCommit)?;
+
+    Ok(Json(portfolio))
+}
+
+/// Checks that a scheduled unpublishing comes after the scheduled publishing,
+/// since the portfolio would otherwise never be unpublished.
+fn check_schedule(portfolio: &PortfolioRow) -> Result<(), ApiError> {
+    match (portfolio.publish_at, portfolio.unpublish_at) {
+        (Some(publish_at), Some(unpublish_at)) if publish_at >= unpublish_at => {
+            Err(ApiError::InvalidPublishSchedule)
+        }
+        _ => Ok(()),
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct CloneArgs {
+    /// The slug of the copy.
+    slug: String,
+}
+async fn clone(
+    State(state): State<Arc<SharedState>>,
+    session: Session,
+    origin: RequestOrigin,
+    Path(slug): Path<String>,
+    Json(CloneArgs { slug: new_slug }): Json<CloneArgs>,
+) -> Result<Json<Portfolio>, ApiError> {
+    session.require_scope(ApiTokenScope::PublishPortfolios)?;
+    let mut conn = state.db_pool.begin().await.map_err(|_| ApiError::DbTransactionBegin)?;
+    let new_slug =
+        check_new_slug(&mut *conn, CollaborationTarget::Portfolio, &new_slug, None).await?;
+
+    let portfolio = services::portfolio::clone_portfolio(
+        &mut *conn,
+        &origin,
+        &slug,
+        &new_slug.0,
+        session.user_id,
+    )
+    .await
+    .map_err(|err| {
+        tracing::error!("Cloning the {slug} portfolio failed: {err:?}");
+        if services::is_unique_constraint_violation(err.root_cause()) {
+            return ApiError::SlugTaken;
+        }
+        ApiError::DbError
+    })?
+    .ok_or(ApiError::NoSuchSlug)?;
+
+    conn.commit().await.map_err(|_| ApiError::DbTransactionCommit)?;
+
+    Ok(Json(portfolio))
+}
+
+async fn publish(
+    State(state): State<Arc<SharedState>>,
+    session: Session,
+    origin: RequestOrigin,
+    Path(slug): Path<String>,
+) -> Result<Json<Portfolio>, ApiError> {
+    session.require_scope(ApiTokenScope::PublishPortfolios)?;
+    let mut conn = state.db_pool.begin().await.map_err(|_| ApiError::DbTransactionBegin)?;
+
+    let portfolio =
+        services::portfolio::publish_portfolio(&mut *conn, &origin, &slug, session.user_id)
+            .await
+            .map_err(|err| {
+                tracing::error!("Publishing the {slug} portfolio failed: {err:?}");
+                ApiError::DbError
+            })?
+            .ok_or(ApiError::NoSuchSlug)?;
 
     conn.commit().await.map_err(|_| ApiError::DbTransactionCommit)?;
 
     Ok(Json(portfolio))
 }
+
+#[derive(serde::Deserialize)]
+struct SetPasswordRequest {
+    /// The password readers need to see the published portfolio, or None to
+    /// make it visible to everyone again.
+    password: Option<String>,
+}
+async fn set_password(
+    State(state): State<Arc<SharedState>>,
+    session: Session,
+    origin: RequestOrigin,
+    Path(slug): Path<String>,
+    Json(SetPasswordRequest { password }): Json<SetPasswordRequest>,
+) -> Result<(), ApiError> {
+    session.require_scope(ApiTokenScope::PublishPortfolios)?;
+    if password.as_deref() == Some("") {
+        return Err(ApiError::PasswordTooShort);
+    }
+    let mut conn = state.db_pool.begin().await.map_err(|_| ApiError::DbTransactionBegin)?;
+
+    let changed = services::portfolio::passwords::set_password(
+        &mut *conn,
+        &origin,
+        &slug,
+        session.user_id,
+        password.as_deref(),
+    )
+    .await
+    .map_err(|err| {
+        tracing::error!("Setting the password of the {slug} portfolio failed: {err:?}");
+        ApiError::DbError
+    })?;
+    if !changed {
+        return Err(ApiError::NoSuchSlug);
+    }
+
+    conn.commit().await.map_err(|_| ApiError::DbTransactionCommit)?;
+
+    Ok(())
+}
+
+#[derive(serde::Deserialize)]
+struct UnlockRequest {
+    password: String,
+}
+/// Gives a viewer token for a password-protected portfolio, which is passed
+/// as the `viewer_token` query parameter to see the portfolio, the works in
+/// it, and their files.
+async fn unlock(
+    State(state): State<Arc<SharedState>>,
+    ClientIp(ip): ClientIp,
+    Path(slug): Path<String>,
+    Json(UnlockRequest { password }): Json<UnlockRequest>,
+) -> Result<Json<PortfolioViewerToken>, ApiError> {
+    // No portfolio has a slug this long, so there's nothing to unlock or count
+    let Ok(portfolio) = ArrayString::from(&slug).map(SlugString) else {
+        return Err(ApiError::InvalidCredentials);
+    };
+    if let Err(retry_after) = state.unlock_rate_limiter.begin_unlock_attempt(ip, portfolio) {
+        tracing::debug!("Refusing to unlock the {slug} portfolio, locked out for {retry_after:?}.");
+        return Err(ApiError::too_many_requests(retry_after));
+    }
+
+    let mut conn = state.db_pool.begin().await.map_err(|_| ApiError::DbTransactionBegin)?;
+    let unlocked = services::portfolio::passwords::unlock(&mut *conn, &slug, &password).await;
+    let viewer_token = unlocked.map_err(|err| {
+        tracing::error!("Unlocking the {slug} portfolio failed: {err:?}");
+        ApiError::DbError
+    })?;
+    conn.commit().await.map_err(|_| ApiError::DbTransactionCommit)?;
+
+    let Some(viewer_token) = viewer_token else {
+        return Err(ApiError::InvalidCredentials);
+    };
+    state.unlock_rate_limiter.record_unlock_success(ip, portfolio);
+    Ok(Json(viewer_token))
+}
+
+async fn remove(
+    State(state): State<Arc<SharedState>>,
+    session: Session,
+    origin: RequestOrigin,
+    Path(slug): Path<String>,
+) -> Result<(), ApiError> {
+    session.require_scope(ApiTokenScope::PublishPortfolios)?;
+    let mut conn = state.db_pool.begin().await.map_err(|_| ApiError::DbTransactionBegin)?;
+
+    let deleted =
+        services::portfolio::delete_portfolio(&mut *conn, &origin, &slug, session.user_id)
+            .await
+            .map_err(|err| {
+                tracing::error!("Deleting the {slug} portfolio failed: {err:?}");
+                ApiError::DbError
+            })?;
+    if !deleted {
+        return Err(ApiError::NoSuchSlug);
+    }
+
+    conn.commit().await.map_err(|_| ApiError::DbTransactionCommit)?;
+
+    Ok(())
+}
@@ -0,0 +1,104 @@
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use axum::extract::{Path, State};
+use axum::routing::{delete, get};
+use axum::{Json, Router};
+
+use crate::api_errors::ApiError;
+use crate::data::audit::RequestOrigin;
+use crate::data::portfolio::PortfolioPreview;
+use crate::data::user::{ApiTokenScope, Session};
+use crate::request_state::SharedState;
+use crate::services;
+
+pub fn create_router() -> Router<Arc<SharedState>> {
+    Router::new().route("/", get(all).post(create)).route("/:token", delete(revoke))
+}
+
+async fn all(
+    State(state): State<Arc<SharedState>>,
+    session: Session,
+    Path(slug): Path<String>,
+) -> Result<Json<Vec<PortfolioPreview>>, ApiError> {
+    session.require_scope(ApiTokenScope::Read)?;
+    let previews =
+        services::portfolio::previews::get_previews(&state.db_pool, &slug, session.user_id)
+            .await
+            .map_err(|err| {
+                tracing::error!("Getting the previews of {slug} failed: {err:?}");
+                ApiError::DbError
+            })?
+            .ok_or(ApiError::NoSuchSlug)?;
+    Ok(Json(previews))
+}
+
+#[derive(serde::Deserialize)]
+struct CreatePreviewRequest {
+    /// In seconds since the unix epoch, None if the preview shouldn't expire.
+    expires_at: Option<i64>,
+}
+
+async fn create(
+    State(state): State<Arc<SharedState>>,
+    session: Session,
+    origin: RequestOrigin,
+    Path(slug): Path<String>,
+    Json(CreatePreviewRequest { expires_at }): Json<CreatePreviewRequest>,
+) -> Result<Json<PortfolioPreview>, ApiError> {
+    session.require_scope(ApiTokenScope::PublishPortfolios)?;
+    let current_time =
+        SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as i64;
+    if expires_at.is_some_and(|expires_at| expires_at <= current_time) {
+        return Err(ApiError::PreviewAlreadyExpired);
+    }
+    let mut conn = state.db_pool.begin().await.map_err(|_| ApiError::DbTransactionBegin)?;
+
+    let preview = services::portfolio::previews::create_preview(
+        &mut *conn,
+        &origin,
+        &slug,
+        session.user_id,
+        expires_at,
+    )
+    .await
+    .map_err(|err| {
+        tracing::error!("Creating a preview of {slug} failed: {err:?}");
+        ApiError::DbError
+    })?
+    .ok_or(ApiError::NoSuchSlug)?;
+
+    conn.commit().await.map_err(|_| ApiError::DbTransactionCommit)?;
+
+    Ok(Json(preview))
+}
+
+async fn revoke(
+    State(state): State<Arc<SharedState>>,
+    session: Session,
+    origin: RequestOrigin,
+    Path((slug, token)): Path<(String, String)>,
+) -> Result<(), ApiError> {
+    session.require_scope(ApiTokenScope::PublishPortfolios)?;
+    let mut conn = state.db_pool.begin().await.map_err(|_| ApiError::DbTransactionBegin)?;
+
+    let revoked = services::portfolio::previews::revoke_preview(
+        &mut *conn,
+        &origin,
+        &slug,
+        session.user_id,
+        &token,
+    )
+    .await
+    .map_err(|err| {
+        tracing::error!("Revoking a preview of {slug} failed: {err:?}");
+        ApiError::DbError
+    })?;
+    if !revoked {
+        return Err(ApiError::NoSuchPreview);
+    }
+
+    conn.commit().await.map_err(|_| ApiError::DbTransactionCommit)?;
+
+    Ok(())
+}
@@ -0,0 +1,87 @@
+use std::sync::Arc;
+
+use axum::extract::{Path, State};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+
+use crate::api_errors::ApiError;
+use crate::data::audit::RequestOrigin;
+use crate::data::trash::Trash;
+use crate::data::user::{ApiTokenScope, Session};
+use crate::request_state::SharedState;
+use crate::services;
+
+pub fn create_router() -> Router<Arc<SharedState>> {
+    Router::new()
+        .route("/", get(all))
+        .route("/work/:slug/restore", post(restore_work))
+        .route("/portfolio/:slug/restore", post(restore_portfolio))
+}
+
+async fn all(
+    State(state): State<Arc<SharedState>>,
+    session: Session,
+) -> Result<Json<Trash>, ApiError> {
+    session.require_scope(ApiTokenScope::Read)?;
+    let works = services::work::get_deleted_works(&state.db_pool, session.user_id).await.map_err(
+        |err| {
+            tracing::error!("Getting deleted works failed: {err:?}");
+            ApiError::DbError
+        },
+    )?;
+    let portfolios = services::portfolio::get_deleted_portfolios(&state.db_pool, session.user_id)
+        .await
+        .map_err(|err| {
+            tracing::error!("Getting deleted portfolios failed: {err:?}");
+            ApiError::DbError
+        })?;
+    Ok(Json(Trash { works, portfolios }))
+}
+
+async fn restore_work(
+    State(state): State<Arc<SharedState>>,
+    session: Session,
+    origin: RequestOrigin,
+    Path(slug): Path<String>,
+) -> Result<(), ApiError> {
+    session.require_scope(ApiTokenScope::EditWorks)?;
+    let mut conn = state.db_pool.begin().await.map_err(|_| ApiError::DbTransactionBegin)?;
+
+    let restored = services::work::restore_work(&mut *conn, &origin, &slug, session.user_id)
+        .await
+        .map_err(|err| {
+            tracing::error!("Restoring the {slug} work failed: {err:?}");
+            ApiError::DbError
+        })?;
+    if !restored {
+        return Err(ApiError::NoSuchSlug);
+    }
+
+    conn.commit().await.map_err(|_| ApiError::DbTransactionCommit)?;
+
+    Ok(())
+}
+
+async fn restore_portfolio(
+    State(state): State<Arc<SharedState>>,
+    session: Session,
+    origin: RequestOrigin,
+    Path(slug): Path<String>,
+) -> Result<(), ApiError> {
+    session.require_scope(ApiTokenScope::PublishPortfolios)?;
+    let mut conn = state.db_pool.begin().await.map_err(|_| ApiError::DbTransactionBegin)?;
+
+    let result =
+        services::portfolio::restore_portfolio(&mut *conn, &origin, &slug, session.user_id).await;
+    let restored = result.map_err(|err| {
+        tracing::error!("Restoring the {slug} portfolio failed: {err:?}");
+        ApiError::DbError
+    })?;
+    if !restored {
+        return Err(ApiError::NoSuchSlug);
+    }
+
+    conn.commit().await.map_err(|_| ApiError::DbTransactionCommit)?;
+
+    Ok(())
+}
@@ -1,20 +1,42 @@
 use std::sync::Arc;
 
-use axum::extract::State;
+use axum::extract::{Query, State};
 use axum::routing::{get, post};
 use axum::{Json, Router};
 
 use crate::api_errors::ApiError;
 use crate::array_string_types::{UsernameString, UuidString};
-use crate::data::user::Session;
+use crate::config::{self, RegistrationMode, RegistrationResponse};
+use crate::data::audit::{AuditEvent, AuditEventFilter, RequestOrigin};
+use crate::data::user::{ApiTokenScope, LoginOutcome, Session, UserInfo, UsernameChangeOutcome};
+use crate::request_state::ClientIp;
 use crate::routes::SharedState;
 use crate::services;
 
+mod export;
+mod invites;
+mod oidc;
+mod profile;
+mod tokens;
+mod totp;
+mod transfers;
+
 pub fn create_router() -> Router<Arc<SharedState>> {
     Router::new()
         .route("/login", post(login))
-        .route("/register", post(register))
+        .route("/register", get(registration_info).post(register))
         .route("/me", get(me))
+        .route("/me/audit", get(audit_events))
+        .route("/me/export", get(export::export))
+        .route("/me/profile", get(profile::mine).put(profile::edit))
+        .route("/me/username", post(change_username))
+        .nest("/me/transfers", transfers::create_router())
+        .route("/:username", get(profile::by_username))
+        .route("/password-reset", post(password_reset))
+        .nest("/totp", totp::create_router())
+        .nest("/tokens", tokens::create_router())
+        .nest("/invites", invites::create_router())
+        .nest("/oidc", oidc::create_router())
 }
 
 #[derive(Clone, serde::Deserialize)]
@@ -28,47 +50,112 @@ struct AuthRequest {
     #[serde(flatten)]
     creds: Credentials,
 }
-#[derive(serde::Serialize)]
+#[derive(Default, serde::Serialize)]
 struct AuthResponse {
-    session_id: UuidString,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    session_id: Option<UuidString>,
+    /// Returned instead of a session id if the user has two-factor
+    /// authentication enabled. Passed to `/user/totp/login` along with the code
+    /// to get the actual session.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pending_login_id: Option<UuidString>,
 }
 async fn login(
     State(state): State<Arc<SharedState>>,
+    client_ip: ClientIp,
+    origin: RequestOrigin,
     Json(req): Json<AuthRequest>,
 ) -> Result<Json<AuthResponse>, ApiError> {
     let AuthRequest { creds: Credentials { username, password } } = req;
-    tracing::trace!("Attempting to log in user {username}.");
+    let ClientIp(ip) = client_ip;
+    if let Err(retry_after) = state.login_rate_limiter.begin_attempt(ip, Some(username)) {
+        tracing::debug!("Refusing to log in user {username}, locked out for {retry_after:?}.");
+        return Err(ApiError::too_many_requests(retry_after));
+    }
+    let outcome = log_in(&state, &origin, username, &password).await?;
+    if outcome.is_some() {
+        state.login_rate_limiter.record_success(ip, Some(username));
+    }
+    auth_response(outcome)
+}
 
+/// Checks the password and creates the session or pending login, without
+/// counting the attempt in the rate limiter.
+async fn log_in(
+    state: &SharedState,
+    origin: &RequestOrigin,
+    username: UsernameString,
+    password: &str,
+) -> Result<Option<LoginOutcome>, ApiError> {
+    tracing::trace!("Attempting to log in user {username}.");
     let mut conn = state.db_pool.begin().await.map_err(|_| ApiError::DbTransactionBegin)?;
-    let session = services::user::login(&mut *conn, username, &password).await.map_err(|err| {
-        tracing::error!("Login failed: {err:?}");
-        ApiError::DbError
-    })?;
+    let outcome =
+        services::user::login(&mut *conn, origin, username, password).await.map_err(|err| {
+            tracing::error!("Login failed: {err:?}");
+            ApiError::DbError
+        })?;
     conn.commit().await.map_err(|_| ApiError::DbTransactionCommit)?;
+    Ok(outcome)
+}
 
-    if let Some(session) = session {
-        tracing::debug!("session: {:?}", session);
-        Ok(Json(AuthResponse { session_id: session.uuid }))
-    } else {
-        Err(ApiError::InvalidCredentials)
+fn auth_response(outcome: Option<LoginOutcome>) -> Result<Json<AuthResponse>, ApiError> {
+    match outcome {
+        Some(LoginOutcome::Session(session)) => {
+            tracing::debug!("session: {:?}", session);
+            Ok(Json(AuthResponse { session_id: Some(session.uuid), ..Default::default() }))
+        }
+        Some(LoginOutcome::TotpRequired(pending_login)) => {
+            tracing::debug!("pending login: {:?}", pending_login);
+            Ok(Json(AuthResponse {
+                pending_login_id: Some(pending_login.uuid),
+                ..Default::default()
+            }))
+        }
+        Some(LoginOutcome::AccountDisabled) => Err(ApiError::AccountDisabled),
+        None => Err(ApiError::InvalidCredentials),
     }
 }
 
+#[derive(serde::Serialize)]
+struct RegistrationInfo {
+    mode: RegistrationMode,
+}
+async fn registration_info() -> Json<RegistrationInfo> {
+    Json(RegistrationInfo { mode: config::registration_mode() })
+}
+
 #[derive(serde::Deserialize)]
 struct RegisterRequest {
     #[serde(flatten)]
     creds: Credentials,
     password2: String,
+    /// Required if the server is in invite-only mode, ignored otherwise.
+    #[serde(default)]
+    invite_code: Option<String>,
 }
 async fn register(
     State(state): State<Arc<SharedState>>,
+    client_ip: ClientIp,
+    origin: RequestOrigin,
     Json(req): Json<RegisterRequest>,
 ) -> Result<Json<AuthResponse>, ApiError> {
-    let RegisterRequest { creds, password2 } = req;
-    let Credentials { username, password } = creds.clone();
+    let RegisterRequest { creds, password2, invite_code } = req;
+    let Credentials { username, password } = creds;
+    let registration_mode = config::registration_mode();
+    if registration_mode == RegistrationMode::Closed {
+        return Err(ApiError::RegistrationClosed);
+    }
+    let invite_code = match (registration_mode, invite_code) {
+        (RegistrationMode::InviteOnly, Some(code)) => Some(code),
+        (RegistrationMode::InviteOnly, None) => return Err(ApiError::InvalidInviteCode),
+        _ => None,
+    };
     if username.0.len() < 3 {
         return Err(ApiError::UsernameTooShort);
     }
+    if !services::user::is_valid_username(&username.0) {
+        return Err(ApiError::InvalidUsername);
+    }
     if password.len() < 10 {
         return Err(ApiError::PasswordTooShort);
     }
@@ -76,34 +163,200 @@ async fn register(
         return Err(ApiError::PasswordsDontMatch);
     }
 
+    // Every registration counts as a failed attempt for the ip address, since
+    // they can be used for checking which usernames exist, and each one
+    // derives a password key
+    let ClientIp(ip) = client_ip;
+    if let Err(retry_after) = state.login_rate_limiter.begin_attempt(ip, None) {
+        tracing::debug!("Refusing to register user {username}, locked out for {retry_after:?}.");
+        return Err(ApiError::too_many_requests(retry_after));
+    }
+
+    let registration_response = config::registration_response();
+
     tracing::trace!("Registering a new user {username}.");
     {
         let mut conn = state.db_pool.begin().await.map_err(|_| ApiError::DbTransactionBegin)?;
 
+        if let Some(code) = &invite_code {
+            let claimed =
+                services::user::invites::claim_invite(&mut *conn, code).await.map_err(|err| {
+                    tracing::error!("Claiming invite failed: {err:?}");
+                    ApiError::DbError
+                })?;
+            if !claimed {
+                return Err(ApiError::InvalidInviteCode);
+            }
+        }
+
         let username_taken =
             services::user::is_username_taken(&mut *conn, username).await.map_err(|err| {
                 tracing::error!("Username availability check failed: {err:?}");
                 ApiError::DbError
             })?;
         if username_taken {
+            if registration_response == RegistrationResponse::Opaque {
+                // Spend the same time as creating the user would, and respond like it was created
+                services::user::dummy_password_derivation(&password).await.map_err(|err| {
+                    tracing::error!("Dummy password derivation failed: {err:?}");
+                    ApiError::DbError
+                })?;
+                return Ok(Json(AuthResponse::default()));
+            }
             return Err(ApiError::UsernameTaken);
         }
 
-        services::user::create_user(&mut *conn, username, &password).await.map_err(|err| {
+        let user_id = services::user::create_user(&mut *conn, &origin, username, &password)
+            .await
+            .map_err(|err| {
             tracing::error!("User creation failed: {err:?}");
             ApiError::DbError
         })?;
 
+        if let Some(code) = &invite_code {
+            services::user::invites::set_invite_user(&mut *conn, code, user_id).await.map_err(
+                |err| {
+                    tracing::error!("Marking the invite as used by {username} failed: {err:?}");
+                    ApiError::DbError
+                },
+            )?;
+        }
+
         conn.commit().await.map_err(|_| ApiError::DbTransactionCommit)?;
     }
 
-    login(State(state), Json(AuthRequest { creds })).await
+    match registration_response {
+        // Already counted as an attempt above
+        RegistrationResponse::Login => {
+            auth_response(log_in(&state, &origin, username, &password).await?)
+        }
+        RegistrationResponse::Opaque => Ok(Json(AuthResponse::default())),
+    }
 }
 
-#[derive(serde::Serialize)]
-struct MyInfo {
-    session_id: UuidString,
+#[derive(serde::Deserialize)]
+struct ChangeUsernameRequest {
+    username: UsernameString,
+    /// The current password, which is needed for deriving the new password key.
+    /// Can be left empty if the user logs in via OpenID Connect and doesn't
+    /// have a password.
+    #[serde(default)]
+    password: String,
+}
+async fn change_username(
+    State(state): State<Arc<SharedState>>,
+    session: Session,
+    client_ip: ClientIp,
+    origin: RequestOrigin,
+    Json(req): Json<ChangeUsernameRequest>,
+) -> Result<(), ApiError> {
+    session.require_login_session()?;
+    let ChangeUsernameRequest { username, password } = req;
+    let ClientIp(ip) = client_ip;
+    if username.0.len() < 3 {
+        return Err(ApiError::UsernameTooShort);
+    }
+    if !services::user::is_valid_username(&username.0) {
+        return Err(ApiError::InvalidUsername);
+    }
+    if let Err(retry_after) = state.login_rate_limiter.begin_attempt(ip, None) {
+        return Err(ApiError::too_many_requests(retry_after));
+    }
+
+    let mut conn = state.db_pool.begin().await.map_err(|_| ApiError::DbTransactionBegin)?;
+
+    let outcome =
+        services::user::change_username(&mut *conn, &origin, session.user_id, username, &password)
+            .await
+            .map_err(|err| {
+                tracing::error!("Changing username to {username} failed: {err:?}");
+                ApiError::DbError
+            })?;
+    if outcome != UsernameChangeOutcome::WrongPassword {
+        state.login_rate_limiter.record_success(ip, None);
+    }
+    match outcome {
+        UsernameChangeOutcome::Changed => {}
+        UsernameChangeOutcome::WrongPassword => return Err(ApiError::InvalidCredentials),
+        UsernameChangeOutcome::UsernameTaken => return Err(ApiError::UsernameTaken),
+    }
+
+    conn.commit().await.map_err(|_| ApiError::DbTransactionCommit)?;
+
+    Ok(())
+}
+
+#[derive(serde::Deserialize)]
+struct PasswordResetRequest {
+    token: String,
+    password: String,
+    password2: String,
+}
+async fn password_reset(
+    State(state): State<Arc<SharedState>>,
+    origin: RequestOrigin,
+    Json(req): Json<PasswordResetRequest>,
+) -> Result<(), ApiError> {
+    let PasswordResetRequest { token, password, password2 } = req;
+    if password.len() < 10 {
+        return Err(ApiError::PasswordTooShort);
+    }
+    if password != password2 {
+        return Err(ApiError::PasswordsDontMatch);
+    }
+
+    let mut conn = state.db_pool.begin().await.map_err(|_| ApiError::DbTransactionBegin)?;
+
+    let reset =
+        services::user::password_resets::use_password_reset(&mut *conn, &origin, &token, &password)
+            .await
+            .map_err(|err| {
+                tracing::error!("Resetting password failed: {err:?}");
+                ApiError::DbError
+            })?;
+    if !reset {
+        return Err(ApiError::InvalidPasswordReset);
+    }
+
+    conn.commit().await.map_err(|_| ApiError::DbTransactionCommit)?;
+
+    Ok(())
+}
+
+async fn me(
+    State(state): State<Arc<SharedState>>,
+    session: Session,
+) -> Result<Json<UserInfo>, ApiError> {
+    session.require_scope(ApiTokenScope::Read)?;
+    let info = services::user::get_user_info(&state.db_pool, &session).await.map_err(|err| {
+        tracing::error!("Getting info about the logged in user failed: {err:?}");
+        ApiError::DbError
+    })?;
+    Ok(Json(info))
+}
+
+#[derive(serde::Deserialize)]
+struct AuditQuery {
+    before_id: Option<i32>,
+    limit: Option<i64>,
 }
-async fn me(session: Session) -> Json<MyInfo> {
-    Json(MyInfo { session_id: session.uuid })
+/// The logged in user's own audit events, newest first.
+async fn audit_events(
+    State(state): State<Arc<SharedState>>,
+    session: Session,
+    Query(query): Query<AuditQuery>,
+) -> Result<Json<Vec<AuditEvent>>, ApiError> {
+    session.require_scope(ApiTokenScope::Read)?;
+    let filter = AuditEventFilter {
+        actor_user_id: Some(session.user_id),
+        before_id: query.before_id,
+        ..Default::default()
+    };
+    let limit = query.limit.unwrap_or(100).clamp(1, 1000);
+    let events =
+        services::audit::get_events(&state.db_pool, &filter, limit).await.map_err(|err| {
+            tracing::error!("Getting the logged in user's audit events failed: {err:?}");
+            ApiError::DbError
+        })?;
+    Ok(Json(events))
 }
@@ -0,0 +1,173 @@
+use std::sync::Arc;
+
+use async_zip::tokio::write::ZipFileWriter;
+use async_zip::{Compression, ZipEntryBuilder};
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::http::Response;
+use futures_lite::AsyncWriteExt;
+use http_body::Frame;
+use http_body_util::StreamBody;
+use sqlx::AnyPool;
+use tokio::io::{AsyncReadExt, DuplexStream};
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::{Instrument, Span};
+
+use crate::api_errors::ApiError;
+use crate::array_string_types::UsernameString;
+use crate::data::audit::AuditEventFilter;
+use crate::data::user::Session;
+use crate::data::work::Work;
+use crate::request_state::SharedState;
+use crate::services;
+
+/// Errors end the response body, so that the client sees the download fail
+/// instead of getting a truncated zip file.
+type Data = Result<Frame<Bytes>, anyhow::Error>;
+type ResponseBody = StreamBody<ReceiverStream<Data>>;
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Streams a zip file with everything stored about the user: `user.json`,
+/// `sessions.json`, `works.json`, `portfolios.json`, `audit.json`, and the
+/// attachments' files under `files/<work slug>/`.
+pub async fn export(
+    State(state): State<Arc<SharedState>>,
+    session: Session,
+) -> Result<Response<ResponseBody>, ApiError> {
+    session.require_login_session()?;
+    let user_id = session.user_id;
+
+    // The json files are collected before responding, so that errors can still
+    // be returned as errors, instead of as broken zip files
+    let export = collect_export(&state.db_pool, user_id).await.map_err(|err| {
+        tracing::error!("Collecting the data export failed: {err:?}");
+        ApiError::DbError
+    })?;
+    let Export { username, json_files, works } = export;
+
+    // The zip is written into one end of the pipe, and the other end is
+    // forwarded to the response body as it fills up
+    let (zip_writer, mut zip_reader) = tokio::io::duplex(CHUNK_SIZE);
+    let logging_span = Span::current();
+    let zip_task = tokio::spawn(
+        async move { write_zip(&state.db_pool, zip_writer, json_files, &works).await }
+            .instrument(logging_span.clone()),
+    );
+
+    let (sender, receiver) = tokio::sync::mpsc::channel::<Data>(1);
+    tokio::spawn(
+        async move {
+            loop {
+                let mut buffer = vec![0; CHUNK_SIZE];
+                let len = match zip_reader.read(&mut buffer).await {
+                    // The writer closes the pipe when it's done, whether or
+                    // not it succeeded
+                    Ok(0) => {
+                        let result = match zip_task.await {
+                            Ok(result) => result,
+                            Err(err) => Err(err.into()),
+                        };
+                        if let Err(err) = result {
+                            tracing::error!("Writing the data export failed mid-stream: {err:?}");
+                            let _ = sender.send(Err(err)).await;
+                        }
+                        break;
+                    }
+                    Ok(len) => len,
+                    Err(err) => {
+                        tracing::error!("Reading the data export from the pipe failed: {err:?}");
+                        let _ = sender.send(Err(err.into())).await;
+                        break;
+                    }
+                };
+                buffer.truncate(len);
+                if let Err(err) = sender.send(Ok(Frame::data(Bytes::from(buffer)))).await {
+                    tracing::debug!(
+                        "Error sending data export, client probably disconnected: {:?}",
+                        err
+                    );
+                    break;
+                }
+            }
+        }
+        .instrument(logging_span),
+    );
+
+    let filename_ascii = username
+        .0
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect::<String>();
+    Ok(Response::builder()
+        .header("Content-Type", "application/zip")
+        .header("Content-Disposition", format!("attachment; filename=\"{filename_ascii}.zip\""))
+        .body(StreamBody::new(ReceiverStream::new(receiver)))
+        .unwrap())
+}
+
+struct Export {
+    username: UsernameString,
+    json_files: Vec<(&'static str, Vec<u8>)>,
+    works: Vec<Work>,
+}
+async fn collect_export(db_pool: &AnyPool, user_id: i32) -> Result<Export, anyhow::Error> {
+    let user = services::user::export::get_user(db_pool, user_id).await?;
+    let sessions = services::user::export::get_sessions(db_pool, user_id).await?;
+    let works = services::user::export::get_works(db_pool, user_id).await?;
+    let portfolios = services::user::export::get_portfolios(db_pool, user_id).await?;
+    let filter = AuditEventFilter { actor_user_id: Some(user_id), ..Default::default() };
+    let audit_events = services::audit::get_events(db_pool, &filter, i64::MAX).await?;
+
+    let json_files = vec![
+        ("user.json", serde_json::to_vec_pretty(&user)?),
+        ("sessions.json", serde_json::to_vec_pretty(&sessions)?),
+        ("works.json", serde_json::to_vec_pretty(&works)?),
+        ("portfolios.json", serde_json::to_vec_pretty(&portfolios)?),
+        ("audit.json", serde_json::to_vec_pretty(&audit_events)?),
+    ];
+    Ok(Export { username: user.username, json_files, works })
+}
+
+async fn write_zip(
+    db_pool: &AnyPool,
+    writer: DuplexStream,
+    json_files: Vec<(&str, Vec<u8>)>,
+    works: &[Work],
+) -> Result<(), anyhow::Error> {
+    let mut zip = ZipFileWriter::with_tokio(writer);
+
+    for (name, json) in json_files {
+        zip.write_entry_whole(ZipEntryBuilder::new(name.into(), Compression::Deflate), &json)
+            .await?;
+    }
+
+    for work in works {
+        for attachment in &work.attachments {
+            let filename = attachment.filename.replace(['/', '\\'], "_");
+            let path = format!("files/{}/{}-{filename}", work.row.slug, attachment.id);
+            // The files are mostly images and binaries, which don't compress much further
+            let entry = ZipEntryBuilder::new(path.into(), Compression::Stored);
+            let mut entry_writer = zip.write_entry_stream(entry).await?;
+
+            if let Some(uuid) = &attachment.big_file_uuid {
+                let mut next_uuid = Some(*uuid);
+                while let Some(uuid) = next_uuid {
+                    let file_part = services::work::big_files::get_file_part(db_pool, &uuid.0)
+                        .await?
+                        .ok_or_else(|| anyhow::anyhow!("file part {uuid} is missing"))?;
+                    entry_writer.write_all(&file_part.bytes).await?;
+                    next_uuid = file_part.next_uuid;
+                }
+            } else {
+                let bytes = data_encoding::BASE64.decode(attachment.bytes_base64.0.as_bytes())?;
+                entry_writer.write_all(&bytes).await?;
+            }
+
+            entry_writer.close().await?;
+        }
+    }
+
+    zip.close().await?;
+    Ok(())
+}
@@ -0,0 +1,77 @@
+use std::sync::Arc;
+
+use axum::extract::{Path, State};
+use axum::routing::{delete, get};
+use axum::{Json, Router};
+
+use crate::api_errors::ApiError;
+use crate::data::user::{Invite, Session};
+use crate::request_state::SharedState;
+use crate::services;
+
+pub fn create_router() -> Router<Arc<SharedState>> {
+    Router::new().route("/", get(all).post(create)).route("/:code", delete(revoke))
+}
+
+async fn all(
+    State(state): State<Arc<SharedState>>,
+    session: Session,
+) -> Result<Json<Vec<Invite>>, ApiError> {
+    session.require_login_session()?;
+    let invites = services::user::invites::get_invites(&state.db_pool, session.user_id)
+        .await
+        .map_err(|err| {
+            tracing::error!("Getting all invites for the logged in user failed: {err:?}");
+            ApiError::DbError
+        })?;
+    Ok(Json(invites))
+}
+
+#[derive(serde::Deserialize)]
+struct CreateInviteArgs {
+    /// In seconds since the unix epoch. If None, the invite never expires.
+    expires_at: Option<i64>,
+}
+async fn create(
+    State(state): State<Arc<SharedState>>,
+    session: Session,
+    Json(args): Json<CreateInviteArgs>,
+) -> Result<Json<Invite>, ApiError> {
+    session.require_login_session()?;
+    let mut conn = state.db_pool.begin().await.map_err(|_| ApiError::DbTransactionBegin)?;
+
+    let invite =
+        services::user::invites::create_invite(&mut *conn, session.user_id, args.expires_at)
+            .await
+            .map_err(|err| {
+                tracing::error!("Creating a new invite failed: {err:?}");
+                ApiError::DbError
+            })?;
+
+    conn.commit().await.map_err(|_| ApiError::DbTransactionCommit)?;
+
+    Ok(Json(invite))
+}
+
+async fn revoke(
+    State(state): State<Arc<SharedState>>,
+    session: Session,
+    Path(code): Path<String>,
+) -> Result<(), ApiError> {
+    session.require_login_session()?;
+    let mut conn = state.db_pool.begin().await.map_err(|_| ApiError::DbTransactionBegin)?;
+
+    let revoked = services::user::invites::revoke_invite(&mut *conn, session.user_id, &code)
+        .await
+        .map_err(|err| {
+            tracing::error!("Revoking invite {code} failed: {err:?}");
+            ApiError::DbError
+        })?;
+    if !revoked {
+        return Err(ApiError::NoSuchInvite);
+    }
+
+    conn.commit().await.map_err(|_| ApiError::DbTransactionCommit)?;
+
+    Ok(())
+}
@@ -0,0 +1,321 @@
+use std::sync::Arc;
+
+use arrayvec::ArrayString;
+use axum::extract::State;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+
+use super::AuthResponse;
+use crate::api_errors::ApiError;
+use crate::array_string_types::UsernameString;
+use crate::config::{self, RegistrationMode};
+use crate::data::audit::RequestOrigin;
+use crate::data::user::{LoginOutcome, OidcIdentity, Session};
+use crate::request_state::SharedState;
+use crate::services;
+use crate::services::user::oidc::VerifiedIdentity;
+
+pub fn create_router() -> Router<Arc<SharedState>> {
+    Router::new()
+        .route("/", get(info))
+        .route("/authorize", post(authorize))
+        .route("/link", post(link))
+        .route("/callback", post(callback))
+        .route("/identities", get(identities).delete(unlink))
+}
+
+#[derive(serde::Serialize)]
+struct OidcInfo {
+    enabled: bool,
+}
+async fn info() -> Json<OidcInfo> {
+    Json(OidcInfo { enabled: config::oidc().is_some() })
+}
+
+#[derive(serde::Serialize)]
+struct AuthorizationUrl {
+    /// The identity provider's login page, where the user should be sent to.
+    /// Afterwards they are sent back to the configured redirect url, with the
+    /// `code` and `state` query parameters meant for `/user/oidc/callback`.
+    authorization_url: String,
+}
+
+/// Starts logging in (or registering) with the identity provider.
+async fn authorize(
+    State(state): State<Arc<SharedState>>,
+) -> Result<Json<AuthorizationUrl>, ApiError> {
+    begin(&state, None).await
+}
+
+/// Starts linking an identity at the identity provider to the logged in user,
+/// so that they can log in with it afterwards.
+async fn link(
+    State(state): State<Arc<SharedState>>,
+    session: Session,
+) -> Result<Json<AuthorizationUrl>, ApiError> {
+    session.require_login_session()?;
+    begin(&state, Some(session.user_id)).await
+}
+
+async fn begin(
+    state: &SharedState,
+    link_user_id: Option<i32>,
+) -> Result<Json<AuthorizationUrl>, ApiError> {
+    let oidc = config::oidc().ok_or(ApiError::OidcDisabled)?;
+
+    let mut conn = state.db_pool.begin().await.map_err(|_| ApiError::DbTransactionBegin)?;
+    let login =
+        services::user::oidc::create_login(&mut *conn, link_user_id).await.map_err(|err| {
+            tracing::error!("Creating an oidc login failed: {err:?}");
+            ApiError::DbError
+        })?;
+    conn.commit().await.map_err(|_| ApiError::DbTransactionCommit)?;
+
+    let authorization_url =
+        services::user::oidc::authorization_url(&state.http_client, &oidc, &login).await.map_err(
+            |err| {
+                tracing::error!("Getting the oidc authorization url failed: {err:?}");
+                ApiError::OidcLoginFailed
+            },
+        )?;
+
+    Ok(Json(AuthorizationUrl { authorization_url }))
+}
+
+#[derive(serde::Deserialize)]
+struct CallbackRequest {
+    code: String,
+    state: String,
+    /// The username for the account created if the identity is not linked to
+    /// anyone yet. Defaults to the username suggested by the identity provider.
+    #[serde(default)]
+    username: Option<UsernameString>,
+    /// Required for creating an account if the server is in invite-only mode,
+    /// ignored otherwise.
+    #[serde(default)]
+    invite_code: Option<String>,
+}
+/// Finishes the login started by `/authorize` or `/link`. For links, the
+/// response is empty, otherwise it's like the response to `/user/login`.
+/// Links must be finished with the session of the user who started them.
+async fn callback(
+    State(state): State<Arc<SharedState>>,
+    origin: RequestOrigin,
+    session: Option<Session>,
+    Json(req): Json<CallbackRequest>,
+) -> Result<Json<AuthResponse>, ApiError> {
+    let oidc = config::oidc().ok_or(ApiError::OidcDisabled)?;
+
+    // Taken in a separate transaction, so that the login is used up even if
+    // the rest of the process fails
+    let mut conn = state.db_pool.begin().await.map_err(|_| ApiError::DbTransactionBegin)?;
+    let login = services::user::oidc::take_login(&mut *conn, &req.state).await.map_err(|err| {
+        tracing::error!("Fetching the oidc login failed: {err:?}");
+        ApiError::DbError
+    })?;
+    conn.commit().await.map_err(|_| ApiError::DbTransactionCommit)?;
+    let login = login.ok_or(ApiError::OidcLoginFailed)?;
+
+    // Linking is only completed by the user who started it, so that nobody
+    // can get their identity linked to someone else's account by sending
+    // them the callback url
+    if let Some(user_id) = login.link_user_id {
+        match session {
+            Some(session) if session.user_id == user_id => session.require_login_session()?,
+            _ => return Err(ApiError::Forbidden),
+        }
+    }
+
+    let identity =
+        services::user::oidc::exchange_code(&state.http_client, &oidc, &login, &req.code)
+            .await
+            .map_err(|err| {
+                tracing::warn!("Exchanging the oidc authorization code failed: {err:?}");
+                ApiError::OidcLoginFailed
+            })?;
+
+    let mut conn = state.db_pool.begin().await.map_err(|_| ApiError::DbTransactionBegin)?;
+
+    if let Some(user_id) = login.link_user_id {
+        let linked = services::user::oidc::link_identity(&mut *conn, &origin, user_id, &identity)
+            .await
+            .map_err(|err| {
+                tracing::error!("Linking oidc identity {identity:?} failed: {err:?}");
+                ApiError::DbError
+            })?;
+        if !linked {
+            return Err(ApiError::OidcIdentityInUse);
+        }
+        conn.commit().await.map_err(|_| ApiError::DbTransactionCommit)?;
+        return Ok(Json(AuthResponse::default()));
+    }
+
+    let mut outcome = oidc_login(&mut conn, &origin, &identity).await?;
+    if outcome.is_none() {
+        register(&mut conn, &origin, &identity, req.username, req.invite_code).await?;
+        outcome = oidc_login(&mut conn, &origin, &identity).await?;
+    }
+
+    conn.commit().await.map_err(|_| ApiError::DbTransactionCommit)?;
+
+    match outcome {
+        Some(LoginOutcome::Session(session)) => {
+            tracing::debug!("session: {:?}", session);
+            Ok(Json(AuthResponse { session_id: Some(session.uuid), ..Default::default() }))
+        }
+        Some(LoginOutcome::TotpRequired(pending_login)) => {
+            tracing::debug!("pending login: {:?}", pending_login);
+            Ok(Json(AuthResponse {
+                pending_login_id: Some(pending_login.uuid),
+                ..Default::default()
+            }))
+        }
+        Some(LoginOutcome::AccountDisabled) => Err(ApiError::AccountDisabled),
+        None => Err(ApiError::OidcLoginFailed),
+    }
+}
+
+async fn oidc_login(
+    conn: &mut sqlx::Transaction<'_, sqlx::Any>,
+    origin: &RequestOrigin,
+    identity: &VerifiedIdentity,
+) -> Result<Option<LoginOutcome>, ApiError> {
+    services::user::oidc::login(&mut **conn, origin, identity).await.map_err(|err| {
+        tracing::error!("Oidc login failed: {err:?}");
+        ApiError::DbError
+    })
+}
+
+/// Creates a new passwordless user for the identity, following the same rules
+/// as the regular registration.
+async fn register(
+    conn: &mut sqlx::Transaction<'_, sqlx::Any>,
+    origin: &RequestOrigin,
+    identity: &VerifiedIdentity,
+    username: Option<UsernameString>,
+    invite_code: Option<String>,
+) -> Result<(), ApiError> {
+    let invite_code = match (config::registration_mode(), invite_code) {
+        (RegistrationMode::Closed, _) => return Err(ApiError::RegistrationClosed),
+        (RegistrationMode::InviteOnly, Some(code)) => Some(code),
+        (RegistrationMode::InviteOnly, None) => return Err(ApiError::InvalidInviteCode),
+        (RegistrationMode::Open, _) => None,
+    };
+    let suggested_username = identity.preferred_username.as_deref();
+    let suggested_username = suggested_username.and_then(|name| ArrayString::from(name).ok());
+    let username = username
+        .or(suggested_username.map(UsernameString))
+        .ok_or(ApiError::OidcUsernameRequired)?;
+    if username.0.len() < 3 {
+        return Err(ApiError::UsernameTooShort);
+    }
+    if !services::user::is_valid_username(&username.0) {
+        return Err(ApiError::InvalidUsername);
+    }
+
+    if let Some(code) = &invite_code {
+        let claimed =
+            services::user::invites::claim_invite(&mut **conn, code).await.map_err(|err| {
+                tracing::error!("Claiming invite failed: {err:?}");
+                ApiError::DbError
+            })?;
+        if !claimed {
+            return Err(ApiError::InvalidInviteCode);
+        }
+    }
+
+    let username_taken =
+        services::user::is_username_taken(&mut **conn, username).await.map_err(|err| {
+            tracing::error!("Username availability check failed: {err:?}");
+            ApiError::DbError
+        })?;
+    if username_taken {
+        return Err(ApiError::UsernameTaken);
+    }
+
+    tracing::trace!("Registering a new user {username} for oidc identity {identity:?}.");
+    let user_id = services::user::create_user_without_password(&mut **conn, origin, username)
+        .await
+        .map_err(|err| {
+            tracing::error!("Passwordless user creation failed: {err:?}");
+            ApiError::DbError
+        })?;
+    services::user::oidc::link_identity(&mut **conn, origin, user_id, identity).await.map_err(
+        |err| {
+            tracing::error!("Linking oidc identity {identity:?} failed: {err:?}");
+            ApiError::DbError
+        },
+    )?;
+
+    if let Some(code) = &invite_code {
+        services::user::invites::set_invite_user(&mut **conn, code, user_id).await.map_err(
+            |err| {
+                tracing::error!("Marking the invite as used by {username} failed: {err:?}");
+                ApiError::DbError
+            },
+        )?;
+    }
+
+    Ok(())
+}
+
+async fn identities(
+    State(state): State<Arc<SharedState>>,
+    session: Session,
+) -> Result<Json<Vec<OidcIdentity>>, ApiError> {
+    session.require_login_session()?;
+    let identities = services::user::oidc::get_identities(&state.db_pool, session.user_id)
+        .await
+        .map_err(|err| {
+            tracing::error!("Getting oidc identities for the logged in user failed: {err:?}");
+            ApiError::DbError
+        })?;
+    Ok(Json(identities))
+}
+
+#[derive(serde::Deserialize)]
+struct UnlinkRequest {
+    issuer: String,
+    subject: String,
+}
+async fn unlink(
+    State(state): State<Arc<SharedState>>,
+    session: Session,
+    origin: RequestOrigin,
+    Json(req): Json<UnlinkRequest>,
+) -> Result<(), ApiError> {
+    session.require_login_session()?;
+    let UnlinkRequest { issuer, subject } = req;
+    let mut conn = state.db_pool.begin().await.map_err(|_| ApiError::DbTransactionBegin)?;
+
+    let only_login_method =
+        services::user::oidc::is_only_login_method(&mut *conn, session.user_id, &issuer, &subject)
+            .await
+            .map_err(|err| {
+                tracing::error!("Checking the user's other login methods failed: {err:?}");
+                ApiError::DbError
+            })?;
+    if only_login_method {
+        return Err(ApiError::CannotUnlinkLastLogin);
+    }
+
+    let unlinked = services::user::oidc::unlink_identity(
+        &mut *conn,
+        &origin,
+        session.user_id,
+        &issuer,
+        &subject,
+    )
+    .await
+    .map_err(|err| {
+        tracing::error!("Unlinking oidc identity {issuer} {subject} failed: {err:?}");
+        ApiError::DbError
+    })?;
+    if !unlinked {
+        return Err(ApiError::NoSuchOidcIdentity);
+    }
+
+    conn.commit().await.map_err(|_| ApiError::DbTransactionCommit)?;
+
+    Ok(())
+}
@@ -0,0 +1,116 @@
+use std::sync::Arc;
+
+use axum::Json;
+use axum::extract::{Path, State};
+use axum::response::{IntoResponse, Redirect, Response};
+use reqwest::Url;
+
+use crate::api_errors::ApiError;
+use crate::data::user::{ApiTokenScope, Avatar, ProfileLink, Session, UserProfile};
+use crate::request_state::SharedState;
+use crate::services::user::profile;
+use crate::{config, services};
+
+pub async fn mine(
+    State(state): State<Arc<SharedState>>,
+    session: Session,
+) -> Result<Json<UserProfile>, ApiError> {
+    session.require_scope(ApiTokenScope::Read)?;
+    let profile = services::user::profile::get_profile(&state.db_pool, session.user_id)
+        .await
+        .map_err(|err| {
+            tracing::error!("Getting the logged in user's profile failed: {err:?}");
+            ApiError::DbError
+        })?;
+    Ok(Json(profile))
+}
+
+pub async fn edit(
+    State(state): State<Arc<SharedState>>,
+    session: Session,
+    Json(profile): Json<UserProfile>,
+) -> Result<Json<UserProfile>, ApiError> {
+    session.require_login_session()?;
+    if let Some(avatar) = &profile.avatar {
+        if !avatar.content_type.0.starts_with("image/") {
+            return Err(ApiError::InvalidAvatar);
+        }
+        let bytes = data_encoding::BASE64
+            .decode(avatar.bytes_base64.0.as_bytes())
+            .map_err(|_| ApiError::InvalidAvatar)?;
+        if bytes.len() > config::avatar_max_bytes() {
+            return Err(ApiError::AvatarTooLarge);
+        }
+    }
+    if profile.display_name.chars().count() > profile::MAX_DISPLAY_NAME_LEN
+        || profile.bio.chars().count() > profile::MAX_BIO_LEN
+    {
+        return Err(ApiError::ProfileTextTooLong);
+    }
+    if profile.links.len() > profile::MAX_LINKS {
+        return Err(ApiError::TooManyProfileLinks);
+    }
+    for link in &profile.links {
+        if link.title.chars().count() > profile::MAX_LINK_TITLE_LEN {
+            return Err(ApiError::ProfileTextTooLong);
+        }
+        // Only web links, so that e.g. javascript: urls can't end up on the
+        // profile page
+        let url = Url::parse(&link.href).map_err(|_| ApiError::InvalidProfileLink)?;
+        if link.href.len() > profile::MAX_LINK_HREF_LEN || !matches!(url.scheme(), "http" | "https")
+        {
+            return Err(ApiError::InvalidProfileLink);
+        }
+    }
+
+    let mut conn = state.db_pool.begin().await.map_err(|_| ApiError::DbTransactionBegin)?;
+
+    services::user::profile::update_profile(&mut *conn, session.user_id, &profile).await.map_err(
+        |err| {
+            tracing::error!("Updating the logged in user's profile failed: {err:?}");
+            ApiError::DbError
+        },
+    )?;
+
+    conn.commit().await.map_err(|_| ApiError::DbTransactionCommit)?;
+
+    Ok(Json(profile))
+}
+
+/// The profile without the user's settings, like [UserProfile::default_author].
+#[derive(serde::Serialize)]
+pub struct PublicProfile {
+    username: String,
+    display_name: String,
+    bio: String,
+    avatar: Option<Avatar>,
+    links: Vec<ProfileLink>,
+}
+/// Returns the user's public profile. Requests using a username the user has
+/// recently changed from are redirected to the current one.
+pub async fn by_username(
+    State(state): State<Arc<SharedState>>,
+    Path(username): Path<String>,
+) -> Result<Response, ApiError> {
+    let user_id = profile::get_public_user_id(&state.db_pool, &username).await.map_err(|err| {
+        tracing::error!("Getting user id for {username} failed: {err:?}");
+        ApiError::DbError
+    })?;
+    let Some(user_id) = user_id else {
+        let renamed = services::user::get_renamed_username(&state.db_pool, &username)
+            .await
+            .map_err(|err| {
+                tracing::error!("Getting the new username for {username} failed: {err:?}");
+                ApiError::DbError
+            })?
+            .ok_or(ApiError::NoSuchUser)?;
+        // Relative to the current path, so that HTTP_BASE_PATH doesn't matter
+        return Ok(Redirect::temporary(renamed.0.as_str()).into_response());
+    };
+    let UserProfile { display_name, bio, avatar, links, .. } =
+        services::user::profile::get_profile(&state.db_pool, user_id).await.map_err(|err| {
+            tracing::error!("Getting the profile of {username} failed: {err:?}");
+            ApiError::DbError
+        })?;
+    Ok(Json(PublicProfile { username, display_name, bio, avatar, links }).into_response())
+}
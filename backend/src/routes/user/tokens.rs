@@ -0,0 +1,97 @@
+use std::sync::Arc;
+
+use axum::extract::{Path, State};
+use axum::routing::{delete, get};
+use axum::{Json, Router};
+
+use crate::api_errors::ApiError;
+use crate::data::audit::RequestOrigin;
+use crate::data::user::{ApiToken, ApiTokenScopes, Session};
+use crate::request_state::SharedState;
+use crate::services;
+
+pub fn create_router() -> Router<Arc<SharedState>> {
+    Router::new().route("/", get(all).post(create)).route("/:uuid", delete(revoke))
+}
+
+async fn all(
+    State(state): State<Arc<SharedState>>,
+    session: Session,
+) -> Result<Json<Vec<ApiToken>>, ApiError> {
+    session.require_login_session()?;
+    let tokens = services::user::api_tokens::get_tokens(&state.db_pool, session.user_id)
+        .await
+        .map_err(|err| {
+            tracing::error!("Getting all API tokens for the logged in user failed: {err:?}");
+            ApiError::DbError
+        })?;
+    Ok(Json(tokens))
+}
+
+#[derive(serde::Deserialize)]
+struct CreateTokenArgs {
+    name: String,
+    scopes: ApiTokenScopes,
+    /// In seconds since the unix epoch. If None, the token never expires.
+    expires_at: Option<i64>,
+}
+#[derive(serde::Serialize)]
+struct CreatedToken {
+    #[serde(flatten)]
+    details: ApiToken,
+    /// The token to be used as the bearer token. Only returned here, it can't
+    /// be fetched afterwards.
+    token: String,
+}
+async fn create(
+    State(state): State<Arc<SharedState>>,
+    session: Session,
+    origin: RequestOrigin,
+    Json(args): Json<CreateTokenArgs>,
+) -> Result<Json<CreatedToken>, ApiError> {
+    session.require_login_session()?;
+    let mut conn = state.db_pool.begin().await.map_err(|_| ApiError::DbTransactionBegin)?;
+
+    let (details, token) = services::user::api_tokens::create_token(
+        &mut *conn,
+        &origin,
+        session.user_id,
+        &args.name,
+        args.scopes,
+        args.expires_at,
+    )
+    .await
+    .map_err(|err| {
+        tracing::error!("Creating a new API token failed: {err:?}");
+        ApiError::DbError
+    })?;
+
+    conn.commit().await.map_err(|_| ApiError::DbTransactionCommit)?;
+
+    Ok(Json(CreatedToken { details, token }))
+}
+
+async fn revoke(
+    State(state): State<Arc<SharedState>>,
+    session: Session,
+    origin: RequestOrigin,
+    Path(uuid): Path<String>,
+) -> Result<(), ApiError> {
+    session.require_login_session()?;
+    let mut conn = state.db_pool.begin().await.map_err(|_| ApiError::DbTransactionBegin)?;
+
+    let revoked =
+        services::user::api_tokens::revoke_token(&mut *conn, &origin, session.user_id, &uuid)
+            .await
+            .map_err(|err| {
+                tracing::error!("Revoking API token {uuid} failed: {err:?}");
+                ApiError::DbError
+            })?;
+    if !revoked {
+        return Err(ApiError::NoSuchApiToken);
+    }
+
+    conn.commit().await.map_err(|_| ApiError::DbTransactionCommit)?;
+
+    Ok(())
+}
@@ -0,0 +1,135 @@
+use std::sync::Arc;
+
+use axum::extract::State;
+use axum::routing::post;
+use axum::{Json, Router};
+
+use crate::api_errors::ApiError;
+use crate::array_string_types::UuidString;
+use crate::data::audit::RequestOrigin;
+use crate::data::user::{Session, TotpEnrollment};
+use crate::request_state::{ClientIp, SharedState};
+use crate::services;
+
+pub fn create_router() -> Router<Arc<SharedState>> {
+    Router::new()
+        .route("/enroll", post(enroll))
+        .route("/confirm", post(confirm))
+        .route("/disable", post(disable))
+        .route("/login", post(login))
+}
+
+async fn enroll(
+    State(state): State<Arc<SharedState>>,
+    session: Session,
+) -> Result<Json<TotpEnrollment>, ApiError> {
+    session.require_login_session()?;
+    let mut conn = state.db_pool.begin().await.map_err(|_| ApiError::DbTransactionBegin)?;
+
+    let enrollment = services::user::totp::begin_enrollment(&mut *conn, session.user_id)
+        .await
+        .map_err(|err| {
+            tracing::error!("Starting TOTP enrollment failed: {err:?}");
+            ApiError::DbError
+        })?
+        .ok_or(ApiError::TotpAlreadyEnabled)?;
+
+    conn.commit().await.map_err(|_| ApiError::DbTransactionCommit)?;
+
+    Ok(Json(enrollment))
+}
+
+#[derive(serde::Deserialize)]
+struct CodeRequest {
+    code: String,
+}
+#[derive(serde::Serialize)]
+struct RecoveryCodes {
+    recovery_codes: Vec<String>,
+}
+async fn confirm(
+    State(state): State<Arc<SharedState>>,
+    session: Session,
+    origin: RequestOrigin,
+    Json(req): Json<CodeRequest>,
+) -> Result<Json<RecoveryCodes>, ApiError> {
+    session.require_login_session()?;
+    let mut conn = state.db_pool.begin().await.map_err(|_| ApiError::DbTransactionBegin)?;
+
+    let recovery_codes =
+        services::user::totp::confirm_enrollment(&mut *conn, &origin, session.user_id, &req.code)
+            .await
+            .map_err(|err| {
+                tracing::error!("Confirming TOTP enrollment failed: {err:?}");
+                ApiError::DbError
+            })?
+            .ok_or(ApiError::InvalidTotpCode)?;
+
+    conn.commit().await.map_err(|_| ApiError::DbTransactionCommit)?;
+
+    Ok(Json(RecoveryCodes { recovery_codes }))
+}
+
+async fn disable(
+    State(state): State<Arc<SharedState>>,
+    session: Session,
+    origin: RequestOrigin,
+    Json(req): Json<CodeRequest>,
+) -> Result<(), ApiError> {
+    session.require_login_session()?;
+    let mut conn = state.db_pool.begin().await.map_err(|_| ApiError::DbTransactionBegin)?;
+
+    let disabled = services::user::totp::disable(&mut *conn, &origin, session.user_id, &req.code)
+        .await
+        .map_err(|err| {
+            tracing::error!("Disabling TOTP failed: {err:?}");
+            ApiError::DbError
+        })?;
+    if !disabled {
+        return Err(ApiError::InvalidTotpCode);
+    }
+
+    conn.commit().await.map_err(|_| ApiError::DbTransactionCommit)?;
+
+    Ok(())
+}
+
+#[derive(serde::Deserialize)]
+struct LoginRequest {
+    pending_login_id: UuidString,
+    /// Either the current TOTP code or one of the recovery codes.
+    code: String,
+}
+#[derive(serde::Serialize)]
+struct LoginResponse {
+    session_id: UuidString,
+}
+async fn login(
+    State(state): State<Arc<SharedState>>,
+    ClientIp(ip): ClientIp,
+    origin: RequestOrigin,
+    Json(req): Json<LoginRequest>,
+) -> Result<Json<LoginResponse>, ApiError> {
+    if let Err(retry_after) = state.login_rate_limiter.begin_attempt(ip, None) {
+        return Err(ApiError::too_many_requests(retry_after));
+    }
+
+    let mut conn = state.db_pool.begin().await.map_err(|_| ApiError::DbTransactionBegin)?;
+
+    let session =
+        services::user::totp::complete_login(&mut *conn, &origin, req.pending_login_id, &req.code)
+            .await
+            .map_err(|err| {
+                tracing::error!("Completing a pending login failed: {err:?}");
+                ApiError::DbError
+            })?;
+
+    conn.commit().await.map_err(|_| ApiError::DbTransactionCommit)?;
+
+    let Some(session) = session else {
+        return Err(ApiError::InvalidTotpCode);
+    };
+    state.login_rate_limiter.record_success(ip, None);
+    tracing::debug!("session: {:?}", session);
+    Ok(Json(LoginResponse { session_id: session.uuid }))
+}
@@ -0,0 +1,88 @@
+use std::sync::Arc;
+
+use axum::extract::{Path, State};
+use axum::routing::{delete, get, post};
+use axum::{Json, Router};
+
+use crate::api_errors::ApiError;
+use crate::data::audit::RequestOrigin;
+use crate::data::collaborator::OwnershipTransfer;
+use crate::data::user::{ApiTokenScope, Session};
+use crate::request_state::SharedState;
+use crate::services;
+
+pub fn create_router() -> Router<Arc<SharedState>> {
+    Router::new()
+        .route("/", get(all))
+        .route("/:id/accept", post(accept))
+        .route("/:id", delete(decline))
+}
+
+async fn all(
+    State(state): State<Arc<SharedState>>,
+    session: Session,
+) -> Result<Json<Vec<OwnershipTransfer>>, ApiError> {
+    session.require_scope(ApiTokenScope::Read)?;
+    let transfers =
+        services::collaborator::transfer::get_transfers(&state.db_pool, session.user_id)
+            .await
+            .map_err(|err| {
+                tracing::error!("Getting the ownership transfers failed: {err:?}");
+                ApiError::DbError
+            })?;
+    Ok(Json(transfers))
+}
+
+async fn accept(
+    State(state): State<Arc<SharedState>>,
+    session: Session,
+    origin: RequestOrigin,
+    Path(id): Path<i32>,
+) -> Result<(), ApiError> {
+    session.require_login_session()?;
+    let mut conn = state.db_pool.begin().await.map_err(|_| ApiError::DbTransactionBegin)?;
+
+    let accepted =
+        services::collaborator::transfer::accept_transfer(&mut *conn, &origin, id, session.user_id)
+            .await
+            .map_err(|err| {
+                tracing::error!("Accepting ownership transfer #{id} failed: {err:?}");
+                ApiError::DbError
+            })?;
+    if !accepted {
+        return Err(ApiError::NoSuchTransfer);
+    }
+
+    conn.commit().await.map_err(|_| ApiError::DbTransactionCommit)?;
+
+    Ok(())
+}
+
+async fn decline(
+    State(state): State<Arc<SharedState>>,
+    session: Session,
+    origin: RequestOrigin,
+    Path(id): Path<i32>,
+) -> Result<(), ApiError> {
+    session.require_login_session()?;
+    let mut conn = state.db_pool.begin().await.map_err(|_| ApiError::DbTransactionBegin)?;
+
+    let declined = services::collaborator::transfer::decline_transfer(
+        &mut *conn,
+        &origin,
+        id,
+        session.user_id,
+    )
+    .await
+    .map_err(|err| {
+        tracing::error!("Declining ownership transfer #{id} failed: {err:?}");
+        ApiError::DbError
+    })?;
+    if !declined {
+        return Err(ApiError::NoSuchTransfer);
+    }
+
+    conn.commit().await.map_err(|_| ApiError::DbTransactionCommit)?;
+
+    Ok(())
+}
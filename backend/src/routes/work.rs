@@ -1,16 +1,20 @@
 use std::sync::Arc;
 
-use axum::extract::{Path, State};
-use axum::routing::{get, post, put};
+use axum::extract::{Path, Query, RawQuery, State};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get, post, put};
 use axum::{Json, Router};
 
 use crate::api_errors::ApiError;
-use crate::data::user::Session;
+use crate::data::audit::RequestOrigin;
+use crate::data::collaborator::CollaborationTarget;
+use crate::data::user::{ApiTokenScope, Session};
 use crate::data::work::{Work, WorkRow};
-use crate::routes::SharedState;
+use crate::routes::{AccessQuery, SharedState, check_new_slug, collaborators, redirect_to_slug};
 use crate::services;
 
 mod file;
+mod revisions;
 
 pub fn create_router() -> Router<Arc<SharedState>> {
     Router::new()
@@ -18,17 +22,24 @@ pub fn create_router() -> Router<Arc<SharedState>> {
         .route("/:slug", get(by_slug))
         .route("/:slug", post(create))
         .route("/:slug", put(edit))
+        .route("/:slug", delete(remove))
+        .route("/:slug/clone", post(clone))
+        .nest("/:slug/collaborators", collaborators::create_router(CollaborationTarget::Work))
+        .nest("/:slug/transfer", collaborators::create_transfer_router(CollaborationTarget::Work))
+        .nest("/:slug/revisions", revisions::create_router())
         .nest("/file", file::create_router())
 }
 
 async fn all(
     State(state): State<Arc<SharedState>>,
-    Session { user_id, .. }: Session,
+    session: Session,
 ) -> Result<Json<Vec<WorkRow>>, ApiError> {
-    let works = services::work::get_works(&state.db_pool, user_id).await.map_err(|err| {
-        tracing::error!("Getting all works for the logged in user failed: {err:?}");
-        ApiError::DbError
-    })?;
+    session.require_scope(ApiTokenScope::Read)?;
+    let works =
+        services::work::get_works(&state.db_pool, session.user_id).await.map_err(|err| {
+            tracing::error!("Getting all works for the logged in user failed: {err:?}");
+            ApiError::DbError
+        })?;
     Ok(Json(works))
 }
 
@@ -36,31 +47,91 @@ async fn by_slug(
     State(state): State<Arc<SharedState>>,
     session: Option<Session>,
     Path(slug): Path<String>,
-) -> Result<Json<Work>, ApiError> {
+    Query(AccessQuery { preview, viewer_token }): Query<AccessQuery>,
+    RawQuery(raw_query): RawQuery,
+) -> Result<Response, ApiError> {
+    // Tokens without the read scope see only what anonymous users do
+    let session = session.filter(|session| session.has_scope(ApiTokenScope::Read));
+    // Slugs are stored in lowercase, so links typed with other cases are
+    // pointed to the stored one
+    let normalized = services::slug::normalize(&slug);
+    if normalized != slug {
+        return Ok(redirect_to_slug(&normalized, raw_query));
+    }
+    let user_id = session.map(|Session { user_id, .. }| user_id);
     let work = services::work::get_work(
         &state.db_pool,
         &slug,
-        session.map(|Session { user_id, .. }| user_id),
+        user_id,
+        preview.as_deref(),
+        viewer_token.as_deref(),
     )
     .await
     .map_err(|err| {
         tracing::error!("Getting work by slug failed: {err:?}");
         ApiError::DbError
-    })?
-    .ok_or(ApiError::NoSuchSlug)?;
-    Ok(Json(work))
+    })?;
+    let Some(work) = work else {
+        let renamed =
+            services::slug::get_renamed_slug(&state.db_pool, CollaborationTarget::Work, &slug)
+                .await
+                .map_err(|err| {
+                    tracing::error!("Checking if the {slug} work was renamed failed: {err:?}");
+                    ApiError::DbError
+                })?;
+        if let Some(renamed) = renamed {
+            // Only redirect callers who could see the work under its new
+            // slug, or unlock it with its password, so the new slug doesn't
+            // leak to anyone else
+            let visible = services::work::get_work(
+                &state.db_pool,
+                &renamed.0,
+                user_id,
+                preview.as_deref(),
+                viewer_token.as_deref(),
+            )
+            .await
+            .map_err(|err| {
+                tracing::error!("Getting the renamed {slug} work failed: {err:?}");
+                ApiError::DbError
+            })?;
+            let locked = services::portfolio::passwords::is_work_locked(&state.db_pool, &renamed.0)
+                .await
+                .map_err(|err| {
+                    tracing::error!(
+                        "Checking if the renamed {slug} work is locked failed: {err:?}"
+                    );
+                    ApiError::DbError
+                })?;
+            if visible.is_some() || locked {
+                return Ok(redirect_to_slug(&renamed.0, raw_query));
+            }
+        }
+        let locked = services::portfolio::passwords::is_work_locked(&state.db_pool, &slug)
+            .await
+            .map_err(|err| {
+                tracing::error!("Checking if the {slug} work is locked failed: {err:?}");
+                ApiError::DbError
+            })?;
+        return Err(if locked { ApiError::PortfolioLocked } else { ApiError::NoSuchSlug });
+    };
+    Ok(Json(work).into_response())
 }
 
 async fn create(
     State(state): State<Arc<SharedState>>,
-    Session { user_id, .. }: Session,
+    session: Session,
+    origin: RequestOrigin,
     Path(slug): Path<String>,
     Json(arg): Json<Work>,
 ) -> Result<Json<Work>, ApiError> {
+    session.require_scope(ApiTokenScope::EditWorks)?;
     let mut conn = state.db_pool.begin().await.map_err(|_| ApiError::DbTransactionBegin)?;
+    let slug = check_new_slug(&mut *conn, CollaborationTarget::Work, &slug, None).await?;
 
-    let work =
-        services::work::create_work(&mut *conn, &slug, user_id, arg).await.map_err(|err| {
+    let work = services::work::create_work(&mut *conn, &origin, &slug.0, session.user_id, arg)
+        .await
+        .map_err(|err| {
             tracing::error!("Creating a new work failed: {err:?}");
             if services::is_unique_constraint_violation(err.root_cause()) {
                 return ApiError::SlugTaken;
@@ -75,22 +146,87 @@ async fn create(
 
 async fn edit(
     State(state): State<Arc<SharedState>>,
-    Session { user_id, .. }: Session,
+    session: Session,
+    origin: RequestOrigin,
     Path(slug): Path<String>,
-    Json(arg): Json<Work>,
+    Json(mut arg): Json<Work>,
 ) -> Result<Json<Work>, ApiError> {
+    session.require_scope(ApiTokenScope::EditWorks)?;
     let mut conn = state.db_pool.begin().await.map_err(|_| ApiError::DbTransactionBegin)?;
+    if arg.row.slug.0.as_str() != slug {
+        let new_slug = arg.row.slug.0.as_str();
+        arg.row.slug =
+            check_new_slug(&mut *conn, CollaborationTarget::Work, new_slug, Some(&slug)).await?;
+    }
 
-    let work =
-        services::work::update_work(&mut *conn, &slug, user_id, arg).await.map_err(|err| {
+    let work = services::work::update_work(&mut *conn, &origin, &slug, session.user_id, arg)
+        .await
+        .map_err(|err| {
             tracing::error!("Updating the {slug} work failed: {err:?}");
             if services::is_unique_constraint_violation(err.root_cause()) {
                 return ApiError::SlugTaken;
             }
             ApiError::DbError
-        })?;
+        })?
+        .ok_or(ApiError::NoSuchSlug)?;
 
     conn.commit().await.map_err(|_| ApiError::DbTransactionCommit)?;
 
     Ok(Json(work))
 }
+
+#[derive(serde::Deserialize)]
+struct CloneArgs {
+    /// The slug of the copy.
+    slug: String,
+}
+async fn clone(
+    State(state): State<Arc<SharedState>>,
+    session: Session,
+    origin: RequestOrigin,
+    Path(slug): Path<String>,
+    Json(CloneArgs { slug: new_slug }): Json<CloneArgs>,
+) -> Result<Json<Work>, ApiError> {
+    session.require_scope(ApiTokenScope::EditWorks)?;
+    let mut conn = state.db_pool.begin().await.map_err(|_| ApiError::DbTransactionBegin)?;
+    let new_slug = check_new_slug(&mut *conn, CollaborationTarget::Work, &new_slug, None).await?;
+
+    let work = services::work::clone_work(&mut *conn, &origin, &slug, &new_slug.0, session.user_id)
+        .await
+        .map_err(|err| {
+            tracing::error!("Cloning the {slug} work failed: {err:?}");
+            if services::is_unique_constraint_violation(err.root_cause()) {
+                return ApiError::SlugTaken;
+            }
+            ApiError::DbError
+        })?
+        .ok_or(ApiError::NoSuchSlug)?;
+
+    conn.commit().await.map_err(|_| ApiError::DbTransactionCommit)?;
+
+    Ok(Json(work))
+}
+
+async fn remove(
+    State(state): State<Arc<SharedState>>,
+    session: Session,
+    origin: RequestOrigin,
+    Path(slug): Path<String>,
+) -> Result<(), ApiError> {
+    session.require_scope(ApiTokenScope::EditWorks)?;
+    let mut conn = state.db_pool.begin().await.map_err(|_| ApiError::DbTransactionBegin)?;
+
+    let deleted = services::work::delete_work(&mut *conn, &origin, &slug, session.user_id)
+        .await
+        .map_err(|err| {
+            tracing::error!("Deleting the {slug} work failed: {err:?}");
+            ApiError::DbError
+        })?;
+    if !deleted {
+        return Err(ApiError::NoSuchSlug);
+    }
+
+    conn.commit().await.map_err(|_| ApiError::DbTransactionCommit)?;
+
+    Ok(())
+}
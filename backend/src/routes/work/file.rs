@@ -2,7 +2,7 @@ use core::convert::Infallible;
 use std::sync::Arc;
 
 use axum::body::Bytes;
-use axum::extract::{Path, State};
+use axum::extract::{Path, Query, State};
 use axum::http::{Method, Response};
 use axum::routing::{get, post};
 use axum::{Json, Router};
@@ -13,8 +13,10 @@ use tracing::{Instrument, Span};
 
 use crate::api_errors::ApiError;
 use crate::array_string_types::UuidString;
-use crate::data::user::Session;
+use crate::data::audit::RequestOrigin;
+use crate::data::user::{ApiTokenScope, Session};
 use crate::request_state::SharedState;
+use crate::routes::AccessQuery;
 use crate::services;
 
 pub fn create_router() -> Router<Arc<SharedState>> {
@@ -26,9 +28,28 @@ type ResponseBody = StreamBody<ReceiverStream<Data>>;
 
 async fn get_stream_by_uuid(
     State(state): State<Arc<SharedState>>,
+    session: Option<Session>,
     method: Method,
     Path(uuid): Path<String>,
+    Query(AccessQuery { preview, viewer_token }): Query<AccessQuery>,
 ) -> Result<Response<ResponseBody>, ApiError> {
+    // Tokens without the read scope see only what anonymous users do
+    let session = session.filter(|session| session.has_scope(ApiTokenScope::Read));
+    let locked = services::portfolio::passwords::is_file_locked(
+        &state.db_pool,
+        &uuid,
+        session.map(|Session { user_id, .. }| user_id),
+        preview.as_deref(),
+        viewer_token.as_deref(),
+    )
+    .await
+    .map_err(|err| {
+        tracing::error!("Checking if file {uuid} is locked failed: {err:?}");
+        ApiError::DbError
+    })?;
+    if locked {
+        return Err(ApiError::PortfolioLocked);
+    }
     let file_part = services::work::big_files::get_file_part(&state.db_pool, &uuid)
         .await
         .map_err(|err| {
@@ -94,23 +115,29 @@ struct CreatedFilePart {
 }
 async fn add_file_part(
     State(state): State<Arc<SharedState>>,
-    Session { user_id, .. }: Session,
+    session: Session,
+    origin: RequestOrigin,
     Json(params): Json<CreateFileParams>,
 ) -> Result<Json<CreatedFilePart>, ApiError> {
+    session.require_scope(ApiTokenScope::UploadFiles)?;
     let mut conn = state.db_pool.begin().await.map_err(|_| ApiError::DbTransactionBegin)?;
 
     let uuid = services::work::big_files::create_file_part(
         &mut *conn,
+        &origin,
         params.previous_uuid,
         params.work_attachment_id,
         params.part_bytes_base64,
-        user_id,
+        session.user_id,
     )
     .await
     .map_err(|err| {
         tracing::error!("Creating a new file part failed: {err:?}");
         ApiError::DbError
     })?;
+    let Some(uuid) = uuid else {
+        return Err(ApiError::FileShared);
+    };
 
     conn.commit().await.map_err(|_| ApiError::DbTransactionCommit)?;
 
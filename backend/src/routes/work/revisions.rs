@@ -0,0 +1,89 @@
+use std::sync::Arc;
+
+use axum::extract::{Path, Query, State};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+
+use crate::api_errors::ApiError;
+use crate::data::audit::RequestOrigin;
+use crate::data::user::{ApiTokenScope, Session};
+use crate::data::work::{Work, WorkRevision, WorkRevisionChange};
+use crate::request_state::SharedState;
+use crate::services;
+
+pub fn create_router() -> Router<Arc<SharedState>> {
+    Router::new()
+        .route("/", get(all))
+        .route("/diff", get(diff))
+        .route("/:id/restore", post(restore))
+}
+
+/// The work's revisions, newest first.
+async fn all(
+    State(state): State<Arc<SharedState>>,
+    session: Session,
+    Path(slug): Path<String>,
+) -> Result<Json<Vec<WorkRevision>>, ApiError> {
+    session.require_scope(ApiTokenScope::Read)?;
+    let revisions =
+        services::work::revisions::get_revisions(&state.db_pool, &slug, session.user_id)
+            .await
+            .map_err(|err| {
+                tracing::error!("Getting the revisions of {slug} failed: {err:?}");
+                ApiError::DbError
+            })?
+            .ok_or(ApiError::NoSuchSlug)?;
+    Ok(Json(revisions))
+}
+
+#[derive(serde::Deserialize)]
+struct DiffQuery {
+    from: i32,
+    to: i32,
+}
+
+async fn diff(
+    State(state): State<Arc<SharedState>>,
+    session: Session,
+    Path(slug): Path<String>,
+    Query(DiffQuery { from, to }): Query<DiffQuery>,
+) -> Result<Json<Vec<WorkRevisionChange>>, ApiError> {
+    session.require_scope(ApiTokenScope::Read)?;
+    let changes =
+        services::work::revisions::diff_revisions(&state.db_pool, &slug, session.user_id, from, to)
+            .await
+            .map_err(|err| {
+                tracing::error!("Diffing revisions {from} and {to} of {slug} failed: {err:?}");
+                ApiError::DbError
+            })?
+            .ok_or(ApiError::NoSuchRevision)?;
+    Ok(Json(changes))
+}
+
+async fn restore(
+    State(state): State<Arc<SharedState>>,
+    session: Session,
+    origin: RequestOrigin,
+    Path((slug, revision_id)): Path<(String, i32)>,
+) -> Result<Json<Work>, ApiError> {
+    session.require_scope(ApiTokenScope::EditWorks)?;
+    let mut conn = state.db_pool.begin().await.map_err(|_| ApiError::DbTransactionBegin)?;
+
+    let work = services::work::revisions::restore_revision(
+        &mut *conn,
+        &origin,
+        &slug,
+        session.user_id,
+        revision_id,
+    )
+    .await
+    .map_err(|err| {
+        tracing::error!("Restoring revision {revision_id} of {slug} failed: {err:?}");
+        ApiError::DbError
+    })?
+    .ok_or(ApiError::NoSuchRevision)?;
+
+    conn.commit().await.map_err(|_| ApiError::DbTransactionCommit)?;
+
+    Ok(Json(work))
+}
@@ -0,0 +1,140 @@
+use std::time::SystemTime;
+
+use anyhow::Context;
+use sqlx::{Any, Executor};
+
+use crate::array_string_types::UsernameString;
+use crate::data::admin::UserSummary;
+use crate::data::audit::{AuditAction, RequestOrigin};
+use crate::services::{audit, portfolio};
+
+#[derive(sqlx::FromRow)]
+struct UserSummaryRow {
+    id: i32,
+    username: UsernameString,
+    is_admin: i32,
+    disabled_at: Option<i64>,
+    totp_enabled_at: Option<i64>,
+    session_count: i64,
+}
+
+pub async fn get_users<E>(conn: &E) -> Result<Vec<UserSummary>, anyhow::Error>
+where
+    for<'e> &'e E: Executor<'e, Database = Any>,
+{
+    let query = sqlx::query_as(
+        "SELECT users.id, users.username, users.is_admin, users.disabled_at, users.totp_enabled_at, \
+            (SELECT COUNT(*) FROM sessions WHERE sessions.user_id = users.id) AS session_count \
+        FROM users ORDER BY users.id ASC",
+    );
+    let rows: Vec<UserSummaryRow> = query.fetch_all(conn).await.context("get all users failed")?;
+
+    let users = rows
+        .into_iter()
+        .map(|row| UserSummary {
+            id: row.id,
+            username: row.username,
+            is_admin: row.is_admin == 1,
+            disabled_at: row.disabled_at,
+            totp_enabled: row.totp_enabled_at.is_some(),
+            session_count: row.session_count,
+        })
+        .collect();
+    Ok(users)
+}
+
+/// Disables or re-enables the account. Disabling also logs the user out
+/// everywhere and revokes their API tokens. Returns false if there's no user
+/// with the id.
+pub async fn set_user_disabled<E>(
+    conn: &mut E,
+    origin: &RequestOrigin,
+    admin_user_id: i32,
+    user_id: i32,
+    disabled: bool,
+) -> Result<bool, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let current_time =
+        SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as i64;
+    let query =
+        sqlx::query_as("UPDATE users SET disabled_at = $1 WHERE id = $2 RETURNING username");
+    let username: Option<(String,)> = query
+        .bind(if disabled { Some(current_time) } else { None })
+        .bind(user_id)
+        .fetch_optional(&mut *conn)
+        .await
+        .context("user disabled_at update failed")?;
+    let Some((username,)) = username else {
+        return Ok(false);
+    };
+    let action = if disabled { AuditAction::UserDisabled } else { AuditAction::UserEnabled };
+    audit::record(&mut *conn, origin, Some(admin_user_id), action, &username).await?;
+
+    if disabled {
+        crate::services::user::remove_user_sessions(&mut *conn, user_id).await?;
+        sqlx::query("DELETE FROM api_tokens WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut *conn)
+            .await
+            .context("removing disabled user's api tokens failed")?;
+    }
+
+    Ok(true)
+}
+
+/// Turns off two-factor authentication for a user who has lost access to
+/// their authenticator and recovery codes.
+pub async fn reset_totp<E>(
+    conn: &mut E,
+    origin: &RequestOrigin,
+    admin_user_id: i32,
+    user_id: i32,
+) -> Result<(), anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let query = sqlx::query_as(
+        "UPDATE users SET totp_secret_base32 = NULL, totp_enabled_at = NULL, totp_last_step = NULL \
+        WHERE id = $1 RETURNING username",
+    );
+    let (username,): (String,) =
+        query.bind(user_id).fetch_one(&mut *conn).await.context("resetting totp failed")?;
+    audit::record(&mut *conn, origin, Some(admin_user_id), AuditAction::TotpReset, &username)
+        .await?;
+    sqlx::query("DELETE FROM totp_recovery_codes WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *conn)
+        .await
+        .context("removing recovery codes failed")?;
+    Ok(())
+}
+
+/// Unpublishes the portfolio regardless of who owns it. Returns false if
+/// there's no portfolio with the slug.
+pub async fn unpublish_portfolio<E>(
+    conn: &mut E,
+    origin: &RequestOrigin,
+    admin_user_id: i32,
+    slug: &str,
+) -> Result<bool, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let query = sqlx::query_as(
+        "UPDATE portfolios SET published_at = NULL, publish_at = NULL WHERE slug = $1 RETURNING id",
+    );
+    let portfolio_id: Option<(i32,)> = query
+        .bind(slug)
+        .fetch_optional(&mut *conn)
+        .await
+        .context("unpublishing portfolio failed")?;
+    let Some((portfolio_id,)) = portfolio_id else {
+        return Ok(false);
+    };
+    portfolio::published::delete_snapshot(&mut *conn, portfolio_id).await?;
+    let actor = Some(admin_user_id);
+    audit::record(&mut *conn, origin, actor, AuditAction::PortfolioUnpublished, slug).await?;
+    Ok(true)
+}
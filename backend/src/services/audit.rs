@@ -0,0 +1,91 @@
+//! The audit log is append-only: events are only ever inserted, and removed
+//! once they're older than the configured retention period.
+
+use std::time::SystemTime;
+
+use anyhow::Context;
+use sqlx::{Any, Executor};
+
+use crate::data::audit::{AuditAction, AuditEvent, AuditEventFilter, RequestOrigin};
+
+pub async fn record<E>(
+    conn: &mut E,
+    origin: &RequestOrigin,
+    actor_user_id: Option<i32>,
+    action: AuditAction,
+    target: &str,
+) -> Result<(), anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let current_time =
+        SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as i64;
+    let query = sqlx::query(
+        "INSERT INTO audit_events (created_at, actor_user_id, action, target, ip, user_agent) \
+        VALUES                    ($1,         $2,            $3,     $4,     $5, $6)",
+    );
+    query
+        .bind(current_time)
+        .bind(actor_user_id)
+        .bind(action)
+        .bind(target)
+        .bind(&origin.ip)
+        .bind(origin.user_agent.as_deref())
+        .execute(conn)
+        .await
+        .context("audit event insert failed")?;
+    Ok(())
+}
+
+/// Returns at most `limit` events matching the filter, newest first.
+pub async fn get_events<E>(
+    conn: &E,
+    filter: &AuditEventFilter,
+    limit: i64,
+) -> Result<Vec<AuditEvent>, anyhow::Error>
+where
+    for<'e> &'e E: Executor<'e, Database = Any>,
+{
+    let query = sqlx::query_as(
+        "SELECT audit_events.*, users.username AS actor_username FROM audit_events \
+        LEFT JOIN users ON (users.id = audit_events.actor_user_id) \
+        WHERE ($1 IS NULL OR audit_events.actor_user_id = $1) \
+            AND ($2 IS NULL OR audit_events.action = $2) \
+            AND ($3 IS NULL OR audit_events.target = $3) \
+            AND ($4 IS NULL OR audit_events.ip = $4) \
+            AND ($5 IS NULL OR audit_events.created_at >= $5) \
+            AND ($6 IS NULL OR audit_events.created_at < $6) \
+            AND ($7 IS NULL OR audit_events.id < $7) \
+        ORDER BY audit_events.id DESC \
+        LIMIT $8",
+    );
+    query
+        .bind(filter.actor_user_id)
+        .bind(filter.action)
+        .bind(filter.target.as_deref())
+        .bind(filter.ip.as_deref())
+        .bind(filter.since)
+        .bind(filter.until)
+        .bind(filter.before_id)
+        .bind(limit)
+        .fetch_all(conn)
+        .await
+        .context("get audit events failed")
+}
+
+pub async fn remove_events<E>(
+    conn: &mut E,
+    before_timestamp: SystemTime,
+) -> Result<(), anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let before_timestamp =
+        before_timestamp.duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as i64;
+    sqlx::query("DELETE FROM audit_events WHERE created_at < $1")
+        .bind(before_timestamp)
+        .execute(conn)
+        .await
+        .context("removing old audit events failed")?;
+    Ok(())
+}
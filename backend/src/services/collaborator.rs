@@ -0,0 +1,389 @@
+use anyhow::Context;
+use sqlx::{Any, Executor};
+
+use crate::data::audit::{AuditAction, RequestOrigin};
+use crate::data::collaborator::{
+    CollaborationTarget, Collaborator, CollaboratorChangeOutcome, CollaboratorRole,
+};
+use crate::services::audit;
+
+pub mod transfer;
+
+/// The table of the target, its rights table, and the rights table's column
+/// referring to the target.
+fn tables(target: CollaborationTarget) -> (&'static str, &'static str, &'static str) {
+    match target {
+        CollaborationTarget::Work => ("works", "work_rights", "work_id"),
+        CollaborationTarget::Portfolio => ("portfolios", "portfolio_rights", "portfolio_id"),
+    }
+}
+
+/// Returns the collaborators of the work or portfolio, or None if it doesn't
+/// exist or the user isn't one of its collaborators.
+pub async fn get_collaborators<E>(
+    conn: &E,
+    target: CollaborationTarget,
+    slug: &str,
+    user_id: i32,
+) -> Result<Option<Vec<Collaborator>>, anyhow::Error>
+where
+    for<'e> &'e E: Executor<'e, Database = Any>,
+{
+    let (table, rights_table, id_column) = tables(target);
+    // Safety: the table names aren't from user input.
+    let query = format!(
+        "SELECT users.username, organizations.slug AS organization, {rights_table}.role \
+        FROM {rights_table} \
+            LEFT JOIN users ON (users.id = {rights_table}.user_id) \
+            LEFT JOIN organizations ON (organizations.id = {rights_table}.organization_id) \
+        WHERE {rights_table}.{id_column} = ( select id from {table} where slug = $1 and deleted_at is null ) \
+        ORDER BY {rights_table}.role, users.username, organizations.slug"
+    );
+    let collaborators: Vec<Collaborator> = sqlx::query_as(&query)
+        .bind(slug)
+        .fetch_all(conn)
+        .await
+        .context("get collaborators failed")?;
+
+    let query = format!(
+        "SELECT user_id FROM effective_{rights_table} \
+        WHERE user_id = $1 AND {id_column} = ( select id from {table} where slug = $2 and deleted_at is null )"
+    );
+    let is_collaborator: Option<(i32,)> = sqlx::query_as(&query)
+        .bind(user_id)
+        .bind(slug)
+        .fetch_optional(conn)
+        .await
+        .context("collaborator check failed")?;
+
+    Ok(is_collaborator.map(|_| collaborators))
+}
+
+/// Who a row in a rights table gives the rights to.
+#[derive(Debug, Clone, Copy)]
+enum RightsHolder {
+    User(i32),
+    Organization(i32),
+}
+
+impl RightsHolder {
+    fn column_and_id(self) -> (&'static str, i32) {
+        match self {
+            RightsHolder::User(user_id) => ("user_id", user_id),
+            RightsHolder::Organization(organization_id) => ("organization_id", organization_id),
+        }
+    }
+
+    fn missing_outcome(self) -> CollaboratorChangeOutcome {
+        match self {
+            RightsHolder::User(_) => CollaboratorChangeOutcome::NoSuchUser,
+            RightsHolder::Organization(_) => CollaboratorChangeOutcome::NoSuchOrganization,
+        }
+    }
+}
+
+/// Adds the user with the username as a collaborator, or changes their role if
+/// they already are one. Only owners can do this.
+#[allow(clippy::too_many_arguments)]
+pub async fn set_collaborator<E>(
+    conn: &mut E,
+    origin: &RequestOrigin,
+    target: CollaborationTarget,
+    slug: &str,
+    user_id: i32,
+    username: &str,
+    role: CollaboratorRole,
+) -> Result<CollaboratorChangeOutcome, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let Some((target_id, own_role)) = get_role(&mut *conn, target, slug, user_id).await? else {
+        return Ok(CollaboratorChangeOutcome::NoSuchSlug);
+    };
+    if own_role != CollaboratorRole::Owner {
+        return Ok(CollaboratorChangeOutcome::NotOwner);
+    }
+    let Some(collaborator_id) = get_user_id(&mut *conn, username).await? else {
+        return Ok(CollaboratorChangeOutcome::NoSuchUser);
+    };
+
+    let holder = RightsHolder::User(collaborator_id);
+    let change = RoleChange { target, target_id, slug, holder, holder_name: username };
+    change_role(&mut *conn, origin, user_id, change, Some(role)).await
+}
+
+/// Removes the user with the username from the collaborators. Owners can
+/// remove anyone, others can only remove themselves.
+pub async fn remove_collaborator<E>(
+    conn: &mut E,
+    origin: &RequestOrigin,
+    target: CollaborationTarget,
+    slug: &str,
+    user_id: i32,
+    username: &str,
+) -> Result<CollaboratorChangeOutcome, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let Some((target_id, own_role)) = get_role(&mut *conn, target, slug, user_id).await? else {
+        return Ok(CollaboratorChangeOutcome::NoSuchSlug);
+    };
+    let Some(collaborator_id) = get_user_id(&mut *conn, username).await? else {
+        return Ok(CollaboratorChangeOutcome::NoSuchUser);
+    };
+    if collaborator_id != user_id && own_role != CollaboratorRole::Owner {
+        return Ok(CollaboratorChangeOutcome::NotOwner);
+    }
+
+    let holder = RightsHolder::User(collaborator_id);
+    let change = RoleChange { target, target_id, slug, holder, holder_name: username };
+    change_role(&mut *conn, origin, user_id, change, None).await
+}
+
+/// Gives all the members of the organization the role in the work or
+/// portfolio. Only owners who are members of the organization can do this.
+#[allow(clippy::too_many_arguments)]
+pub async fn set_organization_collaborator<E>(
+    conn: &mut E,
+    origin: &RequestOrigin,
+    target: CollaborationTarget,
+    slug: &str,
+    user_id: i32,
+    organization_slug: &str,
+    role: CollaboratorRole,
+) -> Result<CollaboratorChangeOutcome, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let Some((target_id, own_role)) = get_role(&mut *conn, target, slug, user_id).await? else {
+        return Ok(CollaboratorChangeOutcome::NoSuchSlug);
+    };
+    if own_role != CollaboratorRole::Owner {
+        return Ok(CollaboratorChangeOutcome::NotOwner);
+    }
+    let query = sqlx::query_as(
+        "SELECT organizations.id FROM organizations \
+            JOIN organization_members ON (organization_members.organization_id = organizations.id) \
+        WHERE organizations.slug = $1 AND organization_members.user_id = $2",
+    );
+    let organization_id: Option<(i32,)> = query
+        .bind(organization_slug)
+        .bind(user_id)
+        .fetch_optional(&mut *conn)
+        .await
+        .context("organization membership check failed")?;
+    let Some((organization_id,)) = organization_id else {
+        return Ok(CollaboratorChangeOutcome::NoSuchOrganization);
+    };
+
+    let holder = RightsHolder::Organization(organization_id);
+    let holder_name = &format!("organization:{organization_slug}");
+    let change = RoleChange { target, target_id, slug, holder, holder_name };
+    change_role(&mut *conn, origin, user_id, change, Some(role)).await
+}
+
+/// Removes the organization from the collaborators. Only owners can do this.
+pub async fn remove_organization_collaborator<E>(
+    conn: &mut E,
+    origin: &RequestOrigin,
+    target: CollaborationTarget,
+    slug: &str,
+    user_id: i32,
+    organization_slug: &str,
+) -> Result<CollaboratorChangeOutcome, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let Some((target_id, own_role)) = get_role(&mut *conn, target, slug, user_id).await? else {
+        return Ok(CollaboratorChangeOutcome::NoSuchSlug);
+    };
+    if own_role != CollaboratorRole::Owner {
+        return Ok(CollaboratorChangeOutcome::NotOwner);
+    }
+    let organization_id: Option<(i32,)> =
+        sqlx::query_as("SELECT id FROM organizations WHERE slug = $1")
+            .bind(organization_slug)
+            .fetch_optional(&mut *conn)
+            .await
+            .context("organization id fetch failed")?;
+    let Some((organization_id,)) = organization_id else {
+        return Ok(CollaboratorChangeOutcome::NoSuchOrganization);
+    };
+
+    let holder = RightsHolder::Organization(organization_id);
+    let holder_name = &format!("organization:{organization_slug}");
+    let change = RoleChange { target, target_id, slug, holder, holder_name };
+    change_role(&mut *conn, origin, user_id, change, None).await
+}
+
+struct RoleChange<'a> {
+    target: CollaborationTarget,
+    target_id: i32,
+    /// The slug of the target, for the audit log.
+    slug: &'a str,
+    holder: RightsHolder,
+    /// The username or organization, for the audit log.
+    holder_name: &'a str,
+}
+
+/// Sets the role of the holder, or removes their rights if `new_role` is None,
+/// as long as that doesn't leave the target without owners.
+async fn change_role<E>(
+    conn: &mut E,
+    origin: &RequestOrigin,
+    user_id: i32,
+    change: RoleChange<'_>,
+    new_role: Option<CollaboratorRole>,
+) -> Result<CollaboratorChangeOutcome, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let RoleChange { target, target_id, slug, holder, holder_name } = change;
+    let (_, rights_table, id_column) = tables(target);
+    let (holder_column, holder_id) = holder.column_and_id();
+    let query =
+        format!("SELECT role FROM {rights_table} WHERE {id_column} = $1 AND {holder_column} = $2");
+    let previous_role: Option<(CollaboratorRole,)> = sqlx::query_as(&query)
+        .bind(target_id)
+        .bind(holder_id)
+        .fetch_optional(&mut *conn)
+        .await
+        .context("collaborator role fetch failed")?;
+    if previous_role.is_none() && new_role.is_none() {
+        return Ok(holder.missing_outcome());
+    }
+    if previous_role == Some((CollaboratorRole::Owner,))
+        && new_role != Some(CollaboratorRole::Owner)
+        && count_other_owners(&mut *conn, target, target_id, holder).await? == 0
+    {
+        return Ok(CollaboratorChangeOutcome::LastOwner);
+    }
+
+    let action = if let Some(new_role) = new_role {
+        set_role(&mut *conn, target, target_id, holder, new_role).await?;
+        match target {
+            CollaborationTarget::Work => AuditAction::WorkCollaboratorSet,
+            CollaborationTarget::Portfolio => AuditAction::PortfolioCollaboratorSet,
+        }
+    } else {
+        let query =
+            format!("DELETE FROM {rights_table} WHERE {id_column} = $1 AND {holder_column} = $2");
+        sqlx::query(&query)
+            .bind(target_id)
+            .bind(holder_id)
+            .execute(&mut *conn)
+            .await
+            .context("collaborator delete failed")?;
+        match target {
+            CollaborationTarget::Work => AuditAction::WorkCollaboratorRemoved,
+            CollaborationTarget::Portfolio => AuditAction::PortfolioCollaboratorRemoved,
+        }
+    };
+    let audit_target = format!("{slug}/{holder_name}");
+    audit::record(&mut *conn, origin, Some(user_id), action, &audit_target).await?;
+
+    Ok(CollaboratorChangeOutcome::Changed)
+}
+
+async fn set_role<E>(
+    conn: &mut E,
+    target: CollaborationTarget,
+    target_id: i32,
+    holder: RightsHolder,
+    role: CollaboratorRole,
+) -> Result<(), anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let (_, rights_table, id_column) = tables(target);
+    let (holder_column, holder_id) = holder.column_and_id();
+    let query =
+        format!("DELETE FROM {rights_table} WHERE {id_column} = $1 AND {holder_column} = $2");
+    sqlx::query(&query)
+        .bind(target_id)
+        .bind(holder_id)
+        .execute(&mut *conn)
+        .await
+        .context("previous collaborator role delete failed")?;
+    let query = format!(
+        "INSERT INTO {rights_table} ({id_column}, {holder_column}, role) VALUES ($1, $2, $3)"
+    );
+    sqlx::query(&query)
+        .bind(target_id)
+        .bind(holder_id)
+        .bind(role)
+        .execute(&mut *conn)
+        .await
+        .context("collaborator insert failed")?;
+    Ok(())
+}
+
+/// Returns the id of the (non-deleted) work or portfolio, and the user's role
+/// in it, if they have one. If the user has multiple roles through
+/// organizations, the one with the most rights is returned.
+async fn get_role<E>(
+    conn: &mut E,
+    target: CollaborationTarget,
+    slug: &str,
+    user_id: i32,
+) -> Result<Option<(i32, CollaboratorRole)>, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let (table, rights_table, id_column) = tables(target);
+    let query = format!(
+        "SELECT {table}.id, rights.role FROM {table} \
+            JOIN effective_{rights_table} rights ON (rights.{id_column} = {table}.id) \
+        WHERE {table}.slug = $1 AND {table}.deleted_at IS NULL AND rights.user_id = $2 \
+        ORDER BY rights.role LIMIT 1"
+    );
+    sqlx::query_as(&query)
+        .bind(slug)
+        .bind(user_id)
+        .fetch_optional(&mut *conn)
+        .await
+        .context("collaborator role fetch failed")
+}
+
+/// Counts the users who are owners of the target, directly or through their
+/// organizations, like `effective_work_rights` and `effective_portfolio_rights`
+/// would, but without the rights of the holder.
+async fn count_other_owners<E>(
+    conn: &mut E,
+    target: CollaborationTarget,
+    target_id: i32,
+    holder: RightsHolder,
+) -> Result<i64, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let (_, rights_table, id_column) = tables(target);
+    let (holder_column, holder_id) = holder.column_and_id();
+    let query = format!(
+        "SELECT COUNT(*) FROM {rights_table} rights \
+            LEFT JOIN organization_members ON (organization_members.organization_id = rights.organization_id) \
+        WHERE rights.{id_column} = $1 AND rights.role = $2 \
+            AND (rights.{holder_column} IS NULL OR rights.{holder_column} <> $3) \
+            AND (rights.user_id IS NOT NULL OR organization_members.user_id IS NOT NULL)"
+    );
+    let (count,): (i64,) = sqlx::query_as(&query)
+        .bind(target_id)
+        .bind(CollaboratorRole::Owner)
+        .bind(holder_id)
+        .fetch_one(&mut *conn)
+        .await
+        .context("owner count failed")?;
+    Ok(count)
+}
+
+async fn get_user_id<E>(conn: &mut E, username: &str) -> Result<Option<i32>, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let user_id: Option<(i32,)> = sqlx::query_as("SELECT id FROM users WHERE username = $1")
+        .bind(username)
+        .fetch_optional(&mut *conn)
+        .await
+        .context("collaborator user id fetch failed")?;
+    Ok(user_id.map(|(id,)| id))
+}
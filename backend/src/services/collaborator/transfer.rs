@@ -0,0 +1,326 @@
+use std::time::SystemTime;
+
+use anyhow::Context;
+use sqlx::{Any, Executor};
+
+use super::{RightsHolder, get_role, get_user_id, set_role, tables};
+use crate::data::audit::{AuditAction, RequestOrigin};
+use crate::data::collaborator::{
+    CollaborationTarget, CollaboratorChangeOutcome, CollaboratorRole, OwnershipTransfer,
+};
+use crate::services::audit;
+
+/// Proposes transferring the user's ownership of the work or portfolio to the
+/// user with the username, replacing any previously proposed transfer of it.
+pub async fn propose_transfer<E>(
+    conn: &mut E,
+    origin: &RequestOrigin,
+    target: CollaborationTarget,
+    slug: &str,
+    user_id: i32,
+    username: &str,
+) -> Result<CollaboratorChangeOutcome, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let Some((target_id, own_role)) = get_role(&mut *conn, target, slug, user_id).await? else {
+        return Ok(CollaboratorChangeOutcome::NoSuchSlug);
+    };
+    if own_role != CollaboratorRole::Owner {
+        return Ok(CollaboratorChangeOutcome::NotOwner);
+    }
+    let Some(recipient_id) = get_user_id(&mut *conn, username).await? else {
+        return Ok(CollaboratorChangeOutcome::NoSuchUser);
+    };
+    if recipient_id == user_id {
+        return Ok(CollaboratorChangeOutcome::SameUser);
+    }
+
+    let (_, _, id_column) = tables(target);
+    sqlx::query(&format!("DELETE FROM ownership_transfers WHERE {id_column} = $1"))
+        .bind(target_id)
+        .execute(&mut *conn)
+        .await
+        .context("previous ownership transfer delete failed")?;
+    let current_time =
+        SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as i64;
+    let query = format!(
+        "INSERT INTO ownership_transfers (created_at, {id_column}, from_user_id, to_user_id) \
+        VALUES ($1, $2, $3, $4)"
+    );
+    sqlx::query(&query)
+        .bind(current_time)
+        .bind(target_id)
+        .bind(user_id)
+        .bind(recipient_id)
+        .execute(&mut *conn)
+        .await
+        .context("ownership transfer insert failed")?;
+
+    let action = match target {
+        CollaborationTarget::Work => AuditAction::WorkTransferProposed,
+        CollaborationTarget::Portfolio => AuditAction::PortfolioTransferProposed,
+    };
+    let audit_target = format!("{slug}/{username}");
+    audit::record(&mut *conn, origin, Some(user_id), action, &audit_target).await?;
+
+    Ok(CollaboratorChangeOutcome::Changed)
+}
+
+/// Cancels the proposed transfer of the work or portfolio. Any of its owners
+/// can do this. Returns false if there's no such transfer.
+pub async fn cancel_transfer<E>(
+    conn: &mut E,
+    origin: &RequestOrigin,
+    target: CollaborationTarget,
+    slug: &str,
+    user_id: i32,
+) -> Result<bool, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let Some((target_id, CollaboratorRole::Owner)) =
+        get_role(&mut *conn, target, slug, user_id).await?
+    else {
+        return Ok(false);
+    };
+    let (_, _, id_column) = tables(target);
+    let query =
+        format!("DELETE FROM ownership_transfers WHERE {id_column} = $1 RETURNING to_user_id");
+    let recipient: Option<(i32,)> = sqlx::query_as(&query)
+        .bind(target_id)
+        .fetch_optional(&mut *conn)
+        .await
+        .context("ownership transfer delete failed")?;
+    let Some((recipient_id,)) = recipient else {
+        return Ok(false);
+    };
+
+    let username = get_username(&mut *conn, recipient_id).await?;
+    let audit_target = format!("{slug}/{username}");
+    audit::record(&mut *conn, origin, Some(user_id), cancelled_action(target), &audit_target)
+        .await?;
+
+    Ok(true)
+}
+
+/// Returns the pending transfers proposed by or to the user.
+pub async fn get_transfers<E>(
+    conn: &E,
+    user_id: i32,
+) -> Result<Vec<OwnershipTransfer>, anyhow::Error>
+where
+    for<'e> &'e E: Executor<'e, Database = Any>,
+{
+    let query = sqlx::query_as(
+        "SELECT ownership_transfers.id, ownership_transfers.created_at, \
+            works.slug AS work_slug, portfolios.slug AS portfolio_slug, \
+            from_users.username AS from_username, to_users.username AS to_username \
+        FROM ownership_transfers \
+            LEFT JOIN works ON (works.id = ownership_transfers.work_id) \
+            LEFT JOIN portfolios ON (portfolios.id = ownership_transfers.portfolio_id) \
+            JOIN users from_users ON (from_users.id = ownership_transfers.from_user_id) \
+            JOIN users to_users ON (to_users.id = ownership_transfers.to_user_id) \
+        WHERE (ownership_transfers.from_user_id = $1 OR ownership_transfers.to_user_id = $2) \
+            AND works.deleted_at IS NULL AND portfolios.deleted_at IS NULL \
+        ORDER BY ownership_transfers.created_at DESC",
+    );
+    query
+        .bind(user_id)
+        .bind(user_id)
+        .fetch_all(conn)
+        .await
+        .context("get ownership transfers failed")
+}
+
+/// Accepts a transfer proposed to the user: the proposer's rights are removed,
+/// along with the ownership of the organizations they're a member of, and the
+/// user becomes an owner. Returns false if there's no such transfer, or if the
+/// proposer isn't an owner anymore.
+pub async fn accept_transfer<E>(
+    conn: &mut E,
+    origin: &RequestOrigin,
+    transfer_id: i32,
+    user_id: i32,
+) -> Result<bool, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let query = sqlx::query_as(
+        "DELETE FROM ownership_transfers WHERE id = $1 AND to_user_id = $2 \
+        RETURNING work_id, portfolio_id, from_user_id",
+    );
+    let transfer: Option<(Option<i32>, Option<i32>, i32)> = query
+        .bind(transfer_id)
+        .bind(user_id)
+        .fetch_optional(&mut *conn)
+        .await
+        .context("ownership transfer delete failed")?;
+    let (target, target_id, from_user_id) = match transfer {
+        Some((Some(work_id), _, from_user_id)) => {
+            (CollaborationTarget::Work, work_id, from_user_id)
+        }
+        Some((None, Some(portfolio_id), from_user_id)) => {
+            (CollaborationTarget::Portfolio, portfolio_id, from_user_id)
+        }
+        _ => return Ok(false),
+    };
+
+    let (table, rights_table, id_column) = tables(target);
+    let query = format!(
+        "SELECT {table}.slug FROM {table} \
+            JOIN effective_{rights_table} rights ON (rights.{id_column} = {table}.id) \
+        WHERE {table}.id = $1 AND {table}.deleted_at IS NULL \
+            AND rights.user_id = $2 AND rights.role = $3"
+    );
+    let slug: Option<(String,)> = sqlx::query_as(&query)
+        .bind(target_id)
+        .bind(from_user_id)
+        .bind(CollaboratorRole::Owner)
+        .fetch_optional(&mut *conn)
+        .await
+        .context("proposer's ownership check failed")?;
+    let Some((slug,)) = slug else {
+        return Ok(false);
+    };
+
+    let query = format!(
+        "DELETE FROM {rights_table} WHERE {id_column} = $1 AND (user_id = $2 \
+            OR (role = $3 AND organization_id IN ( select organization_id from organization_members where user_id = $4 )))"
+    );
+    sqlx::query(&query)
+        .bind(target_id)
+        .bind(from_user_id)
+        .bind(CollaboratorRole::Owner)
+        .bind(from_user_id)
+        .execute(&mut *conn)
+        .await
+        .context("proposer's rights delete failed")?;
+    let holder = RightsHolder::User(user_id);
+    set_role(&mut *conn, target, target_id, holder, CollaboratorRole::Owner).await?;
+
+    let username = get_username(&mut *conn, user_id).await?;
+    let audit_target = format!("{slug}/{username}");
+    audit::record(&mut *conn, origin, Some(user_id), transferred_action(target), &audit_target)
+        .await?;
+
+    Ok(true)
+}
+
+/// Declines a transfer proposed to the user, or cancels one proposed by the
+/// user. Returns false if there's no such transfer.
+pub async fn decline_transfer<E>(
+    conn: &mut E,
+    origin: &RequestOrigin,
+    transfer_id: i32,
+    user_id: i32,
+) -> Result<bool, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let query = sqlx::query_as(
+        "DELETE FROM ownership_transfers WHERE id = $1 AND (to_user_id = $2 OR from_user_id = $3) \
+        RETURNING work_id, portfolio_id, to_user_id",
+    );
+    let transfer: Option<(Option<i32>, Option<i32>, i32)> = query
+        .bind(transfer_id)
+        .bind(user_id)
+        .bind(user_id)
+        .fetch_optional(&mut *conn)
+        .await
+        .context("ownership transfer delete failed")?;
+    let (target, target_id, recipient_id) = match transfer {
+        Some((Some(work_id), _, to_user_id)) => (CollaborationTarget::Work, work_id, to_user_id),
+        Some((None, Some(portfolio_id), to_user_id)) => {
+            (CollaborationTarget::Portfolio, portfolio_id, to_user_id)
+        }
+        _ => return Ok(false),
+    };
+
+    let (table, _, _) = tables(target);
+    let (slug,): (String,) = sqlx::query_as(&format!("SELECT slug FROM {table} WHERE id = $1"))
+        .bind(target_id)
+        .fetch_one(&mut *conn)
+        .await
+        .context("transfer target slug fetch failed")?;
+    let username = get_username(&mut *conn, recipient_id).await?;
+    let audit_target = format!("{slug}/{username}");
+    audit::record(&mut *conn, origin, Some(user_id), cancelled_action(target), &audit_target)
+        .await?;
+
+    Ok(true)
+}
+
+/// Makes the user the only owner of the work or portfolio, without anyone
+/// having to accept it, e.g. when the previous owner has left. Other
+/// collaborators keep their roles. Returns false if there's no work or
+/// portfolio with the slug.
+pub async fn force_transfer<E>(
+    conn: &mut E,
+    origin: &RequestOrigin,
+    admin_user_id: i32,
+    target: CollaborationTarget,
+    slug: &str,
+    to_user_id: i32,
+) -> Result<bool, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let (table, rights_table, id_column) = tables(target);
+    let query = format!("SELECT id FROM {table} WHERE slug = $1 AND deleted_at IS NULL");
+    let target_id: Option<(i32,)> = sqlx::query_as(&query)
+        .bind(slug)
+        .fetch_optional(&mut *conn)
+        .await
+        .context("transfer target id fetch failed")?;
+    let Some((target_id,)) = target_id else {
+        return Ok(false);
+    };
+
+    sqlx::query(&format!("DELETE FROM ownership_transfers WHERE {id_column} = $1"))
+        .bind(target_id)
+        .execute(&mut *conn)
+        .await
+        .context("pending ownership transfer delete failed")?;
+    sqlx::query(&format!("DELETE FROM {rights_table} WHERE {id_column} = $1 AND role = $2"))
+        .bind(target_id)
+        .bind(CollaboratorRole::Owner)
+        .execute(&mut *conn)
+        .await
+        .context("previous owners' rights delete failed")?;
+    let holder = RightsHolder::User(to_user_id);
+    set_role(&mut *conn, target, target_id, holder, CollaboratorRole::Owner).await?;
+
+    let username = get_username(&mut *conn, to_user_id).await?;
+    let audit_target = format!("{slug}/{username}");
+    let actor = Some(admin_user_id);
+    audit::record(&mut *conn, origin, actor, transferred_action(target), &audit_target).await?;
+
+    Ok(true)
+}
+
+fn transferred_action(target: CollaborationTarget) -> AuditAction {
+    match target {
+        CollaborationTarget::Work => AuditAction::WorkTransferred,
+        CollaborationTarget::Portfolio => AuditAction::PortfolioTransferred,
+    }
+}
+
+fn cancelled_action(target: CollaborationTarget) -> AuditAction {
+    match target {
+        CollaborationTarget::Work => AuditAction::WorkTransferCancelled,
+        CollaborationTarget::Portfolio => AuditAction::PortfolioTransferCancelled,
+    }
+}
+
+async fn get_username<E>(conn: &mut E, user_id: i32) -> Result<String, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let (username,): (String,) = sqlx::query_as("SELECT username FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_one(&mut *conn)
+        .await
+        .context("new owner's username fetch failed")?;
+    Ok(username)
+}
@@ -2,12 +2,28 @@ use std::error::Error;
 
 use sqlx::{AnyPool, Row};
 
+pub mod admin;
+pub mod audit;
+pub mod collaborator;
+pub mod organization;
 pub mod portfolio;
+pub mod slug;
 pub mod user;
 pub mod work;
 
-const TABLES_WITH_INTEGER_KEYS: &[&str] =
-    &["categories", "portfolios", "users", "work_attachments", "work_links", "work_tags", "works"];
+const TABLES_WITH_INTEGER_KEYS: &[&str] = &[
+    "audit_events",
+    "categories",
+    "organizations",
+    "ownership_transfers",
+    "portfolios",
+    "users",
+    "work_attachments",
+    "work_links",
+    "work_revisions",
+    "work_tags",
+    "works",
+];
 
 /// Since SQLite and PostgreSQL don't seem to have a way to make good primary
 /// keys that would work as desired for both databases, this function exists to
@@ -0,0 +1,350 @@
+use std::time::SystemTime;
+
+use anyhow::Context;
+use sqlx::{Any, Executor};
+
+use crate::data::audit::{AuditAction, RequestOrigin};
+use crate::data::collaborator::CollaboratorRole;
+use crate::data::organization::{
+    Organization, OrganizationChangeOutcome, OrganizationMember, OrganizationMembership,
+    OrganizationRole,
+};
+use crate::services::audit;
+
+/// Creates the organization, with the user as its only member and admin.
+pub async fn create_organization<E>(
+    conn: &mut E,
+    origin: &RequestOrigin,
+    slug: &str,
+    user_id: i32,
+    name: &str,
+) -> Result<Organization, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let current_time =
+        SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as i64;
+    let query = sqlx::query_as(
+        "INSERT INTO organizations (created_at, slug, name) VALUES ($1, $2, $3) RETURNING *",
+    );
+    let mut organization: Organization = query
+        .bind(current_time)
+        .bind(slug)
+        .bind(name)
+        .fetch_one(&mut *conn)
+        .await
+        .context("organization insert failed")?;
+
+    let query = sqlx::query(
+        "INSERT INTO organization_members (organization_id, user_id, role) VALUES ($1, $2, $3)",
+    );
+    query
+        .bind(organization.id)
+        .bind(user_id)
+        .bind(OrganizationRole::Admin)
+        .execute(&mut *conn)
+        .await
+        .context("organization admin insert failed")?;
+    audit::record(&mut *conn, origin, Some(user_id), AuditAction::OrganizationCreated, slug)
+        .await?;
+
+    organization.members = sqlx::query_as(
+        "SELECT users.username, organization_members.role FROM organization_members \
+            JOIN users ON (users.id = organization_members.user_id) \
+        WHERE organization_members.organization_id = $1",
+    )
+    .bind(organization.id)
+    .fetch_all(&mut *conn)
+    .await
+    .context("get new organization's members failed")?;
+
+    Ok(organization)
+}
+
+pub async fn get_organizations<E>(
+    conn: &E,
+    user_id: i32,
+) -> Result<Vec<OrganizationMembership>, anyhow::Error>
+where
+    for<'e> &'e E: Executor<'e, Database = Any>,
+{
+    let query = sqlx::query_as(
+        "SELECT organizations.slug, organizations.name, organization_members.role \
+        FROM organizations \
+            JOIN organization_members ON (organization_members.organization_id = organizations.id) \
+        WHERE organization_members.user_id = $1 ORDER BY organizations.name",
+    );
+    query.bind(user_id).fetch_all(conn).await.context("get organizations failed")
+}
+
+/// Returns the organization and its members, if the user is one of them.
+pub async fn get_organization<E>(
+    conn: &E,
+    slug: &str,
+    user_id: i32,
+) -> Result<Option<Organization>, anyhow::Error>
+where
+    for<'e> &'e E: Executor<'e, Database = Any>,
+{
+    let query = sqlx::query_as(
+        "SELECT organizations.* FROM organizations \
+            JOIN organization_members ON (organization_members.organization_id = organizations.id) \
+        WHERE organizations.slug = $1 AND organization_members.user_id = $2",
+    );
+    let organization: Option<Organization> = query
+        .bind(slug)
+        .bind(user_id)
+        .fetch_optional(conn)
+        .await
+        .context("get organization failed")?;
+    let Some(mut organization) = organization else {
+        return Ok(None);
+    };
+
+    let query = sqlx::query_as(
+        "SELECT users.username, organization_members.role FROM organization_members \
+            JOIN users ON (users.id = organization_members.user_id) \
+        WHERE organization_members.organization_id = $1 \
+        ORDER BY organization_members.role, users.username",
+    );
+    let members: Vec<OrganizationMember> = query
+        .bind(organization.id)
+        .fetch_all(conn)
+        .await
+        .context("get organization members failed")?;
+    organization.members = members;
+
+    Ok(Some(organization))
+}
+
+/// Adds the user with the username to the organization, or changes their role
+/// if they already are a member. Only admins can do this.
+#[allow(clippy::too_many_arguments)]
+pub async fn set_member<E>(
+    conn: &mut E,
+    origin: &RequestOrigin,
+    slug: &str,
+    user_id: i32,
+    username: &str,
+    role: OrganizationRole,
+) -> Result<OrganizationChangeOutcome, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let Some((organization_id, own_role)) = get_role(&mut *conn, slug, user_id).await? else {
+        return Ok(OrganizationChangeOutcome::NoSuchOrganization);
+    };
+    if own_role != OrganizationRole::Admin {
+        return Ok(OrganizationChangeOutcome::NotAdmin);
+    }
+    let Some(member_id) = get_user_id(&mut *conn, username).await? else {
+        return Ok(OrganizationChangeOutcome::NoSuchUser);
+    };
+    if member_id == user_id
+        && role != OrganizationRole::Admin
+        && count_admins(&mut *conn, organization_id).await? == 1
+    {
+        return Ok(OrganizationChangeOutcome::LastAdmin);
+    }
+
+    sqlx::query("DELETE FROM organization_members WHERE organization_id = $1 AND user_id = $2")
+        .bind(organization_id)
+        .bind(member_id)
+        .execute(&mut *conn)
+        .await
+        .context("previous organization role delete failed")?;
+    let query = sqlx::query(
+        "INSERT INTO organization_members (organization_id, user_id, role) VALUES ($1, $2, $3)",
+    );
+    query
+        .bind(organization_id)
+        .bind(member_id)
+        .bind(role)
+        .execute(&mut *conn)
+        .await
+        .context("organization member insert failed")?;
+
+    let audit_target = format!("{slug}/{username}");
+    let action = AuditAction::OrganizationMemberSet;
+    audit::record(&mut *conn, origin, Some(user_id), action, &audit_target).await?;
+
+    Ok(OrganizationChangeOutcome::Changed)
+}
+
+/// Removes the user with the username from the organization. Admins can
+/// remove anyone, others can only leave themselves.
+pub async fn remove_member<E>(
+    conn: &mut E,
+    origin: &RequestOrigin,
+    slug: &str,
+    user_id: i32,
+    username: &str,
+) -> Result<OrganizationChangeOutcome, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let Some((organization_id, own_role)) = get_role(&mut *conn, slug, user_id).await? else {
+        return Ok(OrganizationChangeOutcome::NoSuchOrganization);
+    };
+    let Some(member_id) = get_user_id(&mut *conn, username).await? else {
+        return Ok(OrganizationChangeOutcome::NoSuchUser);
+    };
+    if member_id != user_id && own_role != OrganizationRole::Admin {
+        return Ok(OrganizationChangeOutcome::NotAdmin);
+    }
+
+    let query = sqlx::query_as(
+        "SELECT role FROM organization_members WHERE organization_id = $1 AND user_id = $2",
+    );
+    let removed_role: Option<(OrganizationRole,)> = query
+        .bind(organization_id)
+        .bind(member_id)
+        .fetch_optional(&mut *conn)
+        .await
+        .context("organization member role fetch failed")?;
+    match removed_role {
+        None => return Ok(OrganizationChangeOutcome::NoSuchUser),
+        Some((OrganizationRole::Admin,))
+            if count_admins(&mut *conn, organization_id).await? == 1 =>
+        {
+            return Ok(OrganizationChangeOutcome::LastAdmin);
+        }
+        Some(_) => {}
+    }
+
+    sqlx::query("DELETE FROM organization_members WHERE organization_id = $1 AND user_id = $2")
+        .bind(organization_id)
+        .bind(member_id)
+        .execute(&mut *conn)
+        .await
+        .context("organization member delete failed")?;
+
+    let audit_target = format!("{slug}/{username}");
+    let action = AuditAction::OrganizationMemberRemoved;
+    audit::record(&mut *conn, origin, Some(user_id), action, &audit_target).await?;
+
+    Ok(OrganizationChangeOutcome::Changed)
+}
+
+/// Deletes the organization, if the user is one of its admins. The works and
+/// portfolios the organization owned are given to the user, so that they
+/// don't end up without owners.
+pub async fn delete_organization<E>(
+    conn: &mut E,
+    origin: &RequestOrigin,
+    slug: &str,
+    user_id: i32,
+) -> Result<OrganizationChangeOutcome, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let Some((organization_id, own_role)) = get_role(&mut *conn, slug, user_id).await? else {
+        return Ok(OrganizationChangeOutcome::NoSuchOrganization);
+    };
+    if own_role != OrganizationRole::Admin {
+        return Ok(OrganizationChangeOutcome::NotAdmin);
+    }
+
+    for (rights_table, id_column) in
+        [("work_rights", "work_id"), ("portfolio_rights", "portfolio_id")]
+    {
+        // Safety: the table names aren't from user input.
+        let owned_by_organization = format!(
+            "select {id_column} from {rights_table} where organization_id = $2 and role = $3"
+        );
+        let query = format!(
+            "DELETE FROM {rights_table} WHERE user_id = $1 AND {id_column} IN ( {owned_by_organization} )"
+        );
+        sqlx::query(&query)
+            .bind(user_id)
+            .bind(organization_id)
+            .bind(CollaboratorRole::Owner)
+            .execute(&mut *conn)
+            .await
+            .with_context(|| format!("deleting the admin's previous {rights_table} failed"))?;
+        let query = format!(
+            "INSERT INTO {rights_table} ({id_column}, user_id, role) \
+            SELECT {id_column}, $1, role FROM {rights_table} WHERE organization_id = $2 AND role = $3"
+        );
+        sqlx::query(&query)
+            .bind(user_id)
+            .bind(organization_id)
+            .bind(CollaboratorRole::Owner)
+            .execute(&mut *conn)
+            .await
+            .with_context(|| {
+                format!("giving the organization's {rights_table} to the admin failed")
+            })?;
+        sqlx::query(&format!("DELETE FROM {rights_table} WHERE organization_id = $1"))
+            .bind(organization_id)
+            .execute(&mut *conn)
+            .await
+            .with_context(|| format!("deleting the organization's {rights_table} failed"))?;
+    }
+    sqlx::query("DELETE FROM organization_members WHERE organization_id = $1")
+        .bind(organization_id)
+        .execute(&mut *conn)
+        .await
+        .context("organization members delete failed")?;
+    sqlx::query("DELETE FROM organizations WHERE id = $1")
+        .bind(organization_id)
+        .execute(&mut *conn)
+        .await
+        .context("organization delete failed")?;
+
+    audit::record(&mut *conn, origin, Some(user_id), AuditAction::OrganizationDeleted, slug)
+        .await?;
+
+    Ok(OrganizationChangeOutcome::Changed)
+}
+
+/// Returns the id of the organization and the user's role in it, if they're a
+/// member.
+async fn get_role<E>(
+    conn: &mut E,
+    slug: &str,
+    user_id: i32,
+) -> Result<Option<(i32, OrganizationRole)>, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let query = sqlx::query_as(
+        "SELECT organizations.id, organization_members.role FROM organizations \
+            JOIN organization_members ON (organization_members.organization_id = organizations.id) \
+        WHERE organizations.slug = $1 AND organization_members.user_id = $2",
+    );
+    query
+        .bind(slug)
+        .bind(user_id)
+        .fetch_optional(&mut *conn)
+        .await
+        .context("organization role fetch failed")
+}
+
+async fn count_admins<E>(conn: &mut E, organization_id: i32) -> Result<i64, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let query = sqlx::query_as(
+        "SELECT COUNT(*) FROM organization_members WHERE organization_id = $1 AND role = $2",
+    );
+    let (count,): (i64,) = query
+        .bind(organization_id)
+        .bind(OrganizationRole::Admin)
+        .fetch_one(&mut *conn)
+        .await
+        .context("organization admin count failed")?;
+    Ok(count)
+}
+
+async fn get_user_id<E>(conn: &mut E, username: &str) -> Result<Option<i32>, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let user_id: Option<(i32,)> = sqlx::query_as("SELECT id FROM users WHERE username = $1")
+        .bind(username)
+        .fetch_optional(&mut *conn)
+        .await
+        .context("member user id fetch failed")?;
+    Ok(user_id.map(|(id,)| id))
+}
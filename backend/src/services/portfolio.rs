@@ -1,86 +1,468 @@
 use std::time::SystemTime;
 
 use anyhow::Context;
-use sqlx::{Any, Executor};
+use sqlx::{Acquire, Any, Executor};
 
 use crate::array_string_types::SlugString;
+use crate::data::audit::{AuditAction, RequestOrigin};
+use crate::data::collaborator::{CollaborationTarget, CollaboratorRole};
 use crate::data::portfolio::{Portfolio, PortfolioCategory, PortfolioCategoryRow, PortfolioRow};
+use crate::data::trash::TrashedItem;
+use crate::services::audit;
+use crate::services::user::profile;
+use crate::{config, services};
 
+pub mod passwords;
+pub mod previews;
+pub mod published;
+
+/// Creates the portfolio, owned by the user. Returns None if some of the works
+/// in its categories don't exist, or the user can't see them.
 pub async fn create_portfolio<E>(
     conn: &mut E,
+    origin: &RequestOrigin,
     slug: &str,
     user_id: i32,
     new_pf: Portfolio,
     publish: bool,
-) -> Result<Portfolio, anyhow::Error>
+) -> Result<Option<Portfolio>, anyhow::Error>
 where
     for<'e> &'e mut E: Executor<'e, Database = Any>,
 {
+    let mut author = new_pf.row.author;
+    if author.is_empty() {
+        author = profile::get_default_author(&mut *conn, user_id).await?.unwrap_or_default();
+    }
+
     let current_time =
         SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as i64;
     let query = sqlx::query_as(
-        "INSERT INTO portfolios (created_at, published_at, slug, title, subtitle, author) \
-        VALUES                  ($1,         $2,           $3,   $4,    $5,       $6) \
+        "INSERT INTO portfolios (created_at, updated_at, published_at, first_published_at, slug, title, subtitle, author, publish_at, unpublish_at) \
+        VALUES                  ($1,         $2,         $3,           $4,                 $5,   $6,    $7,       $8,     $9,         $10) \
         RETURNING *",
     );
+    let published_at = if publish { Some(current_time) } else { None };
     let row: PortfolioRow = query
         .bind(current_time)
-        .bind(if publish { Some(current_time) } else { None })
+        .bind(current_time)
+        .bind(published_at)
+        .bind(published_at)
         .bind(slug)
         .bind(new_pf.row.title)
         .bind(new_pf.row.subtitle)
-        .bind(new_pf.row.author)
+        .bind(author)
+        .bind(new_pf.row.publish_at)
+        .bind(new_pf.row.unpublish_at)
         .fetch_one(&mut *conn)
         .await
         .context("portfolios insert failed")?;
 
-    let query = sqlx::query("INSERT INTO portfolio_rights (portfolio_id, user_id) VALUES ($1, $2)");
+    let query = sqlx::query(
+        "INSERT INTO portfolio_rights (portfolio_id, user_id, role) VALUES ($1, $2, $3)",
+    );
     let result = query
         .bind(row.id)
         .bind(user_id)
+        .bind(CollaboratorRole::Owner)
         .execute(&mut *conn)
         .await
         .context("portfolio_rights insert failed")?;
     assert_eq!(1, result.rows_affected());
 
-    let portfolio = update_portfolio_details(&mut *conn, row, &new_pf.categories).await?;
+    audit::record(&mut *conn, origin, Some(user_id), AuditAction::PortfolioCreated, slug).await?;
+    if publish {
+        audit::record(&mut *conn, origin, Some(user_id), AuditAction::PortfolioPublished, slug)
+            .await?;
+    }
+
+    let Some(portfolio) =
+        update_portfolio_details(&mut *conn, row, user_id, &new_pf.categories).await?
+    else {
+        return Ok(None);
+    };
+    if publish {
+        published::save_snapshot(&mut *conn, portfolio.row.id).await?;
+    }
 
-    Ok(portfolio)
+    Ok(Some(portfolio))
 }
 
+/// Saves the changes to the draft of the portfolio. If the portfolio is
+/// already published, the published version doesn't change until
+/// [publish_portfolio] is called. Returns None if the user isn't an owner or
+/// editor of a portfolio with the slug, or can't add some of the works to it.
 #[allow(clippy::too_many_arguments)]
 pub async fn update_portfolio<E>(
     conn: &mut E,
+    origin: &RequestOrigin,
     original_slug: &str,
     user_id: i32,
     updated_pf: Portfolio,
     publish: bool,
-) -> Result<Portfolio, anyhow::Error>
+) -> Result<Option<Portfolio>, anyhow::Error>
 where
     for<'e> &'e mut E: Executor<'e, Database = Any>,
 {
+    // For keeping the original publication time, and telling apart publishing
+    // and unpublishing in the audit log
+    let query = sqlx::query_as(
+        "SELECT published_at FROM portfolios WHERE slug = $1 AND deleted_at IS NULL",
+    );
+    let previously_published: Option<(Option<i64>,)> = query
+        .bind(original_slug)
+        .fetch_optional(&mut *conn)
+        .await
+        .context("portfolio published_at fetch failed")?;
+    let previously_published_at = previously_published.and_then(|(published_at,)| published_at);
+    let was_published = previously_published_at.is_some();
+
     let current_time =
         SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as i64;
+    let published_at = if publish { previously_published_at.or(Some(current_time)) } else { None };
     let query = sqlx::query_as(
-        "UPDATE portfolios SET published_at = $1, slug = $2, title = $3, subtitle = $4, author = $5 \
-        WHERE slug = $6 AND id IN ( select portfolio_id from portfolio_rights where user_id = $7 ) \
+        "UPDATE portfolios SET published_at = $1, first_published_at = COALESCE(first_published_at, $2), \
+            updated_at = $3, slug = $4, title = $5, subtitle = $6, author = $7, \
+            publish_at = $8, unpublish_at = $9 \
+        WHERE slug = $10 AND deleted_at IS NULL \
+            AND id IN ( select portfolio_id from effective_portfolio_rights where user_id = $11 and role in ($12, $13) ) \
         RETURNING *",
     );
-    let row: PortfolioRow = query
-        .bind(if publish { Some(current_time) } else { None })
+    let row: Option<PortfolioRow> = query
+        .bind(published_at)
+        .bind(published_at)
+        .bind(current_time)
         .bind(&updated_pf.row.slug)
         .bind(updated_pf.row.title)
         .bind(updated_pf.row.subtitle)
         .bind(updated_pf.row.author)
+        .bind(updated_pf.row.publish_at)
+        .bind(updated_pf.row.unpublish_at)
         .bind(original_slug)
         .bind(user_id)
-        .fetch_one(&mut *conn)
+        .bind(CollaboratorRole::Owner)
+        .bind(CollaboratorRole::Editor)
+        .fetch_optional(&mut *conn)
         .await
         .context("portfolios update failed")?;
+    let Some(row) = row else {
+        return Ok(None);
+    };
+
+    let slug = row.slug.0.as_str();
+    let target = CollaborationTarget::Portfolio;
+    services::slug::record_change(&mut *conn, target, row.id, original_slug, slug).await?;
+    audit::record(&mut *conn, origin, Some(user_id), AuditAction::PortfolioUpdated, slug).await?;
+    if publish != was_published {
+        let action = if publish {
+            AuditAction::PortfolioPublished
+        } else {
+            AuditAction::PortfolioUnpublished
+        };
+        audit::record(&mut *conn, origin, Some(user_id), action, slug).await?;
+    }
+
+    let Some(portfolio) =
+        update_portfolio_details(&mut *conn, row, user_id, &updated_pf.categories).await?
+    else {
+        return Ok(None);
+    };
+    if publish && !was_published {
+        published::save_snapshot(&mut *conn, portfolio.row.id).await?;
+    } else if !publish && was_published {
+        published::delete_snapshot(&mut *conn, portfolio.row.id).await?;
+    }
+
+    Ok(Some(portfolio))
+}
+
+/// Creates an unpublished copy of the portfolio's draft under the new slug,
+/// owned by the user, with the same categories listing the same works in the
+/// same order. The publish schedule, password and preview links aren't
+/// copied. Returns None if the user isn't an owner or editor of a portfolio
+/// with the slug, or can't see some of its works.
+pub async fn clone_portfolio<E>(
+    conn: &mut E,
+    origin: &RequestOrigin,
+    slug: &str,
+    new_slug: &str,
+    user_id: i32,
+) -> Result<Option<Portfolio>, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let query = sqlx::query_as(
+        "SELECT * FROM portfolios \
+        WHERE slug = $1 AND deleted_at IS NULL \
+            AND id IN ( select portfolio_id from effective_portfolio_rights where user_id = $2 and role in ($3, $4) )",
+    );
+    let row: Option<PortfolioRow> = query
+        .bind(slug)
+        .bind(user_id)
+        .bind(CollaboratorRole::Owner)
+        .bind(CollaboratorRole::Editor)
+        .fetch_optional(&mut *conn)
+        .await
+        .context("get portfolio to clone failed")?;
+    let Some(row) = row else {
+        return Ok(None);
+    };
+    let mut original = fetch_portfolio_details(&mut *conn, row).await?;
+    original.row.publish_at = None;
+    original.row.unpublish_at = None;
+
+    create_portfolio(&mut *conn, origin, new_slug, user_id, original, false).await
+}
+
+/// Replaces the published version of the portfolio and its works with the
+/// current drafts, publishing the portfolio if it wasn't already. Returns
+/// None if the user isn't an owner or editor of a portfolio with the slug.
+pub async fn publish_portfolio<E>(
+    conn: &mut E,
+    origin: &RequestOrigin,
+    slug: &str,
+    user_id: i32,
+) -> Result<Option<Portfolio>, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let current_time =
+        SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as i64;
+    let query = sqlx::query_as(
+        "UPDATE portfolios SET published_at = COALESCE(published_at, $1), \
+            first_published_at = COALESCE(first_published_at, $2), publish_at = NULL \
+        WHERE slug = $3 AND deleted_at IS NULL \
+            AND id IN ( select portfolio_id from effective_portfolio_rights where user_id = $4 and role in ($5, $6) ) \
+        RETURNING id",
+    );
+    let portfolio_id: Option<(i32,)> = query
+        .bind(current_time)
+        .bind(current_time)
+        .bind(slug)
+        .bind(user_id)
+        .bind(CollaboratorRole::Owner)
+        .bind(CollaboratorRole::Editor)
+        .fetch_optional(&mut *conn)
+        .await
+        .context("portfolio publish failed")?;
+    let Some((portfolio_id,)) = portfolio_id else {
+        return Ok(None);
+    };
+
+    let portfolio = published::save_snapshot(&mut *conn, portfolio_id).await?;
+    audit::record(&mut *conn, origin, Some(user_id), AuditAction::PortfolioPublished, slug).await?;
+
+    Ok(Some(portfolio))
+}
+
+/// Moves the portfolio to the trash, from where it can be restored until it's
+/// purged by [purge_deleted_portfolios]. Returns false if the user doesn't
+/// own a portfolio with the slug.
+pub async fn delete_portfolio<E>(
+    conn: &mut E,
+    origin: &RequestOrigin,
+    slug: &str,
+    user_id: i32,
+) -> Result<bool, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let current_time =
+        SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as i64;
+    let query = sqlx::query(
+        "UPDATE portfolios SET deleted_at = $1 \
+        WHERE slug = $2 AND deleted_at IS NULL \
+            AND id IN ( select portfolio_id from effective_portfolio_rights where user_id = $3 and role = $4 )",
+    );
+    let result = query
+        .bind(current_time)
+        .bind(slug)
+        .bind(user_id)
+        .bind(CollaboratorRole::Owner)
+        .execute(&mut *conn)
+        .await
+        .context("portfolio soft delete failed")?;
+    if result.rows_affected() == 0 {
+        return Ok(false);
+    }
+
+    audit::record(&mut *conn, origin, Some(user_id), AuditAction::PortfolioDeleted, slug).await?;
+
+    Ok(true)
+}
+
+/// Takes the portfolio back out of the trash. Returns false if the user
+/// doesn't own a deleted portfolio with the slug.
+pub async fn restore_portfolio<E>(
+    conn: &mut E,
+    origin: &RequestOrigin,
+    slug: &str,
+    user_id: i32,
+) -> Result<bool, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let query = sqlx::query(
+        "UPDATE portfolios SET deleted_at = NULL \
+        WHERE slug = $1 AND deleted_at IS NOT NULL \
+            AND id IN ( select portfolio_id from effective_portfolio_rights where user_id = $2 and role = $3 )",
+    );
+    let result = query
+        .bind(slug)
+        .bind(user_id)
+        .bind(CollaboratorRole::Owner)
+        .execute(&mut *conn)
+        .await
+        .context("portfolio restore failed")?;
+    if result.rows_affected() == 0 {
+        return Ok(false);
+    }
+
+    audit::record(&mut *conn, origin, Some(user_id), AuditAction::PortfolioRestored, slug).await?;
+
+    Ok(true)
+}
+
+pub async fn get_deleted_portfolios<E>(
+    conn: &E,
+    user_id: i32,
+) -> Result<Vec<TrashedItem>, anyhow::Error>
+where
+    for<'e> &'e E: Executor<'e, Database = Any>,
+{
+    let query = sqlx::query_as(
+        "SELECT slug, title, deleted_at, deleted_at + $1 AS purge_at FROM portfolios \
+        WHERE deleted_at IS NOT NULL \
+            AND id IN ( select portfolio_id from effective_portfolio_rights where user_id = $2 and role = $3 ) \
+        ORDER BY deleted_at DESC",
+    );
+    query
+        .bind(config::trash_retention_seconds().map(|seconds| seconds as i64))
+        .bind(user_id)
+        .bind(CollaboratorRole::Owner)
+        .fetch_all(conn)
+        .await
+        .context("get deleted portfolios failed")
+}
+
+/// Permanently deletes the portfolios which were moved to the trash before the
+/// given time, along with their categories. The works in the categories are
+/// not deleted. Each portfolio is purged in its own transaction.
+pub async fn purge_deleted_portfolios<E>(
+    conn: &E,
+    before_timestamp: SystemTime,
+) -> Result<(), anyhow::Error>
+where
+    for<'e> &'e E: Executor<'e, Database = Any> + Acquire<'e, Database = Any>,
+{
+    let before_timestamp =
+        before_timestamp.duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as i64;
+    let portfolio_ids: Vec<(i32,)> =
+        sqlx::query_as("SELECT id FROM portfolios WHERE deleted_at < $1")
+            .bind(before_timestamp)
+            .fetch_all(conn)
+            .await
+            .context("get portfolios to purge failed")?;
+
+    for (portfolio_id,) in portfolio_ids {
+        let mut tx = conn.begin().await.context("beginning a transaction failed")?;
+        purge_portfolio(&mut *tx, portfolio_id).await?;
+        tx.commit().await.context("committing the purge failed")?;
+    }
+
+    Ok(())
+}
+
+async fn purge_portfolio<E>(conn: &mut E, portfolio_id: i32) -> Result<(), anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    sqlx::query("DELETE FROM works_in_categories WHERE category_id IN ( select id from categories where portfolio_id = $1 )")
+        .bind(portfolio_id)
+        .execute(&mut *conn)
+        .await
+        .context("deleting the portfolio's works_in_categories failed")?;
+    published::delete_snapshot(&mut *conn, portfolio_id).await?;
+    for table in [
+        "categories",
+        "portfolio_rights",
+        "ownership_transfers",
+        "portfolio_previews",
+        "portfolio_viewer_tokens",
+        "slug_history",
+    ] {
+        sqlx::query(&format!("DELETE FROM {table} WHERE portfolio_id = $1"))
+            .bind(portfolio_id)
+            .execute(&mut *conn)
+            .await
+            .with_context(|| format!("deleting the portfolio's {table} failed"))?;
+    }
+    sqlx::query("DELETE FROM portfolios WHERE id = $1")
+        .bind(portfolio_id)
+        .execute(&mut *conn)
+        .await
+        .context("portfolio delete failed")?;
+    Ok(())
+}
+
+/// Publishes the portfolios whose `publish_at` has passed, and unpublishes the
+/// ones whose `unpublish_at` has, recording the changes in the audit log like
+/// they'd been done by hand. The unpublishing is already honored by
+/// [get_portfolio] before this runs, but publishing waits for this, since
+/// it's when the draft is copied into the published version.
+///
+/// Also publishes the drafts of published portfolios which don't have a
+/// published version yet, i.e. the ones published before there were drafts.
+pub async fn apply_publish_schedules<E>(conn: &mut E) -> Result<(), anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let current_time =
+        SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as i64;
+    let origin = RequestOrigin::server();
+
+    let query = sqlx::query_as(
+        "UPDATE portfolios SET published_at = publish_at, \
+            first_published_at = COALESCE(first_published_at, publish_at), publish_at = NULL \
+        WHERE publish_at <= $1 AND published_at IS NULL AND deleted_at IS NULL \
+        RETURNING id, slug",
+    );
+    let published: Vec<(i32, String)> = query
+        .bind(current_time)
+        .fetch_all(&mut *conn)
+        .await
+        .context("scheduled portfolio publish failed")?;
+    for (portfolio_id, slug) in published {
+        published::save_snapshot(&mut *conn, portfolio_id).await?;
+        audit::record(&mut *conn, &origin, None, AuditAction::PortfolioPublished, &slug).await?;
+    }
 
-    let portfolio = update_portfolio_details(&mut *conn, row, &updated_pf.categories).await?;
+    let query = sqlx::query_as(
+        "UPDATE portfolios SET published_at = NULL, unpublish_at = NULL \
+        WHERE unpublish_at <= $1 AND published_at IS NOT NULL AND deleted_at IS NULL \
+        RETURNING id, slug",
+    );
+    let unpublished: Vec<(i32, String)> = query
+        .bind(current_time)
+        .fetch_all(&mut *conn)
+        .await
+        .context("scheduled portfolio unpublish failed")?;
+    for (portfolio_id, slug) in unpublished {
+        published::delete_snapshot(&mut *conn, portfolio_id).await?;
+        audit::record(&mut *conn, &origin, None, AuditAction::PortfolioUnpublished, &slug).await?;
+    }
+
+    let query = sqlx::query_as(
+        "SELECT id FROM portfolios \
+        WHERE published_at IS NOT NULL AND deleted_at IS NULL \
+            AND id NOT IN ( select portfolio_id from published_portfolios )",
+    );
+    let unsnapshotted: Vec<(i32,)> =
+        query.fetch_all(&mut *conn).await.context("get unsnapshotted portfolios failed")?;
+    for (portfolio_id,) in unsnapshotted {
+        published::save_snapshot(&mut *conn, portfolio_id).await?;
+    }
 
-    Ok(portfolio)
+    Ok(())
 }
 
 pub async fn get_portfolios<E>(conn: &E, user_id: i32) -> Result<Vec<PortfolioRow>, anyhow::Error>
@@ -88,7 +470,9 @@ where
     for<'e> &'e E: Executor<'e, Database = Any>,
 {
     sqlx::query_as(
-        "SELECT * FROM portfolios JOIN portfolio_rights ON (id = portfolio_id) WHERE user_id = $1",
+        "SELECT * FROM portfolios \
+        WHERE deleted_at IS NULL \
+            AND id IN ( select portfolio_id from effective_portfolio_rights where user_id = $1 )",
     )
     .bind(user_id)
     .fetch_all(conn)
@@ -96,42 +480,84 @@ where
     .context("get all portfolios failed")
 }
 
+/// Returns the draft of the portfolio to its collaborators and anyone with one
+/// of its preview tokens, and the published version of it to anyone else. If
+/// the portfolio is password-protected, the published version needs a viewer
+/// token.
 pub async fn get_portfolio<E>(
     conn: &E,
     slug: &str,
     user_id: Option<i32>,
+    preview_token: Option<&str>,
+    viewer_token: Option<&str>,
 ) -> Result<Option<Portfolio>, anyhow::Error>
 where
-    for<'e> &'e E: Executor<'e, Database = Any>,
+    for<'e> &'e E: Executor<'e, Database = Any> + Acquire<'e, Database = Any>,
 {
+    let current_time =
+        SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as i64;
     let query = sqlx::query_as(
         "SELECT * FROM portfolios \
-        JOIN portfolio_rights ON (id = portfolio_id) \
-        WHERE slug = $1 and (user_id = $2 or published_at is not null)",
+        WHERE slug = $1 AND deleted_at IS NULL \
+            AND (id IN ( select portfolio_id from effective_portfolio_rights where user_id = $2 ) \
+                OR id IN ( select portfolio_previews.portfolio_id from portfolio_previews \
+                    join portfolios on (portfolios.id = portfolio_previews.portfolio_id) \
+                    where portfolio_previews.token = $3 and portfolios.deleted_at is null \
+                        and (portfolio_previews.expires_at is null or portfolio_previews.expires_at > $4) ))",
     );
     let row: Option<PortfolioRow> = query
         .bind(slug)
         .bind(user_id)
+        .bind(preview_token)
+        .bind(current_time)
         .fetch_optional(conn)
         .await
         .context("get all portfolios failed")?;
 
     if let Some(row) = row {
-        let portfolio = fetch_portfolio_details(conn, row).await?;
+        let mut conn = conn.acquire().await.context("acquiring a db connection failed")?;
+        let portfolio = fetch_portfolio_details(&mut *conn, row).await?;
         Ok(Some(portfolio))
     } else {
-        Ok(None)
+        published::get_published_portfolio(conn, slug, viewer_token).await
     }
 }
 
-async fn fetch_portfolio_details<E>(conn: &E, row: PortfolioRow) -> Result<Portfolio, anyhow::Error>
+/// Returns the id of the portfolio, if the user owns it.
+pub async fn get_owned_portfolio_id<E>(
+    conn: &mut E,
+    slug: &str,
+    user_id: i32,
+) -> Result<Option<i32>, anyhow::Error>
 where
-    for<'e> &'e E: Executor<'e, Database = Any>,
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let query = sqlx::query_as(
+        "SELECT id FROM portfolios \
+        WHERE slug = $1 AND deleted_at IS NULL \
+            AND id IN ( select portfolio_id from effective_portfolio_rights where user_id = $2 and role = $3 )",
+    );
+    let portfolio_id: Option<(i32,)> = query
+        .bind(slug)
+        .bind(user_id)
+        .bind(CollaboratorRole::Owner)
+        .fetch_optional(&mut *conn)
+        .await
+        .context("owned portfolio id fetch failed")?;
+    Ok(portfolio_id.map(|(id,)| id))
+}
+
+async fn fetch_portfolio_details<E>(
+    conn: &mut E,
+    row: PortfolioRow,
+) -> Result<Portfolio, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
 {
     let categories: Vec<PortfolioCategoryRow> =
         sqlx::query_as("SELECT * FROM categories WHERE portfolio_id = $1")
             .bind(row.id)
-            .fetch_all(conn)
+            .fetch_all(&mut *conn)
             .await
             .context("get categories to fill out portfolio details failed")?;
 
@@ -139,12 +565,12 @@ where
         "SELECT categories.id, works.slug FROM works \
         JOIN works_in_categories ON (works.id = works_in_categories.work_id) \
         JOIN categories ON (works_in_categories.category_id = categories.id) \
-        WHERE categories.portfolio_id = $1 \
+        WHERE categories.portfolio_id = $1 AND works.deleted_at IS NULL \
         ORDER BY order_number ASC",
     );
     let all_work_slugs: Vec<(i32, SlugString)> = query
         .bind(row.id)
-        .fetch_all(conn)
+        .fetch_all(&mut *conn)
         .await
         .context("get relevant works' slugs to fill out portfolio details failed")?;
 
@@ -166,14 +592,73 @@ where
     Ok(Portfolio { row, categories })
 }
 
+/// Replaces the categories of the portfolio. Returns None if some of the
+/// works don't exist, or the user can't add them to the portfolio.
 async fn update_portfolio_details<E>(
     conn: &mut E,
     row: PortfolioRow,
+    user_id: i32,
     input_categories: &[PortfolioCategory],
-) -> Result<Portfolio, anyhow::Error>
+) -> Result<Option<Portfolio>, anyhow::Error>
 where
     for<'e> &'e mut E: Executor<'e, Database = Any>,
 {
+    // Collect all the slugs referenced by the client and get their respective
+    // ids. Works can only be added by users who can see them, but the ones
+    // already in the portfolio can stay there.
+    let all_slugs: Vec<&SlugString> =
+        input_categories.iter().flat_map(|c| c.work_slugs.iter()).collect();
+    let mut slug_id_pairs = Vec::with_capacity(all_slugs.len());
+    for slug in all_slugs {
+        let query = sqlx::query_as(
+            "SELECT id FROM works WHERE slug = $1 AND deleted_at IS NULL \
+                AND (id IN ( select work_id from effective_work_rights where user_id = $2 ) \
+                    OR id IN ( select works_in_categories.work_id from works_in_categories \
+                        join categories on (categories.id = works_in_categories.category_id) \
+                        where categories.portfolio_id = $3 ))",
+        );
+        let id: Option<(i32,)> = query
+            .bind(slug)
+            .bind(user_id)
+            .bind(row.id)
+            .fetch_optional(&mut *conn)
+            .await
+            .context("get work id by slug failed")?;
+        let Some((id,)) = id else {
+            return Ok(None);
+        };
+        slug_id_pairs.push((*slug, id));
+    }
+
+    // Works in the trash aren't shown to the client, so they're put back
+    // where they were, after the same work as before, to keep them in the
+    // portfolio when they're restored
+    let query = sqlx::query_as(
+        "SELECT categories.title, works_in_categories.work_id, works.deleted_at \
+        FROM works_in_categories \
+            JOIN categories ON (categories.id = works_in_categories.category_id) \
+            JOIN works ON (works.id = works_in_categories.work_id) \
+        WHERE categories.portfolio_id = $1 \
+        ORDER BY works_in_categories.order_number ASC",
+    );
+    let placements: Vec<(String, i32, Option<i64>)> = query
+        .bind(row.id)
+        .fetch_all(&mut *conn)
+        .await
+        .context("get the portfolio's works in categories failed")?;
+    // (category title, the previous work not in the trash, trashed work id)
+    let mut trashed_placements: Vec<(String, Option<i32>, i32)> = Vec::new();
+    let mut previous_work: Option<(&str, i32)> = None;
+    for (title, work_id, deleted_at) in &placements {
+        let previous_id =
+            previous_work.filter(|&(previous_title, _)| previous_title == title).map(|(_, id)| id);
+        if deleted_at.is_some() {
+            trashed_placements.push((title.clone(), previous_id, *work_id));
+        } else {
+            previous_work = Some((title, *work_id));
+        }
+    }
+
     sqlx::query("DELETE FROM categories WHERE portfolio_id = $1")
         .bind(row.id)
         .execute(&mut *conn)
@@ -196,19 +681,6 @@ where
         category_rows.push(new_category);
     }
 
-    // Collect all the slugs referenced by the client and get their respective ids
-    let all_slugs: Vec<&SlugString> =
-        input_categories.iter().flat_map(|c| c.work_slugs.iter()).collect();
-    let mut slug_id_pairs = Vec::with_capacity(all_slugs.len());
-    for slug in all_slugs {
-        let (id,): (i32,) = sqlx::query_as("SELECT id FROM works WHERE slug = $1")
-            .bind(slug)
-            .fetch_one(&mut *conn)
-            .await
-            .context("get work id by slug failed")?;
-        slug_id_pairs.push((*slug, id));
-    }
-
     // Collect up the (category_id, work_id) pairs to insert, matching categories by title and then translating the slugs into work ids
     let mut category_work_pairs: Vec<(i32, i32)> = Vec::with_capacity(slug_id_pairs.len());
     for category in &category_rows {
@@ -220,8 +692,32 @@ where
         }
     }
 
-    // Insert the category work pairs
-    for (i, (category_id, work_id)) in category_work_pairs.iter().enumerate() {
+    // Insert the category work pairs, with the trashed works among them
+    let mut inserted_pairs: Vec<(i32, i32)> = Vec::with_capacity(category_work_pairs.len());
+    for category in &category_rows {
+        let trashed_after = |previous_id: Option<i32>| {
+            trashed_placements
+                .iter()
+                .filter(move |(title, previous, _)| {
+                    *title == category.title && *previous == previous_id
+                })
+                .map(|&(_, _, work_id)| (category.id, work_id))
+        };
+        inserted_pairs.extend(trashed_after(None));
+        for &(category_id, work_id) in &category_work_pairs {
+            if category_id == category.id {
+                inserted_pairs.push((category_id, work_id));
+                inserted_pairs.extend(trashed_after(Some(work_id)));
+            }
+        }
+        // The works they came after were removed from the category
+        for (title, _, work_id) in &trashed_placements {
+            if *title == category.title && !inserted_pairs.contains(&(category.id, *work_id)) {
+                inserted_pairs.push((category.id, *work_id));
+            }
+        }
+    }
+    for (i, (category_id, work_id)) in inserted_pairs.iter().enumerate() {
         sqlx::query("INSERT INTO works_in_categories (category_id, work_id, order_number) VALUES ($1, $2, $3)")
             .bind(category_id)
             .bind(work_id)
@@ -247,5 +743,5 @@ where
         })
         .collect();
 
-    Ok(Portfolio { row, categories })
+    Ok(Some(Portfolio { row, categories }))
 }
@@ -0,0 +1,284 @@
+//! Access passwords of portfolios. The published version of a
+//! password-protected portfolio, and of the works and files in it, are only
+//! shown to readers with a viewer token, which they get for the password.
+//! Collaborators and readers with a preview token see the draft as usual.
+
+use core::num::NonZeroU32;
+use std::time::SystemTime;
+
+use anyhow::Context;
+use arrayvec::ArrayVec;
+use data_encoding::BASE64;
+use ring::rand::{SecureRandom, SystemRandom};
+use sqlx::{Any, Executor};
+
+use crate::array_string_types::UuidString;
+use crate::config;
+use crate::data::audit::{AuditAction, RequestOrigin};
+use crate::data::portfolio::PortfolioViewerToken;
+use crate::services::{audit, user};
+
+/// Sets the password readers need to see the portfolio, or removes it if
+/// None. The previously given viewer tokens stop working. Returns false if
+/// the user doesn't own a portfolio with the slug.
+pub async fn set_password<E>(
+    conn: &mut E,
+    origin: &RequestOrigin,
+    slug: &str,
+    user_id: i32,
+    password: Option<&str>,
+) -> Result<bool, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let Some(portfolio_id) = super::get_owned_portfolio_id(&mut *conn, slug, user_id).await? else {
+        return Ok(false);
+    };
+
+    let (key_base64, salt_base64, iterations) = if let Some(password) = password {
+        let mut salt_bytes = [0u8; user::SALT_BYTES_LEN];
+        SystemRandom::new()
+            .fill(&mut salt_bytes)
+            .expect("system random should be able to generate random bytes");
+        let salt = user::Salt::try_from(&salt_bytes[..]).unwrap();
+        let iterations = config::pbkdf2_iterations();
+        let key_bytes = user::derive_password_key(iterations, salt, password).await?;
+        (Some(BASE64.encode(&key_bytes)), Some(BASE64.encode(&salt_bytes)), Some(iterations))
+    } else {
+        (None, None, None)
+    };
+    let query = sqlx::query(
+        "UPDATE portfolios \
+        SET access_password_key_base64 = $1, access_salt_base64 = $2, access_pbkdf2_iterations = $3 \
+        WHERE id = $4",
+    );
+    query
+        .bind(key_base64)
+        .bind(salt_base64)
+        .bind(iterations.map(|iterations| iterations.get() as i32))
+        .bind(portfolio_id)
+        .execute(&mut *conn)
+        .await
+        .context("portfolio access password update failed")?;
+    sqlx::query("DELETE FROM portfolio_viewer_tokens WHERE portfolio_id = $1")
+        .bind(portfolio_id)
+        .execute(&mut *conn)
+        .await
+        .context("deleting the portfolio's viewer tokens failed")?;
+
+    audit::record(&mut *conn, origin, Some(user_id), AuditAction::PortfolioPasswordChanged, slug)
+        .await?;
+
+    Ok(true)
+}
+
+/// Returns a viewer token for the published, password-protected portfolio, if
+/// the password is correct.
+pub async fn unlock<E>(
+    conn: &mut E,
+    slug: &str,
+    password: &str,
+) -> Result<Option<PortfolioViewerToken>, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let query = sqlx::query_as(
+        "SELECT id, access_password_key_base64, access_salt_base64, access_pbkdf2_iterations \
+        FROM portfolios \
+        WHERE slug = $1 AND deleted_at IS NULL AND published_at IS NOT NULL \
+            AND access_password_key_base64 IS NOT NULL",
+    );
+    let portfolio: Option<(i32, String, String, i32)> = query
+        .bind(slug)
+        .fetch_optional(&mut *conn)
+        .await
+        .context("get portfolio to unlock failed")?;
+    let Some((portfolio_id, key_base64, salt_base64, iterations)) = portfolio else {
+        user::dummy_password_derivation(password).await?;
+        return Ok(None);
+    };
+
+    let salt_bytes = BASE64.decode(salt_base64.as_bytes()).context("invalid access salt")?;
+    let salt = user::Salt::try_from(&salt_bytes[..]).context("access salt is too long")?;
+    let key_bytes = BASE64.decode(key_base64.as_bytes()).context("invalid access key")?;
+    let key_bytes = ArrayVec::try_from(&key_bytes[..]).context("access key is too long")?;
+    let iterations = NonZeroU32::new(iterations as u32).context("zero access iterations")?;
+    if !user::verify_password_key(iterations, salt, password, key_bytes).await? {
+        return Ok(None);
+    }
+
+    let current_time =
+        SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as i64;
+    let expires_at = current_time + config::portfolio_viewer_token_expiration_seconds() as i64;
+    let query = sqlx::query_as(
+        "INSERT INTO portfolio_viewer_tokens (token, portfolio_id, expires_at) VALUES ($1, $2, $3) \
+        RETURNING token, expires_at",
+    );
+    let viewer_token = query
+        .bind(&UuidString::generate())
+        .bind(portfolio_id)
+        .bind(expires_at)
+        .fetch_one(&mut *conn)
+        .await
+        .context("portfolio viewer token insert failed")?;
+
+    Ok(Some(viewer_token))
+}
+
+/// Returns true if the portfolio is published and password-protected. Used
+/// for telling readers they need the password, rather than that the
+/// portfolio doesn't exist.
+pub async fn is_portfolio_locked<E>(conn: &E, slug: &str) -> Result<bool, anyhow::Error>
+where
+    for<'e> &'e E: Executor<'e, Database = Any>,
+{
+    let current_time =
+        SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as i64;
+    let query = sqlx::query_as(
+        "SELECT COUNT(*) FROM portfolios \
+        WHERE slug = $1 AND deleted_at IS NULL AND published_at IS NOT NULL \
+            AND (unpublish_at IS NULL OR unpublish_at > $2) \
+            AND access_password_key_base64 IS NOT NULL",
+    );
+    let (count,): (i64,) = query
+        .bind(slug)
+        .bind(current_time)
+        .fetch_one(conn)
+        .await
+        .context("portfolio lock check failed")?;
+    Ok(count > 0)
+}
+
+/// Returns true if the work is in a published, password-protected portfolio.
+/// Like [is_portfolio_locked], only used after the work wasn't found
+/// otherwise.
+pub async fn is_work_locked<E>(conn: &E, slug: &str) -> Result<bool, anyhow::Error>
+where
+    for<'e> &'e E: Executor<'e, Database = Any>,
+{
+    let current_time =
+        SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as i64;
+    let query = sqlx::query_as(
+        "SELECT COUNT(*) FROM published_works \
+            JOIN portfolios ON (portfolios.id = published_works.portfolio_id) \
+        WHERE published_works.slug = $1 AND portfolios.deleted_at IS NULL \
+            AND portfolios.published_at IS NOT NULL \
+            AND (portfolios.unpublish_at IS NULL OR portfolios.unpublish_at > $2) \
+            AND portfolios.access_password_key_base64 IS NOT NULL",
+    );
+    let (count,): (i64,) = query
+        .bind(slug)
+        .bind(current_time)
+        .fetch_one(conn)
+        .await
+        .context("work lock check failed")?;
+    Ok(count > 0)
+}
+
+/// Returns true if the file belongs to works which are only published in
+/// password-protected portfolios, and the reader isn't a collaborator, and
+/// doesn't have a viewer or preview token for any of those portfolios. The
+/// files of works which aren't published at all aren't locked, since their
+/// uuids are only shown to the collaborators. A file shared by cloned works is
+/// locked only if it's locked through every one of them.
+pub async fn is_file_locked<E>(
+    conn: &E,
+    file_uuid: &str,
+    user_id: Option<i32>,
+    preview_token: Option<&str>,
+    viewer_token: Option<&str>,
+) -> Result<bool, anyhow::Error>
+where
+    for<'e> &'e E: Executor<'e, Database = Any>,
+{
+    let query = sqlx::query_as(
+        "SELECT DISTINCT sharers.work_id FROM big_file_parts \
+            JOIN work_attachments owner ON (owner.id = big_file_parts.work_attachment_id) \
+            JOIN work_attachments sharers ON (sharers.big_file_uuid = owner.big_file_uuid \
+                OR sharers.id = owner.id) \
+        WHERE big_file_parts.uuid = $1",
+    );
+    let work_ids: Vec<(i32,)> =
+        query.bind(file_uuid).fetch_all(conn).await.context("get file's works failed")?;
+    if work_ids.is_empty() {
+        return Ok(false);
+    }
+    for (work_id,) in work_ids {
+        if !is_work_file_locked(conn, work_id, user_id, preview_token, viewer_token).await? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+async fn is_work_file_locked<E>(
+    conn: &E,
+    work_id: i32,
+    user_id: Option<i32>,
+    preview_token: Option<&str>,
+    viewer_token: Option<&str>,
+) -> Result<bool, anyhow::Error>
+where
+    for<'e> &'e E: Executor<'e, Database = Any>,
+{
+    let current_time =
+        SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as i64;
+    let query = sqlx::query_as(
+        "SELECT portfolios.access_password_key_base64 FROM published_works \
+            JOIN portfolios ON (portfolios.id = published_works.portfolio_id) \
+        WHERE published_works.work_id = $1 AND portfolios.deleted_at IS NULL \
+            AND portfolios.published_at IS NOT NULL \
+            AND (portfolios.unpublish_at IS NULL OR portfolios.unpublish_at > $2)",
+    );
+    let passwords: Vec<(Option<String>,)> = query
+        .bind(work_id)
+        .bind(current_time)
+        .fetch_all(conn)
+        .await
+        .context("get file's portfolios failed")?;
+    if passwords.is_empty() || passwords.iter().any(|(password,)| password.is_none()) {
+        return Ok(false);
+    }
+
+    let query = sqlx::query_as(
+        "SELECT COUNT(*) FROM works \
+        WHERE id = $1 AND (id IN ( select work_id from effective_work_rights where user_id = $2 ) \
+            OR id IN ( select works_in_categories.work_id from works_in_categories \
+                join categories on (categories.id = works_in_categories.category_id) \
+                where categories.portfolio_id IN ( select portfolio_id from effective_portfolio_rights where user_id = $3 ) \
+                    or categories.portfolio_id IN ( select portfolio_previews.portfolio_id from portfolio_previews \
+                        join portfolios on (portfolios.id = portfolio_previews.portfolio_id) \
+                        where portfolio_previews.token = $4 and portfolios.deleted_at is null \
+                            and (portfolio_previews.expires_at is null or portfolio_previews.expires_at > $5) ) ) \
+            OR id IN ( select published_works.work_id from published_works \
+                join portfolio_viewer_tokens on (portfolio_viewer_tokens.portfolio_id = published_works.portfolio_id) \
+                where portfolio_viewer_tokens.token = $6 and portfolio_viewer_tokens.expires_at > $7 ))",
+    );
+    let (count,): (i64,) = query
+        .bind(work_id)
+        .bind(user_id)
+        .bind(user_id)
+        .bind(preview_token)
+        .bind(current_time)
+        .bind(viewer_token)
+        .bind(current_time)
+        .fetch_one(conn)
+        .await
+        .context("file access check failed")?;
+    Ok(count == 0)
+}
+
+/// Deletes the viewer tokens which have expired.
+pub async fn remove_expired_viewer_tokens<E>(conn: &mut E) -> Result<(), anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let current_time =
+        SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as i64;
+    sqlx::query("DELETE FROM portfolio_viewer_tokens WHERE expires_at < $1")
+        .bind(current_time)
+        .execute(&mut *conn)
+        .await
+        .context("expired portfolio viewer tokens delete failed")?;
+    Ok(())
+}
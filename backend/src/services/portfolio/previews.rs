@@ -0,0 +1,121 @@
+//! Secret preview links, which show the draft of a portfolio and the works in
+//! it to anyone with the token, whether the portfolio is published or not.
+
+use std::time::SystemTime;
+
+use anyhow::Context;
+use sqlx::{Acquire, Any, Executor};
+
+use crate::array_string_types::UuidString;
+use crate::data::audit::{AuditAction, RequestOrigin};
+use crate::data::portfolio::PortfolioPreview;
+use crate::services::audit;
+
+/// Returns None if the user doesn't own a portfolio with the slug.
+pub async fn create_preview<E>(
+    conn: &mut E,
+    origin: &RequestOrigin,
+    slug: &str,
+    user_id: i32,
+    expires_at: Option<i64>,
+) -> Result<Option<PortfolioPreview>, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let Some(portfolio_id) = super::get_owned_portfolio_id(&mut *conn, slug, user_id).await? else {
+        return Ok(None);
+    };
+
+    let current_time =
+        SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as i64;
+    let query = sqlx::query_as(
+        "INSERT INTO portfolio_previews (token, portfolio_id, created_at, expires_at) \
+        VALUES ($1, $2, $3, $4) \
+        RETURNING token, created_at, expires_at",
+    );
+    let preview: PortfolioPreview = query
+        .bind(&UuidString::generate())
+        .bind(portfolio_id)
+        .bind(current_time)
+        .bind(expires_at)
+        .fetch_one(&mut *conn)
+        .await
+        .context("portfolio preview insert failed")?;
+    audit::record(&mut *conn, origin, Some(user_id), AuditAction::PortfolioPreviewCreated, slug)
+        .await?;
+
+    Ok(Some(preview))
+}
+
+/// Returns None if the user doesn't own a portfolio with the slug.
+pub async fn get_previews<E>(
+    conn: &E,
+    slug: &str,
+    user_id: i32,
+) -> Result<Option<Vec<PortfolioPreview>>, anyhow::Error>
+where
+    for<'e> &'e E: Executor<'e, Database = Any> + Acquire<'e, Database = Any>,
+{
+    let mut conn = conn.acquire().await.context("acquiring a db connection failed")?;
+    let Some(portfolio_id) = super::get_owned_portfolio_id(&mut *conn, slug, user_id).await? else {
+        return Ok(None);
+    };
+
+    let query = sqlx::query_as(
+        "SELECT token, created_at, expires_at FROM portfolio_previews \
+        WHERE portfolio_id = $1 ORDER BY created_at DESC",
+    );
+    let previews = query
+        .bind(portfolio_id)
+        .fetch_all(&mut *conn)
+        .await
+        .context("get portfolio previews failed")?;
+    Ok(Some(previews))
+}
+
+/// Returns false if the user doesn't own a portfolio with the slug, or it
+/// doesn't have a preview with the token.
+pub async fn revoke_preview<E>(
+    conn: &mut E,
+    origin: &RequestOrigin,
+    slug: &str,
+    user_id: i32,
+    token: &str,
+) -> Result<bool, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let Some(portfolio_id) = super::get_owned_portfolio_id(&mut *conn, slug, user_id).await? else {
+        return Ok(false);
+    };
+    let result =
+        sqlx::query("DELETE FROM portfolio_previews WHERE token = $1 AND portfolio_id = $2")
+            .bind(token)
+            .bind(portfolio_id)
+            .execute(&mut *conn)
+            .await
+            .context("portfolio preview delete failed")?;
+    if result.rows_affected() == 0 {
+        return Ok(false);
+    }
+
+    audit::record(&mut *conn, origin, Some(user_id), AuditAction::PortfolioPreviewRevoked, slug)
+        .await?;
+
+    Ok(true)
+}
+
+/// Deletes the previews which have expired.
+pub async fn remove_expired_previews<E>(conn: &mut E) -> Result<(), anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let current_time =
+        SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as i64;
+    sqlx::query("DELETE FROM portfolio_previews WHERE expires_at < $1")
+        .bind(current_time)
+        .execute(&mut *conn)
+        .await
+        .context("expired portfolio previews delete failed")?;
+    Ok(())
+}
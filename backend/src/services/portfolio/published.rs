@@ -0,0 +1,188 @@
+//! The published versions of portfolios. Editing a portfolio or its works
+//! only changes the draft, which is copied here when the portfolio is
+//! published, and which is what anyone besides the collaborators sees.
+//!
+//! The attachments' big files aren't copied, but the ones used by the
+//! published version are recorded, so that replacing or removing one in the
+//! draft leaves the published version's file alone, see
+//! [crate::services::work::big_files::hand_over_file_parts].
+
+use std::time::SystemTime;
+
+use anyhow::Context;
+use sqlx::{Any, Executor};
+
+use crate::data::portfolio::{Portfolio, PortfolioRow};
+use crate::data::work::Work;
+use crate::services::work;
+
+/// Replaces the published version of the portfolio with its current draft,
+/// including the works in it.
+pub async fn save_snapshot<E>(conn: &mut E, portfolio_id: i32) -> Result<Portfolio, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let row: PortfolioRow = sqlx::query_as("SELECT * FROM portfolios WHERE id = $1")
+        .bind(portfolio_id)
+        .fetch_one(&mut *conn)
+        .await
+        .context("portfolio to publish fetch failed")?;
+    let portfolio = super::fetch_portfolio_details(&mut *conn, row).await?;
+    let works = work::get_portfolio_works(&mut *conn, portfolio_id).await?;
+
+    delete_snapshot(&mut *conn, portfolio_id).await?;
+    let current_time =
+        SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as i64;
+    let query = sqlx::query(
+        "INSERT INTO published_portfolios (portfolio_id, created_at, portfolio) VALUES ($1, $2, $3)",
+    );
+    query
+        .bind(portfolio_id)
+        .bind(current_time)
+        .bind(serde_json::to_string(&portfolio)?)
+        .execute(&mut *conn)
+        .await
+        .context("published portfolio insert failed")?;
+    for work in works {
+        let query = sqlx::query(
+            "INSERT INTO published_works (portfolio_id, work_id, slug, work) VALUES ($1, $2, $3, $4)",
+        );
+        query
+            .bind(portfolio_id)
+            .bind(work.row.id)
+            .bind(&work.row.slug)
+            .bind(serde_json::to_string(&work)?)
+            .execute(&mut *conn)
+            .await
+            .context("published work insert failed")?;
+        for attachment in &work.attachments {
+            let Some(big_file_uuid) = &attachment.big_file_uuid else {
+                continue;
+            };
+            let query = sqlx::query(
+                "INSERT INTO published_work_files (portfolio_id, work_id, big_file_uuid) \
+                VALUES ($1, $2, $3) \
+                ON CONFLICT (portfolio_id, work_id, big_file_uuid) DO NOTHING",
+            );
+            query
+                .bind(portfolio_id)
+                .bind(work.row.id)
+                .bind(big_file_uuid)
+                .execute(&mut *conn)
+                .await
+                .context("published work file insert failed")?;
+        }
+    }
+
+    Ok(portfolio)
+}
+
+pub async fn delete_snapshot<E>(conn: &mut E, portfolio_id: i32) -> Result<(), anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    for table in ["published_work_files", "published_works", "published_portfolios"] {
+        sqlx::query(&format!("DELETE FROM {table} WHERE portfolio_id = $1"))
+            .bind(portfolio_id)
+            .execute(&mut *conn)
+            .await
+            .with_context(|| format!("deleting the portfolio's {table} failed"))?;
+    }
+    Ok(())
+}
+
+/// Returns the published version of the portfolio, if it's currently
+/// published, and either isn't password-protected or the viewer token is
+/// valid for it. The publication times are the current ones, not the ones
+/// from when it was published.
+pub async fn get_published_portfolio<E>(
+    conn: &E,
+    slug: &str,
+    viewer_token: Option<&str>,
+) -> Result<Option<Portfolio>, anyhow::Error>
+where
+    for<'e> &'e E: Executor<'e, Database = Any>,
+{
+    let query = sqlx::query_as(
+        "SELECT * FROM portfolios \
+        WHERE slug = $1 AND deleted_at IS NULL AND published_at IS NOT NULL \
+            AND (unpublish_at IS NULL OR unpublish_at > $2) \
+            AND (access_password_key_base64 IS NULL \
+                OR id IN ( select portfolio_id from portfolio_viewer_tokens where token = $3 and expires_at > $4 ))",
+    );
+    let current_time =
+        SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as i64;
+    let row: Option<PortfolioRow> = query
+        .bind(slug)
+        .bind(current_time)
+        .bind(viewer_token)
+        .bind(current_time)
+        .fetch_optional(conn)
+        .await
+        .context("get published portfolio row failed")?;
+    let Some(row) = row else {
+        return Ok(None);
+    };
+
+    let snapshot: Option<(String,)> =
+        sqlx::query_as("SELECT portfolio FROM published_portfolios WHERE portfolio_id = $1")
+            .bind(row.id)
+            .fetch_optional(conn)
+            .await
+            .context("get published portfolio failed")?;
+    let Some((snapshot,)) = snapshot else {
+        return Ok(None);
+    };
+    let published: Portfolio =
+        serde_json::from_str(&snapshot).context("published portfolio is not valid json")?;
+
+    Ok(Some(Portfolio {
+        row: PortfolioRow {
+            title: published.row.title,
+            subtitle: published.row.subtitle,
+            author: published.row.author,
+            ..row
+        },
+        categories: published.categories,
+    }))
+}
+
+/// Returns the work as it was when it was last published in a portfolio
+/// that's currently published, and either isn't password-protected or the
+/// viewer token is valid for it.
+pub async fn get_published_work<E>(
+    conn: &E,
+    slug: &str,
+    viewer_token: Option<&str>,
+) -> Result<Option<Work>, anyhow::Error>
+where
+    for<'e> &'e E: Executor<'e, Database = Any>,
+{
+    let query = sqlx::query_as(
+        "SELECT published_works.work FROM published_works \
+            JOIN published_portfolios ON (published_portfolios.portfolio_id = published_works.portfolio_id) \
+            JOIN portfolios ON (portfolios.id = published_works.portfolio_id) \
+            JOIN works ON (works.id = published_works.work_id) \
+        WHERE published_works.slug = $1 AND works.deleted_at IS NULL \
+            AND portfolios.deleted_at IS NULL AND portfolios.published_at IS NOT NULL \
+            AND (portfolios.unpublish_at IS NULL OR portfolios.unpublish_at > $2) \
+            AND (portfolios.access_password_key_base64 IS NULL \
+                OR portfolios.id IN ( select portfolio_id from portfolio_viewer_tokens where token = $3 and expires_at > $4 )) \
+        ORDER BY published_portfolios.created_at DESC LIMIT 1",
+    );
+    let current_time =
+        SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as i64;
+    let snapshot: Option<(String,)> = query
+        .bind(slug)
+        .bind(current_time)
+        .bind(viewer_token)
+        .bind(current_time)
+        .fetch_optional(conn)
+        .await
+        .context("get published work failed")?;
+    let Some((snapshot,)) = snapshot else {
+        return Ok(None);
+    };
+    let work = serde_json::from_str(&snapshot).context("published work is not valid json")?;
+    Ok(Some(work))
+}
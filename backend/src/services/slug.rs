@@ -0,0 +1,198 @@
+//! The slugs of works and portfolios, which are a part of the links people
+//! share, so they're kept simple, and the previous slugs keep working after a
+//! slug is changed. Works and portfolios have separate slugs, so a work and a
+//! portfolio can have the same one.
+
+use std::time::SystemTime;
+
+use anyhow::Context;
+use sqlx::{Any, Executor};
+
+use crate::array_string_types::SlugString;
+use crate::data::collaborator::CollaborationTarget;
+
+/// Slugs which would collide with the other paths under `/work` and
+/// `/portfolio`, or would be confusing as links.
+const RESERVED_SLUGS: &[&str] = &["admin", "api", "edit", "file", "new", "trash"];
+
+const MAX_SLUG_LEN: usize = 60;
+
+fn tables(target: CollaborationTarget) -> (&'static str, &'static str) {
+    match target {
+        CollaborationTarget::Work => ("works", "work_id"),
+        CollaborationTarget::Portfolio => ("portfolios", "portfolio_id"),
+    }
+}
+
+/// Slugs are case-insensitive, and stored in lowercase.
+pub fn normalize(slug: &str) -> String {
+    slug.trim().to_ascii_lowercase()
+}
+
+/// Slugs consist of lowercase ASCII letters, digits, and single hyphens
+/// between them.
+pub fn is_valid(slug: &str) -> bool {
+    let valid_char = |c: char| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-';
+    (1..=MAX_SLUG_LEN).contains(&slug.len())
+        && slug.chars().all(valid_char)
+        && !slug.starts_with('-')
+        && !slug.ends_with('-')
+        && !slug.contains("--")
+        && !RESERVED_SLUGS.contains(&slug)
+}
+
+/// Returns true if no other work or portfolio (depending on the target) has
+/// the slug, or has had it before. The work or portfolio which currently has
+/// `own_slug` can take back its own previous slugs.
+pub async fn is_available<E>(
+    conn: &mut E,
+    target: CollaborationTarget,
+    slug: &str,
+    own_slug: Option<&str>,
+) -> Result<bool, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let (table, id_column) = tables(target);
+    // Safety: the table names aren't from user input.
+    let query = format!(
+        "SELECT COUNT(*) FROM {table} WHERE slug = $1 \
+            AND (slug <> $2 OR $2 IS NULL)"
+    );
+    let (live,): (i64,) = sqlx::query_as(&query)
+        .bind(slug)
+        .bind(own_slug)
+        .fetch_one(&mut *conn)
+        .await
+        .context("slug availability check failed")?;
+    let query = format!(
+        "SELECT COUNT(*) FROM slug_history WHERE slug = $1 AND {id_column} IS NOT NULL \
+            AND {id_column} NOT IN ( select id from {table} where slug = $2 )"
+    );
+    let (previous,): (i64,) = sqlx::query_as(&query)
+        .bind(slug)
+        .bind(own_slug)
+        .fetch_one(&mut *conn)
+        .await
+        .context("previous slug availability check failed")?;
+    Ok(live == 0 && previous == 0)
+}
+
+/// Records the slug change of the work or portfolio, so that the old slug
+/// redirects to the new one.
+pub async fn record_change<E>(
+    conn: &mut E,
+    target: CollaborationTarget,
+    id: i32,
+    old_slug: &str,
+    new_slug: &str,
+) -> Result<(), anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    if old_slug == new_slug {
+        return Ok(());
+    }
+    let (_, id_column) = tables(target);
+    // The work or portfolio might be taking back one of its previous slugs
+    let query = format!("DELETE FROM slug_history WHERE {id_column} = $1 AND slug IN ($2, $3)");
+    sqlx::query(&query)
+        .bind(id)
+        .bind(old_slug)
+        .bind(new_slug)
+        .execute(&mut *conn)
+        .await
+        .context("removing reclaimed slug from history failed")?;
+    let current_time =
+        SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as i64;
+    let query =
+        format!("INSERT INTO slug_history (slug, {id_column}, changed_at) VALUES ($1, $2, $3)");
+    sqlx::query(&query)
+        .bind(old_slug)
+        .bind(id)
+        .bind(current_time)
+        .execute(&mut *conn)
+        .await
+        .context("slug history insert failed")?;
+    Ok(())
+}
+
+/// Returns the current slug of the work or portfolio which had the given
+/// slug before, for redirecting requests using the old one.
+pub async fn get_renamed_slug<E>(
+    conn: &E,
+    target: CollaborationTarget,
+    old_slug: &str,
+) -> Result<Option<SlugString>, anyhow::Error>
+where
+    for<'e> &'e E: Executor<'e, Database = Any>,
+{
+    let (table, id_column) = tables(target);
+    let query = format!(
+        "SELECT {table}.slug FROM slug_history \
+            JOIN {table} ON ({table}.id = slug_history.{id_column}) \
+        WHERE slug_history.slug = $1 AND {table}.deleted_at IS NULL"
+    );
+    let slug: Option<(SlugString,)> = sqlx::query_as(&query)
+        .bind(old_slug)
+        .fetch_optional(conn)
+        .await
+        .context("renamed slug fetch failed")?;
+    Ok(slug.map(|(slug,)| slug))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_trims_and_lowercases() {
+        assert_eq!(normalize("  My-Game-2 \n"), "my-game-2");
+        assert_eq!(normalize("already-fine"), "already-fine");
+    }
+
+    #[test]
+    fn normalize_leaves_non_ascii_alone() {
+        assert_eq!(normalize("ÄÖ-Game"), "ÄÖ-game");
+        assert!(!is_valid(&normalize("ÄÖ-Game")));
+    }
+
+    #[test]
+    fn accepts_simple_slugs() {
+        assert!(is_valid("a"));
+        assert!(is_valid("my-game-2"));
+        assert!(is_valid("2024"));
+        assert!(is_valid(&"a".repeat(MAX_SLUG_LEN)));
+    }
+
+    #[test]
+    fn rejects_bad_lengths() {
+        assert!(!is_valid(""));
+        assert!(!is_valid(&"a".repeat(MAX_SLUG_LEN + 1)));
+    }
+
+    #[test]
+    fn rejects_bad_characters() {
+        assert!(!is_valid("My-Game"));
+        assert!(!is_valid("my game"));
+        assert!(!is_valid("my_game"));
+        assert!(!is_valid("my/game"));
+        assert!(!is_valid("peli-ä"));
+    }
+
+    #[test]
+    fn rejects_misplaced_hyphens() {
+        assert!(!is_valid("-game"));
+        assert!(!is_valid("game-"));
+        assert!(!is_valid("my--game"));
+        assert!(!is_valid("-"));
+    }
+
+    #[test]
+    fn rejects_reserved_slugs() {
+        for slug in RESERVED_SLUGS {
+            assert!(!is_valid(slug), "{slug} should be reserved");
+        }
+        assert!(is_valid("new-game"));
+    }
+}
@@ -8,64 +8,154 @@ use ring::pbkdf2::{self, PBKDF2_HMAC_SHA256};
 use ring::rand::{SecureRandom, SystemRandom};
 use sqlx::{Any, Executor};
 
-use crate::array_string_types::{UsernameString, UuidString};
+use crate::array_string_types::{PasswordKeyString, UsernameString, UuidString};
 use crate::config;
-use crate::data::user::{Session, User};
+use crate::data::audit::{AuditAction, RequestOrigin};
+use crate::data::collaborator::CollaboratorRole;
+use crate::data::user::{
+    LoginOutcome, PendingLogin, Session, User, UserInfo, UserRole, UsernameChangeOutcome,
+};
+use crate::services::audit;
+
+pub mod api_tokens;
+pub mod export;
+pub mod invites;
+pub mod oidc;
+pub mod password_resets;
+pub mod profile;
+pub mod totp;
 
 const USERNAME_LEN: usize = 30;
-const SALT_BYTES_LEN: usize = 12;
+pub const SALT_BYTES_LEN: usize = 12;
+
+pub type Salt = ArrayVec<u8, { USERNAME_LEN + SALT_BYTES_LEN }>;
 
 pub async fn create_user<E>(
     conn: &mut E,
+    origin: &RequestOrigin,
     username: UsernameString,
     password: &str,
-) -> Result<(), anyhow::Error>
+) -> Result<i32, anyhow::Error>
 where
     for<'e> &'e mut E: Executor<'e, Database = Any>,
 {
-    let random = SystemRandom::new();
+    let key = derive_new_password_key(username, password).await?;
+
+    let username: &str = username.0.as_str();
+    let query = sqlx::query_as(
+        "INSERT INTO users (username, password_key_base64, pbkdf2_iterations, salt_base64) \
+        VALUES ($1, $2, $3, $4) \
+        RETURNING id",
+    );
+    let (user_id,): (i32,) = query
+        .bind(username)
+        .bind(key.password_key_base64)
+        .bind(key.pbkdf2_iterations.get() as i32)
+        .bind(key.salt_base64)
+        .fetch_one(&mut *conn)
+        .await
+        .context("user insert failed")?;
+    audit::record(&mut *conn, origin, Some(user_id), AuditAction::Register, username).await?;
+
+    Ok(user_id)
+}
 
+/// Creates a user who can only log in via a linked OpenID Connect identity,
+/// until they set a password with a password reset.
+pub async fn create_user_without_password<E>(
+    conn: &mut E,
+    origin: &RequestOrigin,
+    username: UsernameString,
+) -> Result<i32, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    // The salt is required by the schema, even if there's no password to go with it
     let mut db_salt_bytes = [0u8; SALT_BYTES_LEN];
-    random.fill(&mut db_salt_bytes).expect("system random should be able to generate random bytes");
-    let mut salt: ArrayVec<u8, { USERNAME_LEN + SALT_BYTES_LEN }> = ArrayVec::new();
-    salt.try_extend_from_slice(username.0.as_bytes()).unwrap();
-    salt.try_extend_from_slice(&db_salt_bytes).unwrap();
+    SystemRandom::new()
+        .fill(&mut db_salt_bytes)
+        .expect("system random should be able to generate random bytes");
 
-    let mut password_key_bytes = [0u8; 32];
-    let pbkdf2_iterations = config::pbkdf2_iterations();
-    pbkdf2::derive(
-        PBKDF2_HMAC_SHA256,
-        pbkdf2_iterations,
-        &salt,
-        password.as_bytes(),
-        &mut password_key_bytes,
+    let query = sqlx::query_as(
+        "INSERT INTO users (username, pbkdf2_iterations, salt_base64) VALUES ($1, $2, $3) \
+        RETURNING id",
     );
+    let (user_id,): (i32,) = query
+        .bind(username.0.as_str())
+        .bind(config::pbkdf2_iterations().get() as i32)
+        .bind(BASE64.encode(&db_salt_bytes))
+        .fetch_one(&mut *conn)
+        .await
+        .context("passwordless user insert failed")?;
+    audit::record(&mut *conn, origin, Some(user_id), AuditAction::Register, &username.0).await?;
+
+    Ok(user_id)
+}
+
+/// Replaces the user's password. The username is needed since it's a part of
+/// the salt.
+pub async fn set_password<E>(
+    conn: &mut E,
+    user_id: i32,
+    username: UsernameString,
+    password: &str,
+) -> Result<(), anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let key = derive_new_password_key(username, password).await?;
 
-    let username: &str = username.0.as_str();
-    let password_key_base64: String = BASE64.encode(&password_key_bytes);
-    let db_salt_base64: String = BASE64.encode(&db_salt_bytes);
     let query = sqlx::query(
-        "INSERT INTO users (username, password_key_base64, pbkdf2_iterations, salt_base64) \
-        VALUES ($1, $2, $3, $4)",
+        "UPDATE users SET password_key_base64 = $1, pbkdf2_iterations = $2, salt_base64 = $3 \
+        WHERE id = $4",
     );
     let result = query
-        .bind(username)
-        .bind(password_key_base64)
-        .bind(pbkdf2_iterations.get() as i32)
-        .bind(db_salt_base64)
+        .bind(key.password_key_base64)
+        .bind(key.pbkdf2_iterations.get() as i32)
+        .bind(key.salt_base64)
+        .bind(user_id)
         .execute(conn)
         .await
-        .context("user insert failed")?;
+        .context("user password update failed")?;
     assert_eq!(1, result.rows_affected());
 
     Ok(())
 }
 
+struct NewPasswordKey {
+    password_key_base64: String,
+    pbkdf2_iterations: NonZeroU32,
+    salt_base64: String,
+}
+
+async fn derive_new_password_key(
+    username: UsernameString,
+    password: &str,
+) -> Result<NewPasswordKey, anyhow::Error> {
+    let random = SystemRandom::new();
+
+    let mut db_salt_bytes = [0u8; SALT_BYTES_LEN];
+    random.fill(&mut db_salt_bytes).expect("system random should be able to generate random bytes");
+    let mut salt: Salt = ArrayVec::new();
+    salt.try_extend_from_slice(username.0.as_bytes()).unwrap();
+    salt.try_extend_from_slice(&db_salt_bytes).unwrap();
+
+    let pbkdf2_iterations = config::pbkdf2_iterations();
+    let password_key_bytes = derive_password_key(pbkdf2_iterations, salt, password).await?;
+
+    Ok(NewPasswordKey {
+        password_key_base64: BASE64.encode(&password_key_bytes),
+        pbkdf2_iterations,
+        salt_base64: BASE64.encode(&db_salt_bytes),
+    })
+}
+
 pub async fn login<E>(
     conn: &mut E,
+    origin: &RequestOrigin,
     username: UsernameString,
     password: &str,
-) -> Result<Option<Session>, anyhow::Error>
+) -> Result<Option<LoginOutcome>, anyhow::Error>
 where
     for<'e> &'e mut E: Executor<'e, Database = Any>,
 {
@@ -75,40 +165,141 @@ where
         .await
         .context("user fetch on login failed")?;
 
+    // The dummy derivations make the response take as long as it would for an
+    // existing user, so that the response time doesn't reveal which usernames
+    // are registered.
     let Some(user) = user else {
+        dummy_password_derivation(password).await?;
+        audit::record(&mut *conn, origin, None, AuditAction::LoginFailed, &username.0).await?;
         return Ok(None);
     };
-    let Some(password_key_base64) = user.password_key_base64 else {
+    let Some(password_key_base64) = &user.password_key_base64 else {
+        dummy_password_derivation(password).await?;
+        audit::record(&mut *conn, origin, Some(user.id), AuditAction::LoginFailed, &username.0)
+            .await?;
         return Ok(None);
     };
+    if !check_password(&user, password_key_base64, password).await? {
+        audit::record(&mut *conn, origin, Some(user.id), AuditAction::LoginFailed, &username.0)
+            .await?;
+        return Ok(None);
+    }
 
+    Ok(Some(start_login(conn, origin, &user).await?))
+}
+
+/// Checks the password against the user's password key, which was derived
+/// with the user's current username as a part of the salt.
+async fn check_password(
+    user: &User,
+    password_key_base64: &PasswordKeyString,
+    password: &str,
+) -> Result<bool, anyhow::Error> {
     let mut db_salt_bytes = [0u8; 12];
     BASE64.decode_mut(user.salt_base64.0.as_bytes(), &mut db_salt_bytes).unwrap();
-    let mut salt: ArrayVec<u8, { USERNAME_LEN + SALT_BYTES_LEN }> = ArrayVec::new();
-    salt.try_extend_from_slice(username.0.as_bytes()).unwrap();
+    let mut salt: Salt = ArrayVec::new();
+    salt.try_extend_from_slice(user.username.0.as_bytes()).unwrap();
     salt.try_extend_from_slice(&db_salt_bytes).unwrap();
 
     let mut password_key_bytes = [0u8; 32 + 1]; // one extra byte of space for the decoding process
     let len = BASE64.decode_mut(password_key_base64.0.as_bytes(), &mut password_key_bytes).unwrap();
-    let password_key_bytes = &password_key_bytes[0..len];
-
-    let password_verification = pbkdf2::verify(
-        PBKDF2_HMAC_SHA256,
-        NonZeroU32::new(user.pbkdf2_iterations as u32).unwrap(),
-        &salt,
-        password.as_bytes(),
-        password_key_bytes,
-    );
-    if password_verification.is_err() {
-        return Ok(None);
+    let password_key_bytes = ArrayVec::<u8, 32>::try_from(&password_key_bytes[0..len]).unwrap();
+
+    let iterations = NonZeroU32::new(user.pbkdf2_iterations as u32).unwrap();
+    verify_password_key(iterations, salt, password, password_key_bytes).await
+}
+
+/// Logs in the user whose credentials have already been checked, creating
+/// either a session, or a pending login if they have TOTP enabled.
+/// Only complete logins are recorded in the audit log, the TOTP check records
+/// its own event.
+async fn start_login<E>(
+    conn: &mut E,
+    origin: &RequestOrigin,
+    user: &User,
+) -> Result<LoginOutcome, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let username = user.username.0.as_str();
+    if user.disabled_at.is_some() {
+        audit::record(&mut *conn, origin, Some(user.id), AuditAction::LoginFailed, username)
+            .await?;
+        return Ok(LoginOutcome::AccountDisabled);
     }
 
+    if user.totp_enabled_at.is_some() {
+        let pending_login = create_pending_login(&mut *conn, user.id).await?;
+        return Ok(LoginOutcome::TotpRequired(pending_login));
+    }
+
+    let session = create_session(&mut *conn, user.id).await?;
+    audit::record(&mut *conn, origin, Some(user.id), AuditAction::Login, username).await?;
+    Ok(LoginOutcome::Session(session))
+}
+
+/// Runs pbkdf2 on a blocking thread, since it's slow by design, and would
+/// otherwise stall the async runtime for every other request as well.
+pub async fn derive_password_key(
+    iterations: NonZeroU32,
+    salt: Salt,
+    password: &str,
+) -> Result<[u8; 32], anyhow::Error> {
+    let password = password.to_owned();
+    let derive = move || {
+        let mut password_key_bytes = [0u8; 32];
+        pbkdf2::derive(
+            PBKDF2_HMAC_SHA256,
+            iterations,
+            &salt,
+            password.as_bytes(),
+            &mut password_key_bytes,
+        );
+        password_key_bytes
+    };
+    tokio::task::spawn_blocking(derive).await.context("password key derivation task failed")
+}
+
+/// Takes about as long as creating a user or verifying a password, without
+/// doing anything useful. Used when returning early would reveal that a user
+/// does or doesn't exist.
+pub async fn dummy_password_derivation(password: &str) -> Result<(), anyhow::Error> {
+    derive_password_key(config::pbkdf2_iterations(), ArrayVec::new(), password).await?;
+    Ok(())
+}
+
+/// The verifying counterpart to [derive_password_key].
+pub async fn verify_password_key(
+    iterations: NonZeroU32,
+    salt: Salt,
+    password: &str,
+    password_key_bytes: ArrayVec<u8, 32>,
+) -> Result<bool, anyhow::Error> {
+    let password = password.to_owned();
+    let verify = move || {
+        let result = pbkdf2::verify(
+            PBKDF2_HMAC_SHA256,
+            iterations,
+            &salt,
+            password.as_bytes(),
+            &password_key_bytes,
+        );
+        result.is_ok()
+    };
+    tokio::task::spawn_blocking(verify).await.context("password verification task failed")
+}
+
+pub async fn create_session<E>(conn: &mut E, user_id: i32) -> Result<Session, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
     let seconds_since_unix_epoch =
         SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs();
     let session = Session {
         uuid: UuidString::generate(),
-        user_id: user.id,
+        user_id,
         created_at: seconds_since_unix_epoch as i64,
+        api_token_scopes: None,
     };
 
     let result =
@@ -116,14 +307,57 @@ where
             .bind(&session.uuid)
             .bind(session.user_id)
             .bind(session.created_at)
-            .execute(&mut *conn)
+            .execute(conn)
+            .await
+            .context("session creation failed")?;
+    assert_eq!(1, result.rows_affected());
+
+    Ok(session)
+}
+
+async fn create_pending_login<E>(conn: &mut E, user_id: i32) -> Result<PendingLogin, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let seconds_since_unix_epoch =
+        SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs();
+    let pending_login = PendingLogin {
+        uuid: UuidString::generate(),
+        user_id,
+        created_at: seconds_since_unix_epoch as i64,
+    };
+
+    let result =
+        sqlx::query("INSERT INTO pending_logins (uuid, user_id, created_at) VALUES ($1, $2, $3)")
+            .bind(&pending_login.uuid)
+            .bind(pending_login.user_id)
+            .bind(pending_login.created_at)
+            .execute(conn)
             .await
-            .context("session creation on login failed")?;
+            .context("pending login creation failed")?;
     assert_eq!(1, result.rows_affected());
 
-    Ok(Some(session))
+    Ok(pending_login)
 }
 
+/// Usernames which can't be registered, since they'd collide with the paths
+/// under `/user`, where `/user/:username` is the user's public profile.
+const RESERVED_USERNAMES: &[&str] =
+    &["invites", "login", "me", "oidc", "password-reset", "register", "tokens", "totp"];
+
+/// Usernames consist of ASCII letters, digits, underscores, hyphens, and
+/// periods, and start with a letter or a digit. The minimum length is checked
+/// separately, for a more specific error.
+pub fn is_valid_username(username: &str) -> bool {
+    let valid_char = |c: char| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.');
+    username.starts_with(|c: char| c.is_ascii_alphanumeric())
+        && username.chars().all(valid_char)
+        && !RESERVED_USERNAMES.contains(&username.to_ascii_lowercase().as_str())
+}
+
+/// Returns true if someone has the username, or has had it recently enough
+/// that it's still reserved for them. Usernames differing only by case are
+/// considered the same here, so that lookalike accounts can't be created.
 pub async fn is_username_taken<E>(
     conn: &mut E,
     username: UsernameString,
@@ -131,12 +365,254 @@ pub async fn is_username_taken<E>(
 where
     for<'e> &'e mut E: Executor<'e, Database = Any>,
 {
-    let user: Option<User> = sqlx::query_as("SELECT * FROM users WHERE username = $1")
-        .bind(username.0.as_str())
+    Ok(get_username_holder(conn, &username.0).await?.is_some())
+}
+
+/// Returns the id of the user who has or recently had the username (ignoring
+/// case), if any.
+async fn get_username_holder<E>(conn: &mut E, username: &str) -> Result<Option<i32>, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let current_time =
+        SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as i64;
+    let reservation_seconds = config::username_reservation_seconds() as i64;
+    let query = sqlx::query_as(
+        "SELECT id FROM users WHERE LOWER(username) = LOWER($1) \
+        UNION ALL \
+        SELECT user_id FROM username_history WHERE LOWER(username) = LOWER($1) AND changed_at >= $2",
+    );
+    let holder: Option<(i32,)> = query
+        .bind(username)
+        .bind(current_time - reservation_seconds)
+        .fetch_optional(conn)
+        .await
+        .context("user fetch on username availability check failed")?;
+    Ok(holder.map(|(user_id,)| user_id))
+}
+
+/// Renames the user, re-deriving their password key since the username is a
+/// part of the salt. The old username is reserved for the user for a while,
+/// see [config::username_reservation_seconds]. The password is not needed if
+/// the user doesn't have one, i.e. they log in via OpenID Connect.
+pub async fn change_username<E>(
+    conn: &mut E,
+    origin: &RequestOrigin,
+    user_id: i32,
+    new_username: UsernameString,
+    password: &str,
+) -> Result<UsernameChangeOutcome, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let user: User = sqlx::query_as("SELECT * FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_one(&mut *conn)
+        .await
+        .context("user fetch on username change failed")?;
+    if let Some(password_key_base64) = &user.password_key_base64 {
+        if !check_password(&user, password_key_base64, password).await? {
+            return Ok(UsernameChangeOutcome::WrongPassword);
+        }
+    }
+
+    let holder = get_username_holder(&mut *conn, &new_username.0).await?;
+    if holder.is_some_and(|holder| holder != user_id) {
+        return Ok(UsernameChangeOutcome::UsernameTaken);
+    }
+
+    sqlx::query("UPDATE users SET username = $1 WHERE id = $2")
+        .bind(new_username.0.as_str())
+        .bind(user_id)
+        .execute(&mut *conn)
+        .await
+        .context("username update failed")?;
+    if user.password_key_base64.is_some() {
+        set_password(&mut *conn, user_id, new_username, password).await?;
+    }
+
+    let current_time =
+        SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as i64;
+    // The user might be taking back one of their own previous usernames
+    sqlx::query("DELETE FROM username_history WHERE LOWER(username) = LOWER($1)")
+        .bind(new_username.0.as_str())
+        .execute(&mut *conn)
+        .await
+        .context("removing reclaimed username from history failed")?;
+    let query = sqlx::query(
+        "INSERT INTO username_history (username, user_id, changed_at) VALUES ($1, $2, $3) \
+        ON CONFLICT (username) DO UPDATE SET user_id = $2, changed_at = $3",
+    );
+    query
+        .bind(user.username.0.as_str())
+        .bind(user_id)
+        .bind(current_time)
+        .execute(&mut *conn)
+        .await
+        .context("username history insert failed")?;
+
+    let target = format!("{} -> {}", user.username, new_username);
+    audit::record(&mut *conn, origin, Some(user_id), AuditAction::UsernameChanged, &target).await?;
+
+    Ok(UsernameChangeOutcome::Changed)
+}
+
+/// Returns the current username of the user who recently had the given
+/// username, for redirecting requests using the old one.
+pub async fn get_renamed_username<E>(
+    conn: &E,
+    old_username: &str,
+) -> Result<Option<UsernameString>, anyhow::Error>
+where
+    for<'e> &'e E: Executor<'e, Database = Any>,
+{
+    let current_time =
+        SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as i64;
+    let reservation_seconds = config::username_reservation_seconds() as i64;
+    let query = sqlx::query_as(
+        "SELECT users.username FROM username_history \
+        JOIN users ON (users.id = username_history.user_id) \
+        WHERE username_history.username = $1 AND username_history.changed_at >= $2",
+    );
+    let username: Option<(UsernameString,)> = query
+        .bind(old_username)
+        .bind(current_time - reservation_seconds)
+        .fetch_optional(conn)
+        .await
+        .context("renamed user fetch failed")?;
+    Ok(username.map(|(username,)| username))
+}
+
+pub async fn remove_old_usernames<E>(
+    conn: &mut E,
+    before_timestamp: SystemTime,
+) -> Result<(), anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let before_timestamp =
+        before_timestamp.duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as i64;
+    sqlx::query("DELETE FROM username_history WHERE changed_at < $1")
+        .bind(before_timestamp)
+        .execute(conn)
+        .await
+        .context("removing old usernames failed")?;
+    Ok(())
+}
+
+pub async fn get_user_id<E>(conn: &E, username: &str) -> Result<Option<i32>, anyhow::Error>
+where
+    for<'e> &'e E: Executor<'e, Database = Any>,
+{
+    let user_id: Option<(i32,)> = sqlx::query_as("SELECT id FROM users WHERE username = $1")
+        .bind(username)
+        .fetch_optional(conn)
+        .await
+        .context("user id fetch failed")?;
+    Ok(user_id.map(|(id,)| id))
+}
+
+pub async fn is_admin<E>(conn: &E, user_id: i32) -> Result<bool, anyhow::Error>
+where
+    for<'e> &'e E: Executor<'e, Database = Any>,
+{
+    let is_admin: Option<(i32,)> = sqlx::query_as("SELECT is_admin FROM users WHERE id = $1")
+        .bind(user_id)
         .fetch_optional(conn)
         .await
-        .context("user fetch on is_username_taken check failed")?;
-    Ok(user.is_some())
+        .context("user fetch on is_admin check failed")?;
+    Ok(matches!(is_admin, Some((1,))))
+}
+
+/// Gives the user administrator rights, returning false if there's no user
+/// with the username.
+pub async fn make_admin<E>(conn: &mut E, username: &str) -> Result<bool, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let result = sqlx::query("UPDATE users SET is_admin = 1 WHERE username = $1")
+        .bind(username)
+        .execute(conn)
+        .await
+        .context("making user an admin failed")?;
+    Ok(result.rows_affected() > 0)
+}
+
+#[derive(sqlx::FromRow)]
+struct UserInfoRow {
+    username: UsernameString,
+    is_admin: i32,
+    work_count: i64,
+    portfolio_count: i64,
+    storage_base64_len: i64,
+}
+
+/// Gathers the details shown to the user about themselves and the session
+/// they're using.
+pub async fn get_user_info<E>(conn: &E, session: &Session) -> Result<UserInfo, anyhow::Error>
+where
+    for<'e> &'e E: Executor<'e, Database = Any>,
+{
+    let query = sqlx::query_as(
+        "SELECT username, is_admin, \
+            (SELECT COUNT(*) FROM work_rights JOIN works ON (works.id = work_rights.work_id) \
+                WHERE work_rights.user_id = $1 AND work_rights.role = $2 \
+                    AND works.deleted_at IS NULL) AS work_count, \
+            (SELECT COUNT(*) FROM portfolio_rights \
+                JOIN portfolios ON (portfolios.id = portfolio_rights.portfolio_id) \
+                WHERE portfolio_rights.user_id = $1 AND portfolio_rights.role = $2 \
+                    AND portfolios.deleted_at IS NULL) AS portfolio_count, \
+            (SELECT COALESCE(SUM(LENGTH(attachment_contents.bytes_base64)), 0) FROM attachment_contents \
+                WHERE attachment_contents.sha256 IN ( \
+                    select first_use.content_sha256 from work_attachments first_use \
+                    join work_rights on (work_rights.work_id = first_use.work_id) \
+                    where work_rights.user_id = $1 and work_rights.role = $2 \
+                        and first_use.id = ( select min(id) from work_attachments \
+                            where content_sha256 = first_use.content_sha256 ) )) \
+            + (SELECT COALESCE(SUM(LENGTH(big_file_parts.bytes_base64)), 0) FROM big_file_parts \
+                JOIN work_attachments ON (work_attachments.id = big_file_parts.work_attachment_id) \
+                JOIN work_rights ON (work_rights.work_id = work_attachments.work_id) \
+                WHERE work_rights.user_id = $1 AND work_rights.role = $2) \
+            + (SELECT COALESCE(SUM(LENGTH(avatar_bytes_base64)), 0) FROM user_profiles \
+                WHERE user_id = $1) AS storage_base64_len \
+        FROM users WHERE id = $1",
+    );
+    let row: UserInfoRow = query
+        .bind(session.user_id)
+        .bind(CollaboratorRole::Owner)
+        .fetch_one(conn)
+        .await
+        .context("user info fetch failed")?;
+
+    let session_expires_at = if session.api_token_scopes.is_some() {
+        let query = sqlx::query_as("SELECT expires_at FROM api_tokens WHERE uuid = $1");
+        let (expires_at,): (Option<i64>,) = query
+            .bind(&session.uuid)
+            .fetch_one(conn)
+            .await
+            .context("api token expiry fetch failed")?;
+        expires_at
+    } else {
+        Some(session.created_at + config::session_expiration_seconds() as i64)
+    };
+
+    let mut roles = Vec::new();
+    if row.is_admin == 1 {
+        roles.push(UserRole::Admin);
+    }
+
+    Ok(UserInfo {
+        user_id: session.user_id,
+        username: row.username,
+        roles,
+        session_id: session.uuid,
+        session_created_at: session.created_at,
+        session_expires_at,
+        // Every 4 characters of base64 encode 3 bytes
+        storage_bytes: row.storage_base64_len / 4 * 3,
+        work_count: row.work_count,
+        portfolio_count: row.portfolio_count,
+    })
 }
 
 pub async fn get_session<E>(
@@ -153,6 +629,24 @@ where
         .context("user fetch on is_username_taken check failed")
 }
 
+/// Logs the user out of every session, including ones waiting for a TOTP code.
+pub async fn remove_user_sessions<E>(conn: &mut E, user_id: i32) -> Result<(), anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    sqlx::query("DELETE FROM sessions WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *conn)
+        .await
+        .context("removing user's sessions failed")?;
+    sqlx::query("DELETE FROM pending_logins WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *conn)
+        .await
+        .context("removing user's pending logins failed")?;
+    Ok(())
+}
+
 pub async fn remove_sessions<E>(
     conn: &mut E,
     before_timestamp: SystemTime,
@@ -0,0 +1,133 @@
+use std::time::SystemTime;
+
+use anyhow::Context;
+use data_encoding::{BASE64, BASE64URL_NOPAD};
+use ring::digest::{self, SHA256};
+use ring::rand::{SecureRandom, SystemRandom};
+use sqlx::{Any, Executor};
+
+use crate::array_string_types::UuidString;
+use crate::data::audit::{AuditAction, RequestOrigin};
+use crate::data::user::{ApiToken, ApiTokenScopes, Session};
+use crate::services::audit;
+
+/// Prefix of the API token strings, so that they can be told apart from
+/// session ids (and recognized by secret scanners).
+pub const TOKEN_PREFIX: &str = "pat_";
+const TOKEN_BYTES_LEN: usize = 32;
+
+/// Creates a new API token, returning its details and the token itself. The
+/// token is only stored hashed, so this is the only time it can be seen.
+pub async fn create_token<E>(
+    conn: &mut E,
+    origin: &RequestOrigin,
+    user_id: i32,
+    name: &str,
+    scopes: ApiTokenScopes,
+    expires_at: Option<i64>,
+) -> Result<(ApiToken, String), anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let mut token_bytes = [0u8; TOKEN_BYTES_LEN];
+    SystemRandom::new()
+        .fill(&mut token_bytes)
+        .expect("system random should be able to generate random bytes");
+    let token = format!("{TOKEN_PREFIX}{}", BASE64URL_NOPAD.encode(&token_bytes));
+
+    let current_time =
+        SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as i64;
+    let query = sqlx::query_as(
+        "INSERT INTO api_tokens (uuid, user_id, name, token_hash_base64, scopes, created_at, expires_at) \
+        VALUES                  ($1,   $2,      $3,   $4,                $5,     $6,         $7) \
+        RETURNING uuid, user_id, name, scopes, created_at, expires_at, last_used_at",
+    );
+    let api_token: ApiToken = query
+        .bind(&UuidString::generate())
+        .bind(user_id)
+        .bind(name)
+        .bind(hash_token(&token))
+        .bind(scopes)
+        .bind(current_time)
+        .bind(expires_at)
+        .fetch_one(&mut *conn)
+        .await
+        .context("api token insert failed")?;
+    audit::record(&mut *conn, origin, Some(user_id), AuditAction::ApiTokenCreated, name).await?;
+
+    Ok((api_token, token))
+}
+
+pub async fn get_tokens<E>(conn: &E, user_id: i32) -> Result<Vec<ApiToken>, anyhow::Error>
+where
+    for<'e> &'e E: Executor<'e, Database = Any>,
+{
+    let query = sqlx::query_as(
+        "SELECT uuid, user_id, name, scopes, created_at, expires_at, last_used_at FROM api_tokens \
+        WHERE user_id = $1 ORDER BY created_at ASC",
+    );
+    query.bind(user_id).fetch_all(conn).await.context("get all api tokens failed")
+}
+
+/// Deletes the token, returning false if the user has no token with the uuid.
+pub async fn revoke_token<E>(
+    conn: &mut E,
+    origin: &RequestOrigin,
+    user_id: i32,
+    token_uuid: &str,
+) -> Result<bool, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let query =
+        sqlx::query_as("DELETE FROM api_tokens WHERE uuid = $1 AND user_id = $2 RETURNING name");
+    let name: Option<(String,)> = query
+        .bind(token_uuid)
+        .bind(user_id)
+        .fetch_optional(&mut *conn)
+        .await
+        .context("api token delete failed")?;
+    let Some((name,)) = name else {
+        return Ok(false);
+    };
+    audit::record(&mut *conn, origin, Some(user_id), AuditAction::ApiTokenRevoked, &name).await?;
+    Ok(true)
+}
+
+/// Returns a [Session] for the token, if it exists and hasn't expired, and
+/// marks the token as used.
+pub async fn get_session_for_token<E>(
+    conn: &E,
+    token: &str,
+) -> Result<Option<Session>, anyhow::Error>
+where
+    for<'e> &'e E: Executor<'e, Database = Any>,
+{
+    let current_time =
+        SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as i64;
+    let query = sqlx::query_as(
+        "UPDATE api_tokens SET last_used_at = $1 \
+        WHERE token_hash_base64 = $2 AND (expires_at IS NULL OR expires_at > $3) \
+        RETURNING uuid, user_id, name, scopes, created_at, expires_at, last_used_at",
+    );
+    let api_token: Option<ApiToken> = query
+        .bind(current_time)
+        .bind(hash_token(token))
+        .bind(current_time)
+        .fetch_optional(conn)
+        .await
+        .context("api token fetch failed")?;
+
+    Ok(api_token.map(|api_token| Session {
+        uuid: api_token.uuid,
+        user_id: api_token.user_id,
+        created_at: api_token.created_at,
+        api_token_scopes: Some(api_token.scopes),
+    }))
+}
+
+/// The tokens are random enough that a plain hash is sufficient, no need for
+/// pbkdf2 like with passwords.
+fn hash_token(token: &str) -> String {
+    BASE64.encode(digest::digest(&SHA256, token.as_bytes()).as_ref())
+}
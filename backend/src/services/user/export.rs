@@ -0,0 +1,114 @@
+//! Collects everything stored about a user for their data export. The
+//! attachment files are not loaded here, since they can be large, they're
+//! streamed into the export with [crate::services::work::big_files].
+
+use anyhow::Context;
+use sqlx::{Acquire, Any, Executor};
+
+use crate::config;
+use crate::data::collaborator::CollaboratorRole;
+use crate::data::portfolio::Portfolio;
+use crate::data::user::{ExportedSession, ExportedUser, User, UserRole};
+use crate::data::work::Work;
+use crate::services::{portfolio, work};
+
+pub async fn get_user<E>(conn: &E, user_id: i32) -> Result<ExportedUser, anyhow::Error>
+where
+    for<'e> &'e E: Executor<'e, Database = Any>,
+{
+    let user: User = sqlx::query_as("SELECT * FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_one(conn)
+        .await
+        .context("user fetch for export failed")?;
+
+    let mut roles = Vec::new();
+    if super::is_admin(conn, user_id).await? {
+        roles.push(UserRole::Admin);
+    }
+
+    Ok(ExportedUser {
+        id: user.id,
+        username: user.username,
+        roles,
+        totp_enabled_at: user.totp_enabled_at,
+        disabled_at: user.disabled_at,
+        profile: super::profile::get_profile(conn, user_id).await?,
+        oidc_identities: super::oidc::get_identities(conn, user_id).await?,
+        api_tokens: super::api_tokens::get_tokens(conn, user_id).await?,
+    })
+}
+
+pub async fn get_sessions<E>(conn: &E, user_id: i32) -> Result<Vec<ExportedSession>, anyhow::Error>
+where
+    for<'e> &'e E: Executor<'e, Database = Any>,
+{
+    let query = sqlx::query_as(
+        "SELECT created_at, created_at + $1 AS expires_at FROM sessions \
+        WHERE user_id = $2 ORDER BY created_at ASC",
+    );
+    query
+        .bind(config::session_expiration_seconds() as i64)
+        .bind(user_id)
+        .fetch_all(conn)
+        .await
+        .context("get sessions for export failed")
+}
+
+/// Returns the works the user owns with all their details, including the
+/// attachments' metadata, but not the big files' contents. Works the user can
+/// only access as a collaborator or via an organization are someone else's
+/// data, so they're left out.
+pub async fn get_works<E>(conn: &E, user_id: i32) -> Result<Vec<Work>, anyhow::Error>
+where
+    for<'e> &'e E: Executor<'e, Database = Any> + Acquire<'e, Database = Any>,
+{
+    let query = sqlx::query_as(
+        "SELECT works.slug FROM works \
+        JOIN work_rights ON (work_rights.work_id = works.id) \
+        WHERE work_rights.user_id = $1 AND work_rights.role = $2 AND works.deleted_at IS NULL \
+        ORDER BY works.id ASC",
+    );
+    let slugs: Vec<(String,)> = query
+        .bind(user_id)
+        .bind(CollaboratorRole::Owner)
+        .fetch_all(conn)
+        .await
+        .context("get owned works for export failed")?;
+    let mut works = Vec::with_capacity(slugs.len());
+    for (slug,) in slugs {
+        let work = work::get_work(conn, &slug, Some(user_id), None, None)
+            .await?
+            .context("work listed for the user could not be fetched")?;
+        works.push(work);
+    }
+    Ok(works)
+}
+
+/// Returns the portfolios the user owns, like [get_works].
+pub async fn get_portfolios<E>(conn: &E, user_id: i32) -> Result<Vec<Portfolio>, anyhow::Error>
+where
+    for<'e> &'e E: Executor<'e, Database = Any> + Acquire<'e, Database = Any>,
+{
+    let query = sqlx::query_as(
+        "SELECT portfolios.slug FROM portfolios \
+        JOIN portfolio_rights ON (portfolio_rights.portfolio_id = portfolios.id) \
+        WHERE portfolio_rights.user_id = $1 AND portfolio_rights.role = $2 \
+            AND portfolios.deleted_at IS NULL \
+        ORDER BY portfolios.id ASC",
+    );
+    let slugs: Vec<(String,)> = query
+        .bind(user_id)
+        .bind(CollaboratorRole::Owner)
+        .fetch_all(conn)
+        .await
+        .context("get owned portfolios for export failed")?;
+    let mut portfolios = Vec::with_capacity(slugs.len());
+    for (slug,) in slugs {
+        let portfolio = portfolio::get_portfolio(conn, &slug, Some(user_id), None, None)
+            .await?
+            .context("portfolio listed for the user could not be fetched")?;
+        portfolios.push(portfolio);
+    }
+    Ok(portfolios)
+}
@@ -0,0 +1,107 @@
+use std::time::SystemTime;
+
+use anyhow::Context;
+use data_encoding::BASE32_NOPAD;
+use ring::rand::{SecureRandom, SystemRandom};
+use sqlx::{Any, Executor};
+
+use crate::data::user::Invite;
+
+const CODE_BYTES_LEN: usize = 10;
+
+pub async fn create_invite<E>(
+    conn: &mut E,
+    user_id: i32,
+    expires_at: Option<i64>,
+) -> Result<Invite, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let mut code_bytes = [0u8; CODE_BYTES_LEN];
+    SystemRandom::new()
+        .fill(&mut code_bytes)
+        .expect("system random should be able to generate random bytes");
+    let code = BASE32_NOPAD.encode(&code_bytes);
+
+    let current_time =
+        SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as i64;
+    let query = sqlx::query_as(
+        "INSERT INTO invites (code, created_by, created_at, expires_at) VALUES ($1, $2, $3, $4) \
+        RETURNING code, created_by, created_at, expires_at, used_at",
+    );
+    query
+        .bind(&code)
+        .bind(user_id)
+        .bind(current_time)
+        .bind(expires_at)
+        .fetch_one(&mut *conn)
+        .await
+        .context("invite insert failed")
+}
+
+/// Returns the invites created by the user, along with the usernames of the
+/// users who registered with them.
+pub async fn get_invites<E>(conn: &E, user_id: i32) -> Result<Vec<Invite>, anyhow::Error>
+where
+    for<'e> &'e E: Executor<'e, Database = Any>,
+{
+    let query = sqlx::query_as(
+        "SELECT invites.code, invites.created_by, invites.created_at, invites.expires_at, \
+            invites.used_at, users.username AS used_by_username FROM invites \
+        LEFT JOIN users ON (users.id = invites.used_by) \
+        WHERE invites.created_by = $1 ORDER BY invites.created_at ASC",
+    );
+    query.bind(user_id).fetch_all(conn).await.context("get all invites failed")
+}
+
+/// Deletes the invite if it hasn't been used yet, returning false if the user
+/// has no such unused invite.
+pub async fn revoke_invite<E>(conn: &mut E, user_id: i32, code: &str) -> Result<bool, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let result =
+        sqlx::query("DELETE FROM invites WHERE code = $1 AND created_by = $2 AND used_at IS NULL")
+            .bind(code)
+            .bind(user_id)
+            .execute(conn)
+            .await
+            .context("invite delete failed")?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Marks the invite as used if it exists, hasn't been used, and hasn't
+/// expired. Returns false if the invite can't be used. Should be followed up
+/// with [set_invite_user] in the same transaction once the user is created.
+pub async fn claim_invite<E>(conn: &mut E, code: &str) -> Result<bool, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let current_time =
+        SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as i64;
+    let query = sqlx::query(
+        "UPDATE invites SET used_at = $1 \
+        WHERE code = $2 AND used_at IS NULL AND (expires_at IS NULL OR expires_at > $3)",
+    );
+    let result = query
+        .bind(current_time)
+        .bind(code.trim().to_ascii_uppercase())
+        .bind(current_time)
+        .execute(conn)
+        .await
+        .context("invite claim failed")?;
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn set_invite_user<E>(conn: &mut E, code: &str, user_id: i32) -> Result<(), anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    sqlx::query("UPDATE invites SET used_by = $1 WHERE code = $2")
+        .bind(user_id)
+        .bind(code.trim().to_ascii_uppercase())
+        .execute(conn)
+        .await
+        .context("invite user update failed")?;
+    Ok(())
+}
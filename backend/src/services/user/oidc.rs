@@ -0,0 +1,556 @@
+//! Logging in with an external identity provider, using the OpenID Connect
+//! authorization code flow as described in [OpenID Connect Core 1.0] section
+//! 3.1, with [PKCE] on top.
+//!
+//! The ID token's signature is not checked, since it's received directly from
+//! the token endpoint rather than via the user's browser, which the spec allows
+//! in section 3.1.3.7. This does mean that the discovery URL should use https
+//! in production, as the TLS certificate is what ties the token to the issuer.
+//!
+//! [OpenID Connect Core 1.0]: https://openid.net/specs/openid-connect-core-1_0.html
+//! [PKCE]: https://datatracker.ietf.org/doc/html/rfc7636
+
+use std::time::SystemTime;
+
+use anyhow::{Context, anyhow};
+use data_encoding::BASE64URL_NOPAD;
+use reqwest::Url;
+use ring::digest::{self, SHA256};
+use ring::rand::{SecureRandom, SystemRandom};
+use sqlx::{Any, Executor};
+
+use crate::config::{self, OidcConfig};
+use crate::data::audit::{AuditAction, RequestOrigin};
+use crate::data::user::{LoginOutcome, OidcIdentity, OidcLogin, User};
+use crate::services::audit;
+
+const RANDOM_BYTES_LEN: usize = 32;
+
+/// The parts of the identity provider's discovery document that are needed
+/// for the authorization code flow.
+#[derive(serde::Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+}
+
+#[derive(serde::Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum Audience {
+    Single(String),
+    Multiple(Vec<String>),
+}
+
+#[derive(serde::Deserialize)]
+struct IdTokenClaims {
+    iss: String,
+    sub: String,
+    aud: Audience,
+    azp: Option<String>,
+    exp: i64,
+    nonce: Option<String>,
+    preferred_username: Option<String>,
+}
+
+/// The identity the user proved they own by logging in at the identity
+/// provider.
+#[derive(Debug)]
+pub struct VerifiedIdentity {
+    pub issuer: String,
+    pub subject: String,
+    /// The username the identity provider suggests for the user, if any.
+    pub preferred_username: Option<String>,
+}
+
+/// Starts a new login, to be passed to [authorization_url]. If `link_user_id`
+/// is given, the identity will be linked to that user instead of logging in.
+pub async fn create_login<E>(
+    conn: &mut E,
+    link_user_id: Option<i32>,
+) -> Result<OidcLogin, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let current_time =
+        SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as i64;
+    let login = OidcLogin {
+        state: random_string(),
+        nonce: random_string(),
+        code_verifier: random_string(),
+        link_user_id,
+        created_at: current_time,
+    };
+
+    let query = sqlx::query(
+        "INSERT INTO oidc_logins (state, nonce, code_verifier, link_user_id, created_at) \
+        VALUES                   ($1,    $2,    $3,            $4,           $5)",
+    );
+    query
+        .bind(&login.state)
+        .bind(&login.nonce)
+        .bind(&login.code_verifier)
+        .bind(login.link_user_id)
+        .bind(login.created_at)
+        .execute(conn)
+        .await
+        .context("oidc login insert failed")?;
+
+    Ok(login)
+}
+
+/// Returns the URL of the identity provider's login page, where the user
+/// should be redirected to.
+pub async fn authorization_url(
+    http_client: &reqwest::Client,
+    oidc: &OidcConfig,
+    login: &OidcLogin,
+) -> Result<String, anyhow::Error> {
+    let metadata = fetch_provider_metadata(http_client, oidc).await?;
+    let code_challenge =
+        BASE64URL_NOPAD.encode(digest::digest(&SHA256, login.code_verifier.as_bytes()).as_ref());
+    let url = Url::parse_with_params(
+        &metadata.authorization_endpoint,
+        [
+            ("response_type", "code"),
+            ("client_id", &oidc.client_id),
+            ("redirect_uri", &oidc.redirect_url),
+            ("scope", &oidc.scopes),
+            ("state", &login.state),
+            ("nonce", &login.nonce),
+            ("code_challenge", &code_challenge),
+            ("code_challenge_method", "S256"),
+        ],
+    )
+    .context("authorization endpoint is not a valid url")?;
+    Ok(url.into())
+}
+
+/// Removes and returns the login with the given state, if it exists and hasn't
+/// expired. Each login can only be completed once.
+pub async fn take_login<E>(conn: &mut E, state: &str) -> Result<Option<OidcLogin>, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let current_time =
+        SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as i64;
+    let expiration_seconds = config::oidc_login_expiration_seconds() as i64;
+    let query = sqlx::query_as(
+        "DELETE FROM oidc_logins WHERE state = $1 AND created_at >= $2 \
+        RETURNING state, nonce, code_verifier, link_user_id, created_at",
+    );
+    query
+        .bind(state)
+        .bind(current_time - expiration_seconds)
+        .fetch_optional(conn)
+        .await
+        .context("oidc login fetch failed")
+}
+
+/// Exchanges the authorization code for an ID token at the identity provider,
+/// and checks that the token is meant for this login.
+pub async fn exchange_code(
+    http_client: &reqwest::Client,
+    oidc: &OidcConfig,
+    login: &OidcLogin,
+    code: &str,
+) -> Result<VerifiedIdentity, anyhow::Error> {
+    let metadata = fetch_provider_metadata(http_client, oidc).await?;
+
+    let mut request = http_client.post(&metadata.token_endpoint).form(&[
+        ("grant_type", "authorization_code"),
+        ("code", code),
+        ("redirect_uri", &oidc.redirect_url),
+        ("client_id", &oidc.client_id),
+        ("code_verifier", &login.code_verifier),
+    ]);
+    if let Some(client_secret) = &oidc.client_secret {
+        request = request.basic_auth(&oidc.client_id, Some(client_secret));
+    }
+    let response = request.send().await.context("token request failed")?;
+    let response = response.error_for_status().context("token request was rejected")?;
+    let TokenResponse { id_token } =
+        response.json().await.context("token response is not valid json")?;
+
+    let claims = id_token.split('.').nth(1).context("id token is not a jwt")?;
+    let claims = BASE64URL_NOPAD
+        .decode(claims.trim_end_matches('=').as_bytes())
+        .context("id token claims are not valid base64url")?;
+    let claims: IdTokenClaims =
+        serde_json::from_slice(&claims).context("id token claims are not valid json")?;
+
+    // The checks from section 3.1.3.7, except for the ones about signatures
+    let current_time =
+        SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as i64;
+    if claims.iss != metadata.issuer {
+        return Err(anyhow!("id token issuer {:?} is not {:?}", claims.iss, metadata.issuer));
+    }
+    let audience_ok = match &claims.aud {
+        Audience::Single(aud) => *aud == oidc.client_id,
+        Audience::Multiple(auds) => {
+            auds.contains(&oidc.client_id)
+                && (auds.len() == 1 || claims.azp.as_ref() == Some(&oidc.client_id))
+        }
+    };
+    if !audience_ok {
+        return Err(anyhow!("id token is not meant for this client"));
+    }
+    if claims.exp <= current_time {
+        return Err(anyhow!("id token has expired"));
+    }
+    if claims.nonce.as_ref() != Some(&login.nonce) {
+        return Err(anyhow!("id token nonce does not match"));
+    }
+
+    Ok(VerifiedIdentity {
+        issuer: claims.iss,
+        subject: claims.sub,
+        preferred_username: claims.preferred_username,
+    })
+}
+
+/// Logs in the user linked to the identity, just like [super::login] does for
+/// passwords. Returns None if the identity isn't linked to any user.
+pub async fn login<E>(
+    conn: &mut E,
+    origin: &RequestOrigin,
+    identity: &VerifiedIdentity,
+) -> Result<Option<LoginOutcome>, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let query = sqlx::query_as(
+        "SELECT users.* FROM users \
+        JOIN oidc_identities ON oidc_identities.user_id = users.id \
+        WHERE oidc_identities.issuer = $1 AND oidc_identities.subject = $2",
+    );
+    let user: Option<User> = query
+        .bind(&identity.issuer)
+        .bind(&identity.subject)
+        .fetch_optional(&mut *conn)
+        .await
+        .context("user fetch on oidc login failed")?;
+    let Some(user) = user else {
+        return Ok(None);
+    };
+
+    Ok(Some(super::start_login(conn, origin, &user).await?))
+}
+
+/// Links the identity to the user, returning false if the identity is already
+/// linked to someone (including the user themselves).
+pub async fn link_identity<E>(
+    conn: &mut E,
+    origin: &RequestOrigin,
+    user_id: i32,
+    identity: &VerifiedIdentity,
+) -> Result<bool, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let current_time =
+        SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as i64;
+    let query = sqlx::query(
+        "INSERT INTO oidc_identities (issuer, subject, user_id, created_at) \
+        VALUES ($1, $2, $3, $4) \
+        ON CONFLICT DO NOTHING",
+    );
+    let result = query
+        .bind(&identity.issuer)
+        .bind(&identity.subject)
+        .bind(user_id)
+        .bind(current_time)
+        .execute(&mut *conn)
+        .await
+        .context("oidc identity insert failed")?;
+    if result.rows_affected() == 0 {
+        return Ok(false);
+    }
+    let target = format!("{} {}", identity.issuer, identity.subject);
+    audit::record(&mut *conn, origin, Some(user_id), AuditAction::OidcIdentityLinked, &target)
+        .await?;
+    Ok(true)
+}
+
+pub async fn get_identities<E>(conn: &E, user_id: i32) -> Result<Vec<OidcIdentity>, anyhow::Error>
+where
+    for<'e> &'e E: Executor<'e, Database = Any>,
+{
+    let query = sqlx::query_as(
+        "SELECT issuer, subject, created_at FROM oidc_identities WHERE user_id = $1 ORDER BY created_at ASC",
+    );
+    query.bind(user_id).fetch_all(conn).await.context("get all oidc identities failed")
+}
+
+/// Returns true if the identity is the only way the user can log in, i.e. they
+/// don't have a password or other identities.
+pub async fn is_only_login_method<E>(
+    conn: &mut E,
+    user_id: i32,
+    issuer: &str,
+    subject: &str,
+) -> Result<bool, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let (password_key_base64,): (Option<String>,) =
+        sqlx::query_as("SELECT password_key_base64 FROM users WHERE id = $1")
+            .bind(user_id)
+            .fetch_one(&mut *conn)
+            .await
+            .context("user fetch on oidc unlink check failed")?;
+    if password_key_base64.is_some() {
+        return Ok(false);
+    }
+
+    let query = sqlx::query_as(
+        "SELECT COUNT(*) FROM oidc_identities \
+        WHERE user_id = $1 AND NOT (issuer = $2 AND subject = $3)",
+    );
+    let (other_identities,): (i64,) = query
+        .bind(user_id)
+        .bind(issuer)
+        .bind(subject)
+        .fetch_one(&mut *conn)
+        .await
+        .context("oidc identity count failed")?;
+    Ok(other_identities == 0)
+}
+
+/// Unlinks the identity from the user, returning false if the user has no
+/// such identity.
+pub async fn unlink_identity<E>(
+    conn: &mut E,
+    origin: &RequestOrigin,
+    user_id: i32,
+    issuer: &str,
+    subject: &str,
+) -> Result<bool, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let query = sqlx::query(
+        "DELETE FROM oidc_identities WHERE user_id = $1 AND issuer = $2 AND subject = $3",
+    );
+    let result = query
+        .bind(user_id)
+        .bind(issuer)
+        .bind(subject)
+        .execute(&mut *conn)
+        .await
+        .context("oidc identity delete failed")?;
+    if result.rows_affected() == 0 {
+        return Ok(false);
+    }
+    let target = format!("{issuer} {subject}");
+    audit::record(&mut *conn, origin, Some(user_id), AuditAction::OidcIdentityUnlinked, &target)
+        .await?;
+    Ok(true)
+}
+
+pub async fn remove_logins<E>(
+    conn: &mut E,
+    before_timestamp: SystemTime,
+) -> Result<(), anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let before_timestamp =
+        before_timestamp.duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as i64;
+    sqlx::query("DELETE FROM oidc_logins WHERE created_at < $1")
+        .bind(before_timestamp)
+        .execute(conn)
+        .await
+        .context("removing oidc logins failed")?;
+    Ok(())
+}
+
+/// The discovery document is fetched for every login rather than cached, so
+/// that changes at the identity provider are picked up without a restart.
+async fn fetch_provider_metadata(
+    http_client: &reqwest::Client,
+    oidc: &OidcConfig,
+) -> Result<ProviderMetadata, anyhow::Error> {
+    let response =
+        http_client.get(&oidc.discovery_url).send().await.context("discovery request failed")?;
+    let response = response.error_for_status().context("discovery request was rejected")?;
+    response.json().await.context("discovery document is not valid json")
+}
+
+fn random_string() -> String {
+    let mut bytes = [0u8; RANDOM_BYTES_LEN];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .expect("system random should be able to generate random bytes");
+    BASE64URL_NOPAD.encode(&bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::time::SystemTime;
+
+    use axum::extract::{Form, State};
+    use axum::http::StatusCode;
+    use axum::routing::{get, post};
+    use axum::{Json, Router};
+    use data_encoding::BASE64URL_NOPAD;
+    use reqwest::Url;
+    use ring::digest::{self, SHA256};
+    use serde_json::{Value, json};
+
+    use super::{OidcConfig, authorization_url, exchange_code};
+    use crate::data::user::OidcLogin;
+
+    const CLIENT_ID: &str = "crate-test";
+    const VALID_CODE: &str = "valid-code";
+
+    fn test_login() -> OidcLogin {
+        OidcLogin {
+            state: "test-state".into(),
+            nonce: "test-nonce".into(),
+            code_verifier: "test-code-verifier".into(),
+            link_user_id: None,
+            created_at: 0,
+        }
+    }
+
+    fn current_time() -> i64 {
+        SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as i64
+    }
+
+    /// Claims the mock identity provider would put in a valid ID token for
+    /// [test_login].
+    fn valid_claims(issuer: &str) -> Value {
+        json!({
+            "iss": issuer,
+            "sub": "test-subject",
+            "aud": CLIENT_ID,
+            "exp": current_time() + 60,
+            "nonce": test_login().nonce,
+            "preferred_username": "alice",
+        })
+    }
+
+    /// Starts a local identity provider serving the discovery document and a
+    /// token endpoint, which hands out an ID token with the claims returned by
+    /// `claims` (given the issuer) for [VALID_CODE] and [test_login]'s code
+    /// verifier. Returns the configuration for logging in with it.
+    async fn start_mock_idp(claims: impl FnOnce(&str) -> Value) -> OidcConfig {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let issuer = format!("http://{}", listener.local_addr().unwrap());
+
+        let header = BASE64URL_NOPAD.encode(br#"{"alg":"none"}"#);
+        let claims = BASE64URL_NOPAD.encode(claims(&issuer).to_string().as_bytes());
+        let id_token = format!("{header}.{claims}.");
+
+        let discovery = json!({
+            "issuer": issuer,
+            "authorization_endpoint": format!("{issuer}/authorize"),
+            "token_endpoint": format!("{issuer}/token"),
+        });
+        let router = Router::new()
+            .route("/.well-known/openid-configuration", get(move || async { Json(discovery) }))
+            .route("/token", post(token))
+            .with_state(id_token);
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+
+        OidcConfig {
+            discovery_url: format!("{issuer}/.well-known/openid-configuration"),
+            client_id: CLIENT_ID.into(),
+            client_secret: None,
+            redirect_url: "http://localhost/oidc-callback".into(),
+            scopes: "openid profile".into(),
+        }
+    }
+
+    async fn token(
+        State(id_token): State<String>,
+        Form(params): Form<HashMap<String, String>>,
+    ) -> Result<Json<Value>, StatusCode> {
+        let param = |name: &str| params.get(name).map(String::as_str);
+        if param("grant_type") != Some("authorization_code")
+            || param("code") != Some(VALID_CODE)
+            || param("client_id") != Some(CLIENT_ID)
+            || param("code_verifier") != Some(&test_login().code_verifier)
+        {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        Ok(Json(json!({ "id_token": id_token, "token_type": "Bearer" })))
+    }
+
+    #[tokio::test]
+    async fn authorization_url_has_pkce_challenge() {
+        let oidc = start_mock_idp(valid_claims).await;
+        let login = test_login();
+        let url = authorization_url(&reqwest::Client::new(), &oidc, &login).await.unwrap();
+
+        let url = Url::parse(&url).unwrap();
+        assert_eq!(url.path(), "/authorize");
+        let params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+        let challenge = BASE64URL_NOPAD
+            .encode(digest::digest(&SHA256, login.code_verifier.as_bytes()).as_ref());
+        assert_eq!(params["client_id"], CLIENT_ID);
+        assert_eq!(params["state"], login.state);
+        assert_eq!(params["nonce"], login.nonce);
+        assert_eq!(params["code_challenge"], challenge);
+        assert_eq!(params["code_challenge_method"], "S256");
+    }
+
+    #[tokio::test]
+    async fn exchange_code_returns_identity() {
+        let oidc = start_mock_idp(valid_claims).await;
+        let identity =
+            exchange_code(&reqwest::Client::new(), &oidc, &test_login(), VALID_CODE).await.unwrap();
+
+        assert!(oidc.discovery_url.starts_with(&identity.issuer));
+        assert_eq!(identity.subject, "test-subject");
+        assert_eq!(identity.preferred_username.as_deref(), Some("alice"));
+    }
+
+    #[tokio::test]
+    async fn exchange_code_rejects_invalid_code() {
+        let oidc = start_mock_idp(valid_claims).await;
+        let result =
+            exchange_code(&reqwest::Client::new(), &oidc, &test_login(), "invalid-code").await;
+        assert!(result.is_err());
+    }
+
+    /// Checks that the ID token is rejected after changing one of its claims.
+    async fn assert_rejected(claim: &str, value: Value) {
+        let oidc = start_mock_idp(|issuer| {
+            let mut claims = valid_claims(issuer);
+            claims[claim] = value;
+            claims
+        })
+        .await;
+        let result = exchange_code(&reqwest::Client::new(), &oidc, &test_login(), VALID_CODE).await;
+        assert!(result.is_err(), "id token with a different {claim} was accepted");
+    }
+
+    #[tokio::test]
+    async fn exchange_code_rejects_other_issuer() {
+        assert_rejected("iss", json!("https://idp.example.com")).await;
+    }
+
+    #[tokio::test]
+    async fn exchange_code_rejects_other_audience() {
+        assert_rejected("aud", json!("someone-else")).await;
+        assert_rejected("aud", json!([CLIENT_ID, "someone-else"])).await;
+    }
+
+    #[tokio::test]
+    async fn exchange_code_rejects_expired_token() {
+        assert_rejected("exp", json!(current_time() - 1)).await;
+    }
+
+    #[tokio::test]
+    async fn exchange_code_rejects_other_nonce() {
+        assert_rejected("nonce", json!("other-nonce")).await;
+    }
+}
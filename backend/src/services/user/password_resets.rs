@@ -0,0 +1,114 @@
+use std::time::SystemTime;
+
+use anyhow::Context;
+use data_encoding::{BASE64, BASE64URL_NOPAD};
+use ring::digest::{self, SHA256};
+use ring::rand::{SecureRandom, SystemRandom};
+use sqlx::{Any, Executor};
+
+use crate::array_string_types::UsernameString;
+use crate::config;
+use crate::data::audit::{AuditAction, RequestOrigin};
+use crate::services::audit;
+
+const TOKEN_BYTES_LEN: usize = 32;
+
+/// Creates a one-time password reset token for the user, returning the token
+/// and its expiration time in seconds since the unix epoch. Any previous reset
+/// tokens for the user stop working. The reset is recorded in the audit log
+/// as an action of the administrator who created it.
+pub async fn create_password_reset<E>(
+    conn: &mut E,
+    origin: &RequestOrigin,
+    admin_user_id: i32,
+    user_id: i32,
+) -> Result<(String, i64), anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    sqlx::query("DELETE FROM password_resets WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *conn)
+        .await
+        .context("removing old password resets failed")?;
+
+    let (username,): (String,) = sqlx::query_as("SELECT username FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_one(&mut *conn)
+        .await
+        .context("username fetch for password reset failed")?;
+    let actor = Some(admin_user_id);
+    audit::record(&mut *conn, origin, actor, AuditAction::PasswordResetCreated, &username).await?;
+
+    let mut token_bytes = [0u8; TOKEN_BYTES_LEN];
+    SystemRandom::new()
+        .fill(&mut token_bytes)
+        .expect("system random should be able to generate random bytes");
+    let token = BASE64URL_NOPAD.encode(&token_bytes);
+
+    let current_time =
+        SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as i64;
+    let expires_at = current_time + config::password_reset_expiration_seconds() as i64;
+    let query = sqlx::query(
+        "INSERT INTO password_resets (token_hash_base64, user_id, created_at, expires_at) \
+        VALUES ($1, $2, $3, $4)",
+    );
+    let result = query
+        .bind(hash_token(&token))
+        .bind(user_id)
+        .bind(current_time)
+        .bind(expires_at)
+        .execute(&mut *conn)
+        .await
+        .context("password reset insert failed")?;
+    assert_eq!(1, result.rows_affected());
+
+    Ok((token, expires_at))
+}
+
+/// Sets the password of the user the token was created for, and logs them out
+/// everywhere. Returns false if the token doesn't exist or has expired.
+pub async fn use_password_reset<E>(
+    conn: &mut E,
+    origin: &RequestOrigin,
+    token: &str,
+    password: &str,
+) -> Result<bool, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let current_time =
+        SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as i64;
+    let query = sqlx::query_as(
+        "SELECT users.id, users.username FROM password_resets \
+        JOIN users ON (users.id = password_resets.user_id) \
+        WHERE password_resets.token_hash_base64 = $1 AND password_resets.expires_at > $2",
+    );
+    let user: Option<(i32, UsernameString)> = query
+        .bind(hash_token(token))
+        .bind(current_time)
+        .fetch_optional(&mut *conn)
+        .await
+        .context("password reset fetch failed")?;
+    let Some((user_id, username)) = user else {
+        return Ok(false);
+    };
+
+    super::set_password(&mut *conn, user_id, username, password).await?;
+    audit::record(&mut *conn, origin, Some(user_id), AuditAction::PasswordReset, &username.0)
+        .await?;
+    sqlx::query("DELETE FROM password_resets WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *conn)
+        .await
+        .context("removing used password resets failed")?;
+    super::remove_user_sessions(&mut *conn, user_id).await?;
+
+    Ok(true)
+}
+
+/// The tokens are random enough that a plain hash is sufficient, no need for
+/// pbkdf2 like with passwords.
+fn hash_token(token: &str) -> String {
+    BASE64.encode(digest::digest(&SHA256, token.as_bytes()).as_ref())
+}
@@ -0,0 +1,146 @@
+use std::time::SystemTime;
+
+use anyhow::Context;
+use sqlx::{Any, Executor};
+
+use crate::array_string_types::ContentType;
+use crate::data::user::{Avatar, ProfileLink, UserProfile};
+use crate::data::work::BytesBase64;
+
+/// The longest allowed display name, in characters.
+pub const MAX_DISPLAY_NAME_LEN: usize = 100;
+/// The longest allowed bio, in characters.
+pub const MAX_BIO_LEN: usize = 5000;
+pub const MAX_LINKS: usize = 20;
+/// The longest allowed link title, in characters.
+pub const MAX_LINK_TITLE_LEN: usize = 100;
+/// The longest allowed link url, in bytes.
+pub const MAX_LINK_HREF_LEN: usize = 2000;
+
+#[derive(sqlx::FromRow)]
+struct ProfileRow {
+    display_name: String,
+    bio: String,
+    avatar_content_type: Option<ContentType>,
+    avatar_bytes_base64: Option<BytesBase64>,
+    default_author: i32,
+}
+
+/// Returns the user's profile. Users who haven't filled out their profile get
+/// an empty one.
+pub async fn get_profile<E>(conn: &E, user_id: i32) -> Result<UserProfile, anyhow::Error>
+where
+    for<'e> &'e E: Executor<'e, Database = Any>,
+{
+    let query = sqlx::query_as(
+        "SELECT display_name, bio, avatar_content_type, avatar_bytes_base64, default_author \
+        FROM user_profiles WHERE user_id = $1",
+    );
+    let row: Option<ProfileRow> =
+        query.bind(user_id).fetch_optional(conn).await.context("get user profile failed")?;
+    let Some(row) = row else {
+        return Ok(UserProfile::default());
+    };
+
+    let links =
+        sqlx::query_as("SELECT * FROM user_profile_links WHERE user_id = $1 ORDER BY order_number")
+            .bind(user_id)
+            .fetch_all(conn)
+            .await
+            .context("get user profile links failed")?;
+
+    let avatar = match (row.avatar_content_type, row.avatar_bytes_base64) {
+        (Some(content_type), Some(bytes_base64)) => Some(Avatar { content_type, bytes_base64 }),
+        _ => None,
+    };
+
+    Ok(UserProfile {
+        display_name: row.display_name,
+        bio: row.bio,
+        avatar,
+        links,
+        default_author: row.default_author == 1,
+    })
+}
+
+/// Returns the id of the user with the username, if their profile can be
+/// shown publicly, i.e. their account isn't disabled.
+pub async fn get_public_user_id<E>(conn: &E, username: &str) -> Result<Option<i32>, anyhow::Error>
+where
+    for<'e> &'e E: Executor<'e, Database = Any>,
+{
+    let query = sqlx::query_as("SELECT id FROM users WHERE username = $1 AND disabled_at IS NULL");
+    let user_id: Option<(i32,)> =
+        query.bind(username).fetch_optional(conn).await.context("public user id fetch failed")?;
+    Ok(user_id.map(|(id,)| id))
+}
+
+pub async fn update_profile<E>(
+    conn: &mut E,
+    user_id: i32,
+    profile: &UserProfile,
+) -> Result<(), anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let current_time =
+        SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as i64;
+    let query = sqlx::query(
+        "INSERT INTO user_profiles (user_id, display_name, bio, avatar_content_type, avatar_bytes_base64, default_author, updated_at) \
+        VALUES                     ($1,      $2,           $3,  $4,                  $5,                  $6,             $7) \
+        ON CONFLICT (user_id) DO UPDATE SET display_name = $2, bio = $3, avatar_content_type = $4, \
+            avatar_bytes_base64 = $5, default_author = $6, updated_at = $7",
+    );
+    let avatar = profile.avatar.as_ref();
+    query
+        .bind(user_id)
+        .bind(&profile.display_name)
+        .bind(&profile.bio)
+        .bind(avatar.map(|avatar| &avatar.content_type))
+        .bind(avatar.map(|avatar| &avatar.bytes_base64))
+        .bind(if profile.default_author { 1 } else { 0 })
+        .bind(current_time)
+        .execute(&mut *conn)
+        .await
+        .context("user profile upsert failed")?;
+
+    sqlx::query("DELETE FROM user_profile_links WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *conn)
+        .await
+        .context("delete user profile links before insert failed")?;
+    for (i, ProfileLink { title, href }) in profile.links.iter().enumerate() {
+        let query = sqlx::query(
+            "INSERT INTO user_profile_links (user_id, order_number, title, href) \
+            VALUES ($1, $2, $3, $4)",
+        );
+        query
+            .bind(user_id)
+            .bind(i as i32)
+            .bind(title)
+            .bind(href)
+            .execute(&mut *conn)
+            .await
+            .context("insert into user profile links failed")?;
+    }
+
+    Ok(())
+}
+
+/// Returns the author name for the user's new portfolios, if they've set their
+/// profile's display name to be used as such.
+pub async fn get_default_author<E>(
+    conn: &mut E,
+    user_id: i32,
+) -> Result<Option<String>, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let query = sqlx::query_as(
+        "SELECT display_name FROM user_profiles \
+        WHERE user_id = $1 AND default_author = 1 AND display_name <> ''",
+    );
+    let display_name: Option<(String,)> =
+        query.bind(user_id).fetch_optional(conn).await.context("get default author failed")?;
+    Ok(display_name.map(|(display_name,)| display_name))
+}
@@ -0,0 +1,344 @@
+//! Time-based one-time passwords as described in [RFC 6238], using the
+//! default parameters (HMAC-SHA1, 6 digits, 30 second steps) since those are
+//! the only ones authenticator apps reliably support.
+//!
+//! [RFC 6238]: https://datatracker.ietf.org/doc/html/rfc6238
+
+use std::time::SystemTime;
+
+use anyhow::Context;
+use data_encoding::{BASE32_NOPAD, BASE64};
+use ring::digest::{self, SHA256};
+use ring::hmac::{self, HMAC_SHA1_FOR_LEGACY_USE_ONLY};
+use ring::rand::{SecureRandom, SystemRandom};
+use sqlx::{Any, Executor};
+
+use crate::array_string_types::UuidString;
+use crate::config;
+use crate::data::audit::{AuditAction, RequestOrigin};
+use crate::data::user::{PendingLogin, Session, TotpEnrollment};
+use crate::services::audit;
+
+const SECRET_BYTES_LEN: usize = 20;
+const STEP_SECONDS: u64 = 30;
+const DIGITS: usize = 6;
+/// How many steps before or after the current one are still accepted, to allow
+/// for some clock drift between the server and the user's device.
+const ALLOWED_STEP_DRIFT: u64 = 1;
+const RECOVERY_CODE_COUNT: usize = 10;
+const RECOVERY_CODE_BYTES_LEN: usize = 10;
+/// How many codes can be tried for a pending login before it's deleted, and
+/// the password has to be entered again.
+const MAX_CODE_ATTEMPTS: i32 = 5;
+
+/// Generates a new TOTP secret for the user, replacing any previous
+/// unconfirmed one. Returns None if the user already has TOTP enabled.
+pub async fn begin_enrollment<E>(
+    conn: &mut E,
+    user_id: i32,
+) -> Result<Option<TotpEnrollment>, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let mut secret = [0u8; SECRET_BYTES_LEN];
+    SystemRandom::new()
+        .fill(&mut secret)
+        .expect("system random should be able to generate random bytes");
+    let secret_base32 = BASE32_NOPAD.encode(&secret);
+
+    let query = sqlx::query_as(
+        "UPDATE users SET totp_secret_base32 = $1 \
+        WHERE id = $2 AND totp_enabled_at IS NULL \
+        RETURNING username",
+    );
+    let username: Option<(String,)> = query
+        .bind(&secret_base32)
+        .bind(user_id)
+        .fetch_optional(&mut *conn)
+        .await
+        .context("totp secret update failed")?;
+    let Some((username,)) = username else {
+        return Ok(None);
+    };
+
+    let issuer = percent_encode(&config::totp_issuer());
+    let account = percent_encode(&username);
+    let otpauth_uri = format!(
+        "otpauth://totp/{issuer}:{account}?secret={secret_base32}&issuer={issuer}\
+        &algorithm=SHA1&digits={DIGITS}&period={STEP_SECONDS}"
+    );
+
+    Ok(Some(TotpEnrollment { secret_base32, otpauth_uri }))
+}
+
+/// Enables TOTP for the user if the code matches the secret generated in
+/// [begin_enrollment]. Returns the recovery codes generated for the user, or
+/// None if the code was wrong or there was no enrollment in progress.
+pub async fn confirm_enrollment<E>(
+    conn: &mut E,
+    origin: &RequestOrigin,
+    user_id: i32,
+    code: &str,
+) -> Result<Option<Vec<String>>, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let query = sqlx::query_as(
+        "SELECT totp_secret_base32, totp_last_step FROM users \
+        WHERE id = $1 AND totp_enabled_at IS NULL AND totp_secret_base32 IS NOT NULL",
+    );
+    let secret: Option<(String, Option<i64>)> =
+        query.bind(user_id).fetch_optional(&mut *conn).await.context("totp secret fetch failed")?;
+    let Some((secret_base32, last_step)) = secret else {
+        return Ok(None);
+    };
+    let Some(step) = verify_code(&secret_base32, code, last_step) else {
+        return Ok(None);
+    };
+
+    let current_time =
+        SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as i64;
+    sqlx::query("UPDATE users SET totp_enabled_at = $1, totp_last_step = $2 WHERE id = $3")
+        .bind(current_time)
+        .bind(step)
+        .bind(user_id)
+        .execute(&mut *conn)
+        .await
+        .context("enabling totp failed")?;
+
+    let recovery_codes = replace_recovery_codes(&mut *conn, user_id).await?;
+    audit::record(&mut *conn, origin, Some(user_id), AuditAction::TotpEnabled, "").await?;
+
+    Ok(Some(recovery_codes))
+}
+
+/// Disables TOTP for the user if the code is a valid TOTP or recovery code.
+/// Returns false if the code was wrong.
+pub async fn disable<E>(
+    conn: &mut E,
+    origin: &RequestOrigin,
+    user_id: i32,
+    code: &str,
+) -> Result<bool, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    if !check_code(&mut *conn, user_id, code).await? {
+        return Ok(false);
+    }
+
+    let query = sqlx::query(
+        "UPDATE users SET totp_secret_base32 = NULL, totp_enabled_at = NULL, totp_last_step = NULL \
+        WHERE id = $1",
+    );
+    query.bind(user_id).execute(&mut *conn).await.context("disabling totp failed")?;
+    sqlx::query("DELETE FROM totp_recovery_codes WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *conn)
+        .await
+        .context("removing recovery codes failed")?;
+    audit::record(&mut *conn, origin, Some(user_id), AuditAction::TotpDisabled, "").await?;
+
+    Ok(true)
+}
+
+/// Turns a [PendingLogin] into a [Session] if the code is a valid TOTP or
+/// recovery code. Returns None if the pending login does not exist (or has
+/// expired, or has used up its attempts), or if the code was wrong.
+pub async fn complete_login<E>(
+    conn: &mut E,
+    origin: &RequestOrigin,
+    pending_login_id: UuidString,
+    code: &str,
+) -> Result<Option<Session>, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let current_time =
+        SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as i64;
+    let expiration_seconds = config::pending_login_expiration_seconds() as i64;
+    // The attempt is counted before checking the code, so that concurrent
+    // attempts can't get past the limit
+    let query = sqlx::query_as(
+        "UPDATE pending_logins SET attempts = attempts + 1 \
+        WHERE uuid = $1 AND created_at >= $2 AND attempts < $3 \
+        RETURNING uuid, user_id, created_at, attempts",
+    );
+    let pending_login: Option<(UuidString, i32, i64, i32)> = query
+        .bind(&pending_login_id)
+        .bind(current_time - expiration_seconds)
+        .bind(MAX_CODE_ATTEMPTS)
+        .fetch_optional(&mut *conn)
+        .await
+        .context("pending login attempt update failed")?;
+    let Some((uuid, user_id, created_at, attempts)) = pending_login else {
+        return Ok(None);
+    };
+    let pending_login = PendingLogin { uuid, user_id, created_at };
+
+    let (username,): (String,) = sqlx::query_as("SELECT username FROM users WHERE id = $1")
+        .bind(pending_login.user_id)
+        .fetch_one(&mut *conn)
+        .await
+        .context("username fetch on totp login failed")?;
+
+    let user_id = Some(pending_login.user_id);
+    if !check_code(&mut *conn, pending_login.user_id, code).await? {
+        if attempts >= MAX_CODE_ATTEMPTS {
+            sqlx::query("DELETE FROM pending_logins WHERE uuid = $1")
+                .bind(&pending_login.uuid)
+                .execute(&mut *conn)
+                .await
+                .context("removing the pending login with no attempts left failed")?;
+        }
+        audit::record(&mut *conn, origin, user_id, AuditAction::LoginFailed, &username).await?;
+        return Ok(None);
+    }
+
+    sqlx::query("DELETE FROM pending_logins WHERE uuid = $1")
+        .bind(&pending_login.uuid)
+        .execute(&mut *conn)
+        .await
+        .context("removing the completed pending login failed")?;
+
+    let session = super::create_session(&mut *conn, pending_login.user_id).await?;
+    audit::record(&mut *conn, origin, user_id, AuditAction::Login, &username).await?;
+    Ok(Some(session))
+}
+
+pub async fn remove_pending_logins<E>(
+    conn: &mut E,
+    before_timestamp: SystemTime,
+) -> Result<(), anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let before_timestamp =
+        before_timestamp.duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as i64;
+    sqlx::query("DELETE FROM pending_logins WHERE created_at < $1")
+        .bind(before_timestamp)
+        .execute(conn)
+        .await
+        .context("removing pending logins failed")?;
+    Ok(())
+}
+
+/// Checks the code against the user's TOTP secret, and if that doesn't match,
+/// against their recovery codes. A matching code is used up in either case.
+async fn check_code<E>(conn: &mut E, user_id: i32, code: &str) -> Result<bool, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let query = sqlx::query_as(
+        "SELECT totp_secret_base32, totp_last_step FROM users \
+        WHERE id = $1 AND totp_enabled_at IS NOT NULL",
+    );
+    let secret: Option<(String, Option<i64>)> =
+        query.bind(user_id).fetch_optional(&mut *conn).await.context("totp secret fetch failed")?;
+    let Some((secret_base32, last_step)) = secret else {
+        return Ok(false);
+    };
+
+    if let Some(step) = verify_code(&secret_base32, code, last_step) {
+        sqlx::query("UPDATE users SET totp_last_step = $1 WHERE id = $2")
+            .bind(step)
+            .bind(user_id)
+            .execute(&mut *conn)
+            .await
+            .context("totp last step update failed")?;
+        return Ok(true);
+    }
+
+    let result =
+        sqlx::query("DELETE FROM totp_recovery_codes WHERE user_id = $1 AND code_hash_base64 = $2")
+            .bind(user_id)
+            .bind(hash_recovery_code(code))
+            .execute(&mut *conn)
+            .await
+            .context("recovery code removal failed")?;
+    Ok(result.rows_affected() > 0)
+}
+
+async fn replace_recovery_codes<E>(conn: &mut E, user_id: i32) -> Result<Vec<String>, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    sqlx::query("DELETE FROM totp_recovery_codes WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *conn)
+        .await
+        .context("removing old recovery codes failed")?;
+
+    let random = SystemRandom::new();
+    let mut codes = Vec::with_capacity(RECOVERY_CODE_COUNT);
+    for _ in 0..RECOVERY_CODE_COUNT {
+        let mut code_bytes = [0u8; RECOVERY_CODE_BYTES_LEN];
+        random
+            .fill(&mut code_bytes)
+            .expect("system random should be able to generate random bytes");
+        let code = BASE32_NOPAD.encode(&code_bytes);
+        let code = format!("{}-{}-{}-{}", &code[0..4], &code[4..8], &code[8..12], &code[12..16]);
+
+        sqlx::query("INSERT INTO totp_recovery_codes (user_id, code_hash_base64) VALUES ($1, $2)")
+            .bind(user_id)
+            .bind(hash_recovery_code(&code))
+            .execute(&mut *conn)
+            .await
+            .context("recovery code insert failed")?;
+        codes.push(code);
+    }
+
+    Ok(codes)
+}
+
+/// The recovery codes are random enough that a plain hash is sufficient, no
+/// need for pbkdf2 like with passwords.
+fn hash_recovery_code(code: &str) -> String {
+    let normalized: String =
+        code.chars().filter(char::is_ascii_alphanumeric).map(|c| c.to_ascii_uppercase()).collect();
+    BASE64.encode(digest::digest(&SHA256, normalized.as_bytes()).as_ref())
+}
+
+/// Returns the time step the code matched, if it matched one that is close
+/// enough to the current time and is newer than `last_step`.
+fn verify_code(secret_base32: &str, code: &str, last_step: Option<i64>) -> Option<i64> {
+    let code = code.trim();
+    if code.len() != DIGITS || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let secret = BASE32_NOPAD.decode(secret_base32.as_bytes()).ok()?;
+    let key = hmac::Key::new(HMAC_SHA1_FOR_LEGACY_USE_ONLY, &secret);
+
+    let current_step =
+        SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() / STEP_SECONDS;
+    let first_step = current_step.saturating_sub(ALLOWED_STEP_DRIFT);
+    let last_step = last_step.map(|step| step as u64);
+    (first_step..=current_step + ALLOWED_STEP_DRIFT)
+        .filter(|&step| last_step.is_none_or(|last_step| step > last_step))
+        .find(|&step| hotp(&key, step).as_bytes() == code.as_bytes())
+        .map(|step| step as i64)
+}
+
+/// HOTP as described in [RFC 4226](https://datatracker.ietf.org/doc/html/rfc4226#section-5.3).
+fn hotp(key: &hmac::Key, counter: u64) -> String {
+    let tag = hmac::sign(key, &counter.to_be_bytes());
+    let hash = tag.as_ref();
+    let offset = (hash[hash.len() - 1] & 0xF) as usize;
+    let binary = u32::from_be_bytes(hash[offset..offset + 4].try_into().unwrap()) & 0x7FFF_FFFF;
+    format!("{:0width$}", binary % 10u32.pow(DIGITS as u32), width = DIGITS)
+}
+
+/// Percent-encodes everything except unreserved characters, for the label and
+/// issuer in the `otpauth://` URI.
+fn percent_encode(s: &str) -> String {
+    use core::fmt::Write;
+    let mut result = String::with_capacity(s.len());
+    for byte in s.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            result.push(byte as char);
+        } else {
+            write!(&mut result, "%{byte:02X}").unwrap();
+        }
+    }
+    result
+}
@@ -1,13 +1,23 @@
+use std::time::SystemTime;
+
 use anyhow::Context;
-use sqlx::{Any, Executor};
+use sqlx::{Acquire, Any, Executor};
 
+use crate::data::audit::{AuditAction, RequestOrigin};
+use crate::data::collaborator::{CollaborationTarget, CollaboratorRole};
+use crate::data::trash::TrashedItem;
 use crate::data::work::{Work, WorkRow};
+use crate::services::audit;
+use crate::services::portfolio::published;
+use crate::{config, services};
 
 pub mod big_files;
+pub mod revisions;
 mod subtables;
 
 pub async fn create_work<E>(
     conn: &mut E,
+    origin: &RequestOrigin,
     slug: &str,
     user_id: i32,
     new_work: Work,
@@ -15,9 +25,11 @@ pub async fn create_work<E>(
 where
     for<'e> &'e mut E: Executor<'e, Database = Any>,
 {
+    let current_time =
+        SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as i64;
     let query = sqlx::query_as(
-        "INSERT INTO works (slug, title, short_description, long_description) \
-        VALUES ($1, $2, $3, $4) \
+        "INSERT INTO works (slug, title, short_description, long_description, updated_at) \
+        VALUES ($1, $2, $3, $4, $5) \
         RETURNING *",
     );
     let row: WorkRow = query
@@ -25,16 +37,19 @@ where
         .bind(&new_work.row.title)
         .bind(&new_work.row.short_description)
         .bind(&new_work.row.long_description)
+        .bind(current_time)
         .fetch_one(&mut *conn)
         .await
         .context("work insert failed")?;
 
-    sqlx::query("INSERT INTO work_rights (user_id, work_id) VALUES ($1, $2)")
+    sqlx::query("INSERT INTO work_rights (user_id, work_id, role) VALUES ($1, $2, $3)")
         .bind(user_id)
         .bind(row.id)
+        .bind(CollaboratorRole::Owner)
         .execute(&mut *conn)
         .await
         .context("work-user rights insert failed")?;
+    audit::record(&mut *conn, origin, Some(user_id), AuditAction::WorkCreated, slug).await?;
 
     let work = subtables::update_work_details(
         &mut *conn,
@@ -45,34 +60,53 @@ where
     )
     .await
     .context("inserting details for the new work failed")?;
+    revisions::record_revision(&mut *conn, &work, Some(user_id)).await?;
 
     Ok(work)
 }
 
+/// Returns None if the user isn't an owner or editor of a work with the slug.
 pub async fn update_work<E>(
     conn: &mut E,
+    origin: &RequestOrigin,
     original_slug: &str,
     user_id: i32,
     new_version: Work,
-) -> Result<Work, anyhow::Error>
+) -> Result<Option<Work>, anyhow::Error>
 where
     for<'e> &'e mut E: Executor<'e, Database = Any>,
 {
+    revisions::record_initial_revision(&mut *conn, original_slug).await?;
+
+    let current_time =
+        SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as i64;
     let query = sqlx::query_as(
-        "UPDATE works SET slug = $1, title = $2, short_description = $3, long_description = $4 \
-        WHERE slug = $5 AND id IN ( select work_id from work_rights where user_id = $6 ) \
+        "UPDATE works SET slug = $1, title = $2, short_description = $3, long_description = $4, \
+            updated_at = $5 \
+        WHERE slug = $6 AND deleted_at IS NULL \
+            AND id IN ( select work_id from effective_work_rights where user_id = $7 and role in ($8, $9) ) \
         RETURNING *",
     );
-    let row: WorkRow = query
+    let row: Option<WorkRow> = query
         .bind(&new_version.row.slug)
         .bind(&new_version.row.title)
         .bind(&new_version.row.short_description)
         .bind(&new_version.row.long_description)
+        .bind(current_time)
         .bind(original_slug)
         .bind(user_id)
-        .fetch_one(&mut *conn)
+        .bind(CollaboratorRole::Owner)
+        .bind(CollaboratorRole::Editor)
+        .fetch_optional(&mut *conn)
         .await
         .context("work update failed")?;
+    let Some(row) = row else {
+        return Ok(None);
+    };
+    let slug = row.slug.0.as_str();
+    let target = CollaborationTarget::Work;
+    services::slug::record_change(&mut *conn, target, row.id, original_slug, slug).await?;
+    audit::record(&mut *conn, origin, Some(user_id), AuditAction::WorkUpdated, slug).await?;
 
     let work = subtables::update_work_details(
         &mut *conn,
@@ -83,52 +117,326 @@ where
     )
     .await
     .context("updating work details failed")?;
+    revisions::record_revision(&mut *conn, &work, Some(user_id)).await?;
 
-    Ok(work)
+    Ok(Some(work))
 }
 
-pub async fn get_works<E>(conn: &E, user_id: i32) -> Result<Vec<WorkRow>, anyhow::Error>
+/// Creates a copy of the work's draft under the new slug, owned by the user.
+/// Nothing is copied byte by byte: inline attachment contents are shared
+/// through the `attachment_contents` table like those of revisions, and big
+/// files are shared with the original's attachments, which keep owning their
+/// parts, see [big_files::hand_over_file_parts]. Shared big files can't be
+/// appended to. Returns None if the user isn't an owner or editor of a work
+/// with the slug.
+pub async fn clone_work<E>(
+    conn: &mut E,
+    origin: &RequestOrigin,
+    slug: &str,
+    new_slug: &str,
+    user_id: i32,
+) -> Result<Option<Work>, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let query = sqlx::query_as(
+        "SELECT * FROM works \
+        WHERE slug = $1 AND deleted_at IS NULL \
+            AND id IN ( select work_id from effective_work_rights where user_id = $2 and role in ($3, $4) )",
+    );
+    let row: Option<WorkRow> = query
+        .bind(slug)
+        .bind(user_id)
+        .bind(CollaboratorRole::Owner)
+        .bind(CollaboratorRole::Editor)
+        .fetch_optional(&mut *conn)
+        .await
+        .context("get work to clone failed")?;
+    let Some(row) = row else {
+        return Ok(None);
+    };
+    let original = subtables::fetch_work_details(&mut *conn, row).await?;
+
+    let work = create_work(&mut *conn, origin, new_slug, user_id, original).await?;
+    Ok(Some(work))
+}
+
+/// Moves the work to the trash, from where it can be restored until it's
+/// purged by [purge_deleted_works]. Returns false if the user doesn't own a
+/// work with the slug.
+pub async fn delete_work<E>(
+    conn: &mut E,
+    origin: &RequestOrigin,
+    slug: &str,
+    user_id: i32,
+) -> Result<bool, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let current_time =
+        SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as i64;
+    let query = sqlx::query(
+        "UPDATE works SET deleted_at = $1 \
+        WHERE slug = $2 AND deleted_at IS NULL \
+            AND id IN ( select work_id from effective_work_rights where user_id = $3 and role = $4 )",
+    );
+    let result = query
+        .bind(current_time)
+        .bind(slug)
+        .bind(user_id)
+        .bind(CollaboratorRole::Owner)
+        .execute(&mut *conn)
+        .await
+        .context("work soft delete failed")?;
+    if result.rows_affected() == 0 {
+        return Ok(false);
+    }
+
+    audit::record(&mut *conn, origin, Some(user_id), AuditAction::WorkDeleted, slug).await?;
+
+    Ok(true)
+}
+
+/// Takes the work back out of the trash. Returns false if the user doesn't own
+/// a deleted work with the slug.
+pub async fn restore_work<E>(
+    conn: &mut E,
+    origin: &RequestOrigin,
+    slug: &str,
+    user_id: i32,
+) -> Result<bool, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let query = sqlx::query(
+        "UPDATE works SET deleted_at = NULL \
+        WHERE slug = $1 AND deleted_at IS NOT NULL \
+            AND id IN ( select work_id from effective_work_rights where user_id = $2 and role = $3 )",
+    );
+    let result = query
+        .bind(slug)
+        .bind(user_id)
+        .bind(CollaboratorRole::Owner)
+        .execute(&mut *conn)
+        .await
+        .context("work restore failed")?;
+    if result.rows_affected() == 0 {
+        return Ok(false);
+    }
+
+    audit::record(&mut *conn, origin, Some(user_id), AuditAction::WorkRestored, slug).await?;
+
+    Ok(true)
+}
+
+pub async fn get_deleted_works<E>(conn: &E, user_id: i32) -> Result<Vec<TrashedItem>, anyhow::Error>
 where
     for<'e> &'e E: Executor<'e, Database = Any>,
 {
-    let works: Vec<WorkRow> =
-        sqlx::query_as("SELECT * FROM works JOIN work_rights ON (id = work_id) WHERE user_id = $1")
-            .bind(user_id)
-            .fetch_all(conn)
+    let query = sqlx::query_as(
+        "SELECT slug, title, deleted_at, deleted_at + $1 AS purge_at FROM works \
+        WHERE deleted_at IS NOT NULL \
+            AND id IN ( select work_id from effective_work_rights where user_id = $2 and role = $3 ) \
+        ORDER BY deleted_at DESC",
+    );
+    query
+        .bind(config::trash_retention_seconds().map(|seconds| seconds as i64))
+        .bind(user_id)
+        .bind(CollaboratorRole::Owner)
+        .fetch_all(conn)
+        .await
+        .context("get deleted works failed")
+}
+
+/// Permanently deletes the works which were moved to the trash before the
+/// given time, along with their attachments, their files, and their places in
+/// portfolios' categories. Each work is purged in its own transaction.
+pub async fn purge_deleted_works<E>(
+    conn: &E,
+    before_timestamp: SystemTime,
+) -> Result<(), anyhow::Error>
+where
+    for<'e> &'e E: Executor<'e, Database = Any> + Acquire<'e, Database = Any>,
+{
+    let before_timestamp =
+        before_timestamp.duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as i64;
+    let work_ids: Vec<(i32,)> = sqlx::query_as("SELECT id FROM works WHERE deleted_at < $1")
+        .bind(before_timestamp)
+        .fetch_all(conn)
+        .await
+        .context("get works to purge failed")?;
+
+    for (work_id,) in work_ids {
+        let mut tx = conn.begin().await.context("beginning a transaction failed")?;
+        purge_work(&mut *tx, work_id).await?;
+        tx.commit().await.context("committing the purge failed")?;
+    }
+    let mut conn = conn.acquire().await.context("acquiring a db connection failed")?;
+    revisions::remove_unused_contents(&mut *conn).await?;
+
+    Ok(())
+}
+
+async fn purge_work<E>(conn: &mut E, work_id: i32) -> Result<(), anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    // The work's own published versions and revisions go along with it, so
+    // they shouldn't keep its files around
+    sqlx::query("DELETE FROM published_work_files WHERE work_id = $1")
+        .bind(work_id)
+        .execute(&mut *conn)
+        .await
+        .context("deleting the work's published_work_files failed")?;
+    for table in ["work_revision_contents", "work_revision_files"] {
+        let query = format!(
+            "DELETE FROM {table} WHERE revision_id IN ( select id from work_revisions where work_id = $1 )"
+        );
+        sqlx::query(&query)
+            .bind(work_id)
+            .execute(&mut *conn)
             .await
-            .context("get all works failed")?;
+            .with_context(|| format!("deleting the work's {table} failed"))?;
+    }
+    let attachment_ids: Vec<(i32,)> =
+        sqlx::query_as("SELECT id FROM work_attachments WHERE work_id = $1")
+            .bind(work_id)
+            .fetch_all(&mut *conn)
+            .await
+            .context("get the work's attachments failed")?;
+    for (attachment_id,) in attachment_ids {
+        big_files::hand_over_file_parts(&mut *conn, attachment_id).await?;
+    }
+    // The attachments refer to their first file part and vice versa, so
+    // the references are cleared before deleting either
+    sqlx::query("UPDATE work_attachments SET big_file_uuid = NULL WHERE work_id = $1")
+        .bind(work_id)
+        .execute(&mut *conn)
+        .await
+        .context("clearing attachments' file references failed")?;
+    sqlx::query("UPDATE big_file_parts SET next_uuid = NULL WHERE work_attachment_id IN ( select id from work_attachments where work_id = $1 )")
+        .bind(work_id)
+        .execute(&mut *conn)
+        .await
+        .context("clearing big file parts' references failed")?;
+    sqlx::query("DELETE FROM big_file_parts WHERE work_attachment_id IN ( select id from work_attachments where work_id = $1 )")
+        .bind(work_id)
+        .execute(&mut *conn)
+        .await
+        .context("deleting the work's big file parts failed")?;
+    for table in [
+        "work_attachments",
+        "work_links",
+        "work_tags",
+        "works_in_categories",
+        "work_rights",
+        "ownership_transfers",
+        "published_works",
+        "work_revisions",
+        "slug_history",
+    ] {
+        sqlx::query(&format!("DELETE FROM {table} WHERE work_id = $1"))
+            .bind(work_id)
+            .execute(&mut *conn)
+            .await
+            .with_context(|| format!("deleting the work's {table} failed"))?;
+    }
+    sqlx::query("DELETE FROM works WHERE id = $1")
+        .bind(work_id)
+        .execute(&mut *conn)
+        .await
+        .context("work delete failed")?;
+    Ok(())
+}
+
+pub async fn get_works<E>(conn: &E, user_id: i32) -> Result<Vec<WorkRow>, anyhow::Error>
+where
+    for<'e> &'e E: Executor<'e, Database = Any>,
+{
+    let works: Vec<WorkRow> = sqlx::query_as(
+        "SELECT * FROM works \
+        WHERE deleted_at IS NULL \
+            AND id IN ( select work_id from effective_work_rights where user_id = $1 )",
+    )
+    .bind(user_id)
+    .fetch_all(conn)
+    .await
+    .context("get all works failed")?;
 
     Ok(works)
 }
 
+/// Returns the draft of the work to its collaborators, the collaborators of
+/// the portfolios it's in and anyone with a preview token of one of those
+/// portfolios, and the published version of it to anyone else, if it's in a
+/// published portfolio which isn't password-protected, or which the viewer
+/// token is valid for.
 pub async fn get_work<E>(
     conn: &E,
     work_slug: &str,
     user_id: Option<i32>,
+    preview_token: Option<&str>,
+    viewer_token: Option<&str>,
 ) -> Result<Option<Work>, anyhow::Error>
 where
-    for<'e> &'e E: Executor<'e, Database = Any>,
+    for<'e> &'e E: Executor<'e, Database = Any> + Acquire<'e, Database = Any>,
 {
+    let current_time =
+        SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as i64;
     let query = sqlx::query_as(
         "SELECT works.* FROM works \
-        JOIN work_rights ON (works.id = work_rights.work_id) \
         LEFT JOIN works_in_categories ON (works_in_categories.work_id = works.id) \
         LEFT JOIN categories ON (categories.id = works_in_categories.category_id) \
-        LEFT JOIN portfolios ON (portfolios.id = categories.portfolio_id) \
-        LEFT JOIN portfolio_rights ON (portfolio_rights.portfolio_id = categories.portfolio_id) \
-        WHERE works.slug = $1 AND (work_rights.user_id = $2 OR portfolio_rights.user_id = $3 OR portfolios.published_at IS NOT NULL)",
+        WHERE works.slug = $1 AND works.deleted_at IS NULL \
+            AND (works.id IN ( select work_id from effective_work_rights where user_id = $2 ) \
+                OR categories.portfolio_id IN ( select portfolio_id from effective_portfolio_rights where user_id = $3 ) \
+                OR categories.portfolio_id IN ( select portfolio_previews.portfolio_id from portfolio_previews \
+                    join portfolios on (portfolios.id = portfolio_previews.portfolio_id) \
+                    where portfolio_previews.token = $4 and portfolios.deleted_at is null \
+                        and (portfolio_previews.expires_at is null or portfolio_previews.expires_at > $5) ))",
     );
     let row: Option<WorkRow> = query
         .bind(work_slug)
         .bind(user_id)
         .bind(user_id)
+        .bind(preview_token)
+        .bind(current_time)
         .fetch_optional(conn)
         .await
         .context("get work failed")?;
     if let Some(row) = row {
-        let work = subtables::fetch_work_details(conn, row).await?;
+        let mut conn = conn.acquire().await.context("acquiring a db connection failed")?;
+        let work = subtables::fetch_work_details(&mut *conn, row).await?;
         Ok(Some(work))
     } else {
-        Ok(None)
+        published::get_published_work(conn, work_slug, viewer_token).await
     }
 }
+
+/// Returns the drafts of the works in the portfolio's categories.
+pub async fn get_portfolio_works<E>(
+    conn: &mut E,
+    portfolio_id: i32,
+) -> Result<Vec<Work>, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let query = sqlx::query_as(
+        "SELECT * FROM works \
+        WHERE deleted_at IS NULL AND id IN ( \
+            select works_in_categories.work_id from works_in_categories \
+                join categories on (categories.id = works_in_categories.category_id) \
+            where categories.portfolio_id = $1 )",
+    );
+    let rows: Vec<WorkRow> = query
+        .bind(portfolio_id)
+        .fetch_all(&mut *conn)
+        .await
+        .context("get portfolio's works failed")?;
+
+    let mut works = Vec::with_capacity(rows.len());
+    for row in rows {
+        works.push(subtables::fetch_work_details(&mut *conn, row).await?);
+    }
+    Ok(works)
+}
@@ -1,9 +1,14 @@
 use anyhow::Context;
-use sqlx::{Any, Executor};
+use sqlx::{Acquire, Any, Executor};
 
 use crate::array_string_types::UuidString;
+use crate::data::audit::{AuditAction, RequestOrigin};
+use crate::data::collaborator::CollaboratorRole;
 use crate::data::work::{BigFilePart, BigFilePartDecoded};
+use crate::services::audit;
 
+/// Returns the part of a big file, if some work which isn't in the trash uses
+/// the file.
 pub async fn get_file_part<E>(
     conn: &E,
     file_uuid: &str,
@@ -14,7 +19,10 @@ where
     let query = sqlx::query_as(
         "SELECT big_file_parts.*, work_attachments.filename FROM big_file_parts \
         JOIN work_attachments ON (work_attachments.id = big_file_parts.work_attachment_id) \
-        WHERE big_file_parts.uuid = $1",
+        WHERE big_file_parts.uuid = $1 AND EXISTS ( select 1 from work_attachments sharers \
+            join works on (works.id = sharers.work_id) \
+            where (sharers.id = work_attachments.id or sharers.big_file_uuid = work_attachments.big_file_uuid) \
+                and works.deleted_at is null )",
     );
     let part: Option<BigFilePart> =
         query.bind(file_uuid).fetch_optional(conn).await.context("get big file part failed")?;
@@ -29,29 +37,57 @@ where
     }
 }
 
+/// Adds a part to the attachment's file, or replaces the file if there's no
+/// previous part. Returns None if the file would be appended to while it's
+/// shared with another attachment, a published version or a revision, since
+/// they'd see it change.
 pub async fn create_file_part<E>(
     conn: &mut E,
+    origin: &RequestOrigin,
     previous_uuid: Option<UuidString>,
     work_attachment_id: i32,
     bytes_base64: String,
     user_id: i32,
-) -> Result<UuidString, anyhow::Error>
+) -> Result<Option<UuidString>, anyhow::Error>
 where
     for<'e> &'e mut E: Executor<'e, Database = Any>,
 {
-    let query = sqlx::query(
-        "SELECT work_attachments.id FROM work_attachments \
+    let query = sqlx::query_as(
+        "SELECT works.slug, work_attachments.filename FROM work_attachments \
             JOIN works ON (works.id = work_attachments.work_id) \
-            JOIN work_rights ON (work_rights.work_id = works.id) \
-        WHERE work_attachments.id = $1 AND work_rights.user_id = $2",
+        WHERE work_attachments.id = $1 AND work_attachments.detached = 0 \
+            AND works.id IN ( select work_id from effective_work_rights where user_id = $2 and role in ($3, $4) )",
     );
-    query
+    let (work_slug, filename): (String, String) = query
         .bind(work_attachment_id)
         .bind(user_id)
+        .bind(CollaboratorRole::Owner)
+        .bind(CollaboratorRole::Editor)
         .fetch_one(&mut *conn)
         .await
         .context("user id + work attachment pair not found")?;
 
+    if previous_uuid.is_some() {
+        let query = sqlx::query_as(
+            "SELECT COUNT(*) FROM work_attachments WHERE id = $1 AND ( \
+                big_file_uuid IN ( select big_file_uuid from work_attachments where id <> $1 ) \
+                OR big_file_uuid IN ( select big_file_uuid from published_work_files ) \
+                OR big_file_uuid IN ( select big_file_uuid from work_revision_files ) )",
+        );
+        let (shared,): (i64,) = query
+            .bind(work_attachment_id)
+            .fetch_one(&mut *conn)
+            .await
+            .context("checking if the big file is shared failed")?;
+        if shared > 0 {
+            return Ok(None);
+        }
+    } else {
+        // The file is being replaced, but other works' attachments might still
+        // use the old one
+        hand_over_file_parts(&mut *conn, work_attachment_id).await?;
+    }
+
     // Insert the new part
     let new_uuid = UuidString::generate();
     let query = sqlx::query(
@@ -103,6 +139,9 @@ where
             .execute(&mut *conn)
             .await
             .context("could not update the parent work attachment with the first file part uuid")?;
+        let target = format!("{work_slug}/{filename}");
+        audit::record(&mut *conn, origin, Some(user_id), AuditAction::FileUploaded, &target)
+            .await?;
     }
 
     // Update file lengths for all parts of this file
@@ -117,5 +156,136 @@ where
         .await
         .context("failed to update file lengths for file parts")?;
 
-    Ok(new_uuid)
+    Ok(Some(new_uuid))
+}
+
+/// The parts of a big file belong to one attachment, but cloned works share
+/// their files with the original's attachments, and published versions and
+/// revisions of works keep using the files they were saved with. Before the
+/// owning attachment is deleted or its file is replaced, the parts are handed
+/// over to another work's attachment which uses the same file, if there is
+/// one, so that the file isn't deleted from under it. Otherwise, if a
+/// published version or a revision still uses the file, the parts are handed
+/// over to a new detached attachment, which holds on to them until
+/// [remove_unused_files] finds the file unused.
+pub async fn hand_over_file_parts<E>(
+    conn: &mut E,
+    work_attachment_id: i32,
+) -> Result<(), anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let (part_count,): (i64,) =
+        sqlx::query_as("SELECT COUNT(*) FROM big_file_parts WHERE work_attachment_id = $1")
+            .bind(work_attachment_id)
+            .fetch_one(&mut *conn)
+            .await
+            .context("big file part count failed")?;
+    if part_count == 0 {
+        return Ok(());
+    }
+
+    let query = sqlx::query_as(
+        "SELECT others.id FROM work_attachments others \
+            JOIN work_attachments this ON (this.big_file_uuid = others.big_file_uuid) \
+        WHERE this.id = $1 AND others.work_id <> this.work_id AND others.detached = 0 \
+        ORDER BY others.id ASC LIMIT 1",
+    );
+    let mut heir: Option<(i32,)> = query
+        .bind(work_attachment_id)
+        .fetch_optional(&mut *conn)
+        .await
+        .context("get other attachments using the file failed")?;
+
+    if heir.is_none() {
+        // Preferably kept in the same work, so that the file is deleted along
+        // with it when it's purged
+        let query = sqlx::query_as(
+            "SELECT users.work_id FROM ( \
+                select work_id, big_file_uuid from published_work_files \
+                union all \
+                select work_revisions.work_id, work_revision_files.big_file_uuid from work_revision_files \
+                    join work_revisions on (work_revisions.id = work_revision_files.revision_id) \
+            ) users \
+                JOIN work_attachments this ON (this.big_file_uuid = users.big_file_uuid) \
+            WHERE this.id = $1 \
+            ORDER BY CASE WHEN users.work_id = this.work_id THEN 0 ELSE 1 END ASC \
+            LIMIT 1",
+        );
+        let user: Option<(i32,)> = query
+            .bind(work_attachment_id)
+            .fetch_optional(&mut *conn)
+            .await
+            .context("get published works and revisions using the file failed")?;
+        if let Some((work_id,)) = user {
+            let query = sqlx::query_as(
+                "INSERT INTO work_attachments (work_id, attachment_kind, content_type, filename, title, bytes_base64, big_file_uuid, detached) \
+                SELECT $1, attachment_kind, content_type, filename, title, '', big_file_uuid, 1 \
+                FROM work_attachments WHERE id = $2 \
+                RETURNING id",
+            );
+            heir = Some(
+                query
+                    .bind(work_id)
+                    .bind(work_attachment_id)
+                    .fetch_one(&mut *conn)
+                    .await
+                    .context("detached attachment insert failed")?,
+            );
+        }
+    }
+
+    if let Some((heir_id,)) = heir {
+        sqlx::query(
+            "UPDATE big_file_parts SET work_attachment_id = $1 WHERE work_attachment_id = $2",
+        )
+        .bind(heir_id)
+        .bind(work_attachment_id)
+        .execute(&mut *conn)
+        .await
+        .context("handing over big file parts failed")?;
+    }
+    Ok(())
+}
+
+/// Deletes the detached attachments left behind by [hand_over_file_parts]
+/// whose files aren't used by any attachment, published version or revision
+/// anymore, along with the files.
+pub async fn remove_unused_files<E>(conn: &E) -> Result<(), anyhow::Error>
+where
+    for<'e> &'e E: Executor<'e, Database = Any> + Acquire<'e, Database = Any>,
+{
+    let mut tx = conn.begin().await.context("beginning a transaction failed")?;
+    let query = sqlx::query_as(
+        "SELECT id FROM work_attachments \
+        WHERE detached = 1 AND (big_file_uuid IS NULL \
+            OR (big_file_uuid NOT IN ( select big_file_uuid from work_attachments where detached = 0 and big_file_uuid is not null ) \
+                AND big_file_uuid NOT IN ( select big_file_uuid from published_work_files ) \
+                AND big_file_uuid NOT IN ( select big_file_uuid from work_revision_files )))",
+    );
+    let unused: Vec<(i32,)> =
+        query.fetch_all(&mut *tx).await.context("get unused detached attachments failed")?;
+
+    for (attachment_id,) in unused {
+        // The parts refer to each other, so the references are cleared before
+        // deleting them
+        sqlx::query("UPDATE big_file_parts SET next_uuid = NULL WHERE work_attachment_id = $1")
+            .bind(attachment_id)
+            .execute(&mut *tx)
+            .await
+            .context("clearing unused big file parts' references failed")?;
+        sqlx::query("DELETE FROM big_file_parts WHERE work_attachment_id = $1")
+            .bind(attachment_id)
+            .execute(&mut *tx)
+            .await
+            .context("unused big file parts delete failed")?;
+        sqlx::query("DELETE FROM work_attachments WHERE id = $1")
+            .bind(attachment_id)
+            .execute(&mut *tx)
+            .await
+            .context("unused detached attachment delete failed")?;
+    }
+
+    tx.commit().await.context("committing the unused file removal failed")?;
+    Ok(())
 }
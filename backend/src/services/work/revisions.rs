@@ -0,0 +1,366 @@
+//! The revision history of works. A revision is recorded of every version of a
+//! work, including the current one, and the oldest ones are pruned once a work
+//! has more than [config::work_revisions_kept]. The attachments' contents are
+//! shared with the attachments themselves through attachment_contents, and the
+//! big files the revisions use are kept around until the revisions are pruned,
+//! see [super::big_files::hand_over_file_parts].
+
+use std::time::SystemTime;
+
+use anyhow::Context;
+use sqlx::{Any, Executor};
+
+use crate::array_string_types::SlugString;
+use crate::config;
+use crate::data::audit::{AuditAction, RequestOrigin};
+use crate::data::collaborator::CollaboratorRole;
+use crate::data::work::{
+    BytesBase64, Work, WorkAttachment, WorkLink, WorkRevision, WorkRevisionAttachment,
+    WorkRevisionChange, WorkRevisionContents, WorkRevisionLink, WorkRow, WorkTag,
+};
+use crate::services::audit;
+
+/// Records the work as a new revision. The user is None if the revision
+/// wasn't made by anyone in particular.
+pub async fn record_revision<E>(
+    conn: &mut E,
+    work: &Work,
+    user_id: Option<i32>,
+) -> Result<(), anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let mut hashes = Vec::with_capacity(work.attachments.len());
+    let mut attachments = Vec::with_capacity(work.attachments.len());
+    for attachment in &work.attachments {
+        let bytes_sha256 =
+            super::subtables::save_attachment_contents(&mut *conn, &attachment.bytes_base64)
+                .await?;
+        hashes.extend(bytes_sha256.clone());
+        attachments.push(WorkRevisionAttachment {
+            attachment_kind: attachment.attachment_kind,
+            content_type: attachment.content_type,
+            filename: attachment.filename.clone(),
+            title: attachment.title.clone(),
+            bytes_sha256,
+            big_file_uuid: attachment.big_file_uuid,
+        });
+    }
+    let contents = WorkRevisionContents {
+        slug: work.row.slug,
+        title: work.row.title.clone(),
+        short_description: work.row.short_description.clone(),
+        long_description: work.row.long_description.clone(),
+        attachments,
+        links: (work.links.iter())
+            .map(|link| WorkRevisionLink { title: link.title.clone(), href: link.href.clone() })
+            .collect(),
+        tags: work.tags.iter().map(|tag| tag.tag.clone()).collect(),
+    };
+
+    let current_time =
+        SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as i64;
+    let query = sqlx::query_as(
+        "INSERT INTO work_revisions (work_id, created_at, user_id, work) VALUES ($1, $2, $3, $4) \
+        RETURNING id",
+    );
+    let (revision_id,): (i32,) = query
+        .bind(work.row.id)
+        .bind(current_time)
+        .bind(user_id)
+        .bind(serde_json::to_string(&contents)?)
+        .fetch_one(&mut *conn)
+        .await
+        .context("work revision insert failed")?;
+    for hash in hashes {
+        let query = sqlx::query(
+            "INSERT INTO work_revision_contents (revision_id, sha256) VALUES ($1, $2) \
+            ON CONFLICT (revision_id, sha256) DO NOTHING",
+        );
+        query
+            .bind(revision_id)
+            .bind(hash)
+            .execute(&mut *conn)
+            .await
+            .context("work revision contents insert failed")?;
+    }
+    for big_file_uuid in work.attachments.iter().filter_map(|a| a.big_file_uuid.as_ref()) {
+        let query = sqlx::query(
+            "INSERT INTO work_revision_files (revision_id, big_file_uuid) VALUES ($1, $2) \
+            ON CONFLICT (revision_id, big_file_uuid) DO NOTHING",
+        );
+        query
+            .bind(revision_id)
+            .bind(big_file_uuid)
+            .execute(&mut *conn)
+            .await
+            .context("work revision files insert failed")?;
+    }
+
+    if let Some(kept) = config::work_revisions_kept() {
+        prune_revisions(&mut *conn, work.row.id, kept).await?;
+    }
+
+    Ok(())
+}
+
+/// Deletes the work's revisions other than the newest `kept` ones. Their
+/// contents and files are left for [remove_unused_contents] and
+/// [super::big_files::remove_unused_files] to clean up.
+async fn prune_revisions<E>(conn: &mut E, work_id: i32, kept: u64) -> Result<(), anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let query = sqlx::query_as(
+        "SELECT id FROM work_revisions WHERE work_id = $1 ORDER BY id DESC LIMIT 1 OFFSET $2",
+    );
+    let newest_pruned: Option<(i32,)> = query
+        .bind(work_id)
+        .bind(kept as i64)
+        .fetch_optional(&mut *conn)
+        .await
+        .context("get revisions to prune failed")?;
+    let Some((newest_pruned,)) = newest_pruned else {
+        return Ok(());
+    };
+
+    for table in ["work_revision_contents", "work_revision_files"] {
+        let query = format!(
+            "DELETE FROM {table} WHERE revision_id IN \
+                ( select id from work_revisions where work_id = $1 and id <= $2 )"
+        );
+        sqlx::query(&query)
+            .bind(work_id)
+            .bind(newest_pruned)
+            .execute(&mut *conn)
+            .await
+            .with_context(|| format!("pruning the work's {table} failed"))?;
+    }
+    sqlx::query("DELETE FROM work_revisions WHERE work_id = $1 AND id <= $2")
+        .bind(work_id)
+        .bind(newest_pruned)
+        .execute(&mut *conn)
+        .await
+        .context("pruning the work's revisions failed")?;
+    Ok(())
+}
+
+/// Records the current version of the work as its first revision, if it
+/// doesn't have any revisions yet, i.e. if it was created before revisions
+/// were recorded.
+pub async fn record_initial_revision<E>(conn: &mut E, slug: &str) -> Result<(), anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let query = sqlx::query_as(
+        "SELECT * FROM works \
+        WHERE slug = $1 AND deleted_at IS NULL \
+            AND id NOT IN ( select work_id from work_revisions )",
+    );
+    let row: Option<WorkRow> =
+        query.bind(slug).fetch_optional(&mut *conn).await.context("get unrevised work failed")?;
+    if let Some(row) = row {
+        let work = super::subtables::fetch_work_details(&mut *conn, row).await?;
+        record_revision(&mut *conn, &work, None).await?;
+    }
+    Ok(())
+}
+
+/// Returns the revisions of the work, newest first, or None if the user isn't
+/// a collaborator of a work with the slug.
+pub async fn get_revisions<E>(
+    conn: &E,
+    slug: &str,
+    user_id: i32,
+) -> Result<Option<Vec<WorkRevision>>, anyhow::Error>
+where
+    for<'e> &'e E: Executor<'e, Database = Any>,
+{
+    let query = sqlx::query_as(
+        "SELECT id FROM works \
+        WHERE slug = $1 AND deleted_at IS NULL \
+            AND id IN ( select work_id from effective_work_rights where user_id = $2 )",
+    );
+    let work_id: Option<(i32,)> = query
+        .bind(slug)
+        .bind(user_id)
+        .fetch_optional(conn)
+        .await
+        .context("work id fetch for revisions failed")?;
+    let Some((work_id,)) = work_id else {
+        return Ok(None);
+    };
+
+    let query = sqlx::query_as(
+        "SELECT work_revisions.id, work_revisions.created_at, users.username FROM work_revisions \
+            LEFT JOIN users ON (users.id = work_revisions.user_id) \
+        WHERE work_revisions.work_id = $1 \
+        ORDER BY work_revisions.id DESC",
+    );
+    let revisions =
+        query.bind(work_id).fetch_all(conn).await.context("get work revisions failed")?;
+    Ok(Some(revisions))
+}
+
+/// Returns the fields which differ between the two revisions of the work, or
+/// None if the user isn't a collaborator of a work with the slug, or it
+/// doesn't have both revisions.
+pub async fn diff_revisions<E>(
+    conn: &E,
+    slug: &str,
+    user_id: i32,
+    from_revision_id: i32,
+    to_revision_id: i32,
+) -> Result<Option<Vec<WorkRevisionChange>>, anyhow::Error>
+where
+    for<'e> &'e E: Executor<'e, Database = Any>,
+{
+    let mut revisions = Vec::with_capacity(2);
+    for revision_id in [from_revision_id, to_revision_id] {
+        let query = sqlx::query_as(
+            "SELECT work_revisions.work FROM work_revisions \
+                JOIN works ON (works.id = work_revisions.work_id) \
+            WHERE works.slug = $1 AND works.deleted_at IS NULL AND work_revisions.id = $2 \
+                AND works.id IN ( select work_id from effective_work_rights where user_id = $3 )",
+        );
+        let revision: Option<(String,)> = query
+            .bind(slug)
+            .bind(revision_id)
+            .bind(user_id)
+            .fetch_optional(conn)
+            .await
+            .context("get work revision failed")?;
+        let Some((revision,)) = revision else {
+            return Ok(None);
+        };
+        let revision: serde_json::Value =
+            serde_json::from_str(&revision).context("work revision is not valid json")?;
+        revisions.push(revision);
+    }
+    let (Some(from), Some(to)) = (revisions[0].as_object(), revisions[1].as_object()) else {
+        anyhow::bail!("work revision is not a json object");
+    };
+
+    let changes = (from.iter())
+        .filter(|(field, value)| to.get(*field) != Some(*value))
+        .map(|(field, value)| WorkRevisionChange {
+            field: field.clone(),
+            from: value.clone(),
+            to: to.get(field).cloned().unwrap_or_default(),
+        })
+        .collect();
+    Ok(Some(changes))
+}
+
+/// Replaces the work's contents with the ones from the revision, which is
+/// recorded as a new revision like any other edit. The slug is not restored.
+/// Returns None if the user isn't an owner or editor of a work with the slug,
+/// or it doesn't have the revision.
+pub async fn restore_revision<E>(
+    conn: &mut E,
+    origin: &RequestOrigin,
+    slug: &str,
+    user_id: i32,
+    revision_id: i32,
+) -> Result<Option<Work>, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let query = sqlx::query_as(
+        "SELECT works.slug, work_revisions.work FROM work_revisions \
+            JOIN works ON (works.id = work_revisions.work_id) \
+        WHERE works.slug = $1 AND works.deleted_at IS NULL AND work_revisions.id = $2 \
+            AND works.id IN ( select work_id from effective_work_rights where user_id = $3 and role in ($4, $5) )",
+    );
+    let revision: Option<(SlugString, String)> = query
+        .bind(slug)
+        .bind(revision_id)
+        .bind(user_id)
+        .bind(CollaboratorRole::Owner)
+        .bind(CollaboratorRole::Editor)
+        .fetch_optional(&mut *conn)
+        .await
+        .context("get work revision to restore failed")?;
+    let Some((current_slug, revision)) = revision else {
+        return Ok(None);
+    };
+    let contents: WorkRevisionContents =
+        serde_json::from_str(&revision).context("work revision is not valid json")?;
+
+    let mut attachments = Vec::with_capacity(contents.attachments.len());
+    for attachment in contents.attachments {
+        let bytes_base64 = if let Some(hash) = &attachment.bytes_sha256 {
+            let query =
+                sqlx::query_as("SELECT bytes_base64 FROM attachment_contents WHERE sha256 = $1");
+            let (bytes_base64,): (BytesBase64,) = query
+                .bind(hash)
+                .fetch_one(&mut *conn)
+                .await
+                .context("get revision's attachment contents failed")?;
+            bytes_base64
+        } else {
+            BytesBase64(String::new())
+        };
+        let mut big_file_uuid = attachment.big_file_uuid;
+        if let Some(uuid) = &big_file_uuid {
+            let part: Option<(String,)> =
+                sqlx::query_as("SELECT uuid FROM big_file_parts WHERE uuid = $1")
+                    .bind(uuid)
+                    .fetch_optional(&mut *conn)
+                    .await
+                    .context("revision's big file check failed")?;
+            if part.is_none() {
+                big_file_uuid = None;
+            }
+        }
+        attachments.push(WorkAttachment {
+            id: 0,
+            work_id: 0,
+            attachment_kind: attachment.attachment_kind,
+            content_type: attachment.content_type,
+            filename: attachment.filename,
+            title: attachment.title,
+            bytes_base64,
+            big_file_uuid,
+        });
+    }
+    let restored = Work {
+        row: WorkRow {
+            id: 0,
+            slug: current_slug,
+            title: contents.title,
+            short_description: contents.short_description,
+            long_description: contents.long_description,
+            updated_at: 0,
+        },
+        attachments,
+        links: (contents.links.into_iter())
+            .map(|WorkRevisionLink { title, href }| WorkLink { id: 0, work_id: 0, title, href })
+            .collect(),
+        tags: (contents.tags.into_iter()).map(|tag| WorkTag { id: 0, work_id: 0, tag }).collect(),
+    };
+
+    let Some(work) = super::update_work(&mut *conn, origin, slug, user_id, restored).await? else {
+        return Ok(None);
+    };
+    let audit_target = format!("{slug}/{revision_id}");
+    let action = AuditAction::WorkRevisionRestored;
+    audit::record(&mut *conn, origin, Some(user_id), action, &audit_target).await?;
+
+    Ok(Some(work))
+}
+
+/// Deletes the attachment contents which aren't used by any attachment or
+/// revision.
+pub async fn remove_unused_contents<E>(conn: &mut E) -> Result<(), anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    let query = sqlx::query(
+        "DELETE FROM attachment_contents \
+        WHERE sha256 NOT IN ( select sha256 from work_revision_contents ) \
+            AND sha256 NOT IN ( select content_sha256 from work_attachments where content_sha256 is not null )",
+    );
+    query.execute(&mut *conn).await.context("unused attachment contents delete failed")?;
+    Ok(())
+}
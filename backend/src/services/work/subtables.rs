@@ -1,29 +1,37 @@
 use anyhow::Context;
+use data_encoding::HEXLOWER;
+use ring::digest::{self, SHA256};
 use sqlx::{Any, Executor};
 
-use crate::data::work::{Work, WorkAttachment, WorkLink, WorkRow, WorkTag};
+use super::big_files;
+use crate::data::work::{BytesBase64, Work, WorkAttachment, WorkLink, WorkRow, WorkTag};
 
-pub async fn fetch_work_details<E>(conn: &E, row: WorkRow) -> Result<Work, anyhow::Error>
+pub async fn fetch_work_details<E>(conn: &mut E, row: WorkRow) -> Result<Work, anyhow::Error>
 where
-    for<'e> &'e E: Executor<'e, Database = Any>,
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
 {
     let query = sqlx::query_as(
-        "SELECT id, work_id, attachment_kind, content_type, filename, title, bytes_base64, big_file_uuid FROM work_attachments \
-        WHERE work_id = $1",
+        "SELECT work_attachments.id, work_attachments.work_id, work_attachments.attachment_kind, \
+            work_attachments.content_type, work_attachments.filename, work_attachments.title, \
+            COALESCE(attachment_contents.bytes_base64, '') AS bytes_base64, \
+            work_attachments.big_file_uuid \
+        FROM work_attachments \
+            LEFT JOIN attachment_contents ON (attachment_contents.sha256 = work_attachments.content_sha256) \
+        WHERE work_attachments.work_id = $1 AND work_attachments.detached = 0",
     );
     let attachments =
-        query.bind(row.id).fetch_all(conn).await.context("get work attachments failed")?;
+        query.bind(row.id).fetch_all(&mut *conn).await.context("get work attachments failed")?;
 
     let links = sqlx::query_as("SELECT * FROM work_links WHERE work_id = $1")
         .bind(row.id)
-        .fetch_all(conn)
+        .fetch_all(&mut *conn)
         .await
         .context("get work links failed")?;
 
     let tags =
         sqlx::query_as("SELECT * FROM work_tags WHERE work_id = $1 ORDER BY order_number ASC")
             .bind(row.id)
-            .fetch_all(conn)
+            .fetch_all(&mut *conn)
             .await
             .context("get work tags failed")?;
 
@@ -42,7 +50,7 @@ where
 {
     // First, get the old attachments (to delete later)...
     let old_attachments: Vec<(i32,)> =
-        sqlx::query_as("SELECT id FROM work_attachments WHERE work_id = $1")
+        sqlx::query_as("SELECT id FROM work_attachments WHERE work_id = $1 AND detached = 0")
             .bind(row.id)
             .fetch_all(&mut *conn)
             .await
@@ -51,35 +59,41 @@ where
     // ...add in new attachments...
     let mut attachments: Vec<WorkAttachment> = Vec::with_capacity(new_attachments.len());
     for input in new_attachments {
+        let content_sha256 = save_attachment_contents(&mut *conn, &input.bytes_base64).await?;
         let query = sqlx::query_as(
-            "INSERT INTO work_attachments (work_id, attachment_kind, content_type, filename, title, bytes_base64, big_file_uuid) \
-            VALUES ($1, $2, $3, $4, $5, $6, $7) \
+            "INSERT INTO work_attachments (work_id, attachment_kind, content_type, filename, title, bytes_base64, big_file_uuid, content_sha256) \
+            VALUES ($1, $2, $3, $4, $5, '', $6, $7) \
             RETURNING id, work_id, attachment_kind, content_type, filename, title, bytes_base64, big_file_uuid",
         );
-        let new_attachment = query
+        let mut new_attachment: WorkAttachment = query
             .bind(row.id)
-            .bind(&input.attachment_kind)
+            .bind(input.attachment_kind)
             .bind(&input.content_type)
             .bind(&input.filename)
             .bind(&input.title)
-            .bind(&input.bytes_base64)
             .bind(input.big_file_uuid.as_ref())
+            .bind(content_sha256)
             .fetch_one(&mut *conn)
             .await
             .context("insert into work attachments failed")?;
+        new_attachment.bytes_base64 = BytesBase64(input.bytes_base64.0.clone());
         attachments.push(new_attachment);
     }
 
-    // ...update any relevant big_file_parts to point to the new attachments...
+    // ...update any relevant big_file_parts to point to the new attachments
+    // (only taking them from this work's old attachments, since the same file
+    // may be owned by another work's attachment, e.g. if this is a clone)...
     for inserted_attachment in &attachments {
         if let Some(uuid) = inserted_attachment.big_file_uuid.as_ref() {
             let query = sqlx::query(
                 "UPDATE big_file_parts SET work_attachment_id = $1 \
-                WHERE work_attachment_id = (SELECT work_attachment_id FROM big_file_parts WHERE uuid = $2)"
+                WHERE work_attachment_id = (SELECT work_attachment_id FROM big_file_parts WHERE uuid = $2) \
+                    AND work_attachment_id IN ( select id from work_attachments where work_id = $3 and detached = 0 and id <> $1 )",
             );
             query
                 .bind(inserted_attachment.id)
                 .bind(uuid)
+                .bind(row.id)
                 .execute(&mut *conn)
                 .await
                 .context("failed to update big file parts with new work attachment ids")?;
@@ -88,6 +102,7 @@ where
 
     // ...and finally, delete the old attachments.
     for (old_id,) in old_attachments {
+        big_files::hand_over_file_parts(&mut *conn, old_id).await?;
         sqlx::query("DELETE FROM work_attachments WHERE id = $1")
             .bind(old_id)
             .execute(&mut *conn)
@@ -141,3 +156,31 @@ where
 
     Ok(Work { row, attachments, links, tags })
 }
+
+/// Stores the attachment's inline contents in attachment_contents, where
+/// they're shared by every attachment and revision with the same contents.
+/// Returns the key of the contents, or None if there aren't any.
+pub async fn save_attachment_contents<E>(
+    conn: &mut E,
+    bytes_base64: &BytesBase64,
+) -> Result<Option<String>, anyhow::Error>
+where
+    for<'e> &'e mut E: Executor<'e, Database = Any>,
+{
+    if bytes_base64.0.is_empty() {
+        return Ok(None);
+    }
+    let hash = digest::digest(&SHA256, bytes_base64.0.as_bytes());
+    let hash = HEXLOWER.encode(hash.as_ref());
+    let query = sqlx::query(
+        "INSERT INTO attachment_contents (sha256, bytes_base64) VALUES ($1, $2) \
+        ON CONFLICT (sha256) DO NOTHING",
+    );
+    query
+        .bind(&hash)
+        .bind(bytes_base64)
+        .execute(&mut *conn)
+        .await
+        .context("attachment contents insert failed")?;
+    Ok(Some(hash))
+}
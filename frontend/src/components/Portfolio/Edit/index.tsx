@@ -101,43 +101,78 @@ export const PortfolioEditor = (props: { slug?: string }) => {
         });
     }, [slug, title, subtitle, author, categories, publish, isEdit]);
 
-    const submitHandler: FormEventHandler<HTMLFormElement> = (event) => {
-        event.preventDefault();
+    // Saving only changes the draft of a published portfolio, the published
+    // version is replaced separately.
+    const mapPublishResult = useCallback(typecheckPortfolio, []);
+    const [publishedBody, setPublishedBody] = useState<BodyInit | null>(null);
+    const {
+        refetch: publishPortfolio,
+        loading: publishLoading,
+    } = useApiFetch(`/portfolio/${slug}/publish`, mapPublishResult, { method: "POST" }, true);
+
+    const validate = () => {
         setShouldValidate(true);
-        const validArguments = validateSlug(slug) == null
+        return validateSlug(slug) == null
             && validateTitle(title) == null
             && validateSubtitle(subtitle) == null
             && validateAuthor(author) == null
             && validatePublish(publish) == null;
-        if (!validArguments) {
+    };
+
+    /** Saves the draft, returning whether it succeeded. */
+    const save = async () => {
+        const result = await createPortfolio();
+
+        if ("userError" in result) {
+            setLatestSentReqParams({});
+            setServerError(result.userError);
+            if (result.userError === ApiError.SlugTaken) {
+                setSlugsInUse(slugsInUse.concat(slug));
+                setServerError("");
+            }
+            return false;
+        }
+
+        if (isEdit) {
+            setShouldValidate(false);
+            navigate(`/p/${result.value.slug}/edit`);
+        } else {
+            setSlug("");
+            setTitle("");
+            setSubtitle("");
+            setAuthor("");
+            setPublish(false);
+            navigate(`/p/${result.value.slug}`);
+        }
+
+        setLatestSentReqParams(reqParams);
+        setServerError("");
+        return true;
+    };
+
+    const submitHandler: FormEventHandler<HTMLFormElement> = (event) => {
+        event.preventDefault();
+        if (!validate()) {
+            return;
+        }
+        void save();
+    };
+
+    const publishHandler = () => {
+        if (!validate()) {
             return;
         }
         const submit = async () => {
-            const result = await createPortfolio();
+            if (reqParams.body !== latestSentReqParams.body && !(await save())) {
+                return;
+            }
 
+            const result = await publishPortfolio();
             if ("userError" in result) {
-                setLatestSentReqParams({});
                 setServerError(result.userError);
-                if (result.userError === ApiError.SlugTaken) {
-                    setSlugsInUse(slugsInUse.concat(slug));
-                    setServerError("");
-                }
                 return;
             }
-
-            if (isEdit) {
-                setShouldValidate(false);
-                navigate(`/p/${result.value.slug}/edit`);
-            } else {
-                setSlug("");
-                setTitle("");
-                setSubtitle("");
-                setAuthor("");
-                setPublish(false);
-                navigate(`/p/${result.value.slug}`);
-            }
-
-            setLatestSentReqParams(reqParams);
+            setPublishedBody(reqParams.body);
             setServerError("");
         };
         void submit();
@@ -175,6 +210,11 @@ export const PortfolioEditor = (props: { slug?: string }) => {
                         {isEdit && reqParams.body !== latestSentReqParams.body && t("action.edit-portfolio")}
                         {!isEdit && t("action.create-portfolio")}
                     </Button>
+                    {isEdit && publish && <Button variant="secondary" className="ms-2" onClick={publishHandler}
+                        disabled={loading || publishLoading || reqParams.body === publishedBody}>
+                        {publishLoading && <Spinner size="sm" role="status" aria-hidden="true" style={{ marginRight: 6 }} />}
+                        {reqParams.body === publishedBody ? t("action.publish-portfolio-done") : t("action.publish-portfolio")}
+                    </Button>}
                 </Form.Group>
             </Form>
         </Container>
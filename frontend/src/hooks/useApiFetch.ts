@@ -21,6 +21,43 @@ export enum ApiError {
     NoSuchSlug = "NoSuchSlug",
     SlugTaken = "SlugTaken",
     OwnedDocumentNotFound = "OwnedDocumentNotFound",
+    TotpAlreadyEnabled = "TotpAlreadyEnabled",
+    InvalidTotpCode = "InvalidTotpCode",
+    TooManyRequests = "TooManyRequests",
+    InsufficientScope = "InsufficientScope",
+    NoSuchApiToken = "NoSuchApiToken",
+    RegistrationClosed = "RegistrationClosed",
+    InvalidInviteCode = "InvalidInviteCode",
+    NoSuchInvite = "NoSuchInvite",
+    Forbidden = "Forbidden",
+    AccountDisabled = "AccountDisabled",
+    NoSuchUser = "NoSuchUser",
+    InvalidPasswordReset = "InvalidPasswordReset",
+    CannotDisableSelf = "CannotDisableSelf",
+    OidcLoginFailed = "OidcLoginFailed",
+    OidcUsernameRequired = "OidcUsernameRequired",
+    OidcIdentityInUse = "OidcIdentityInUse",
+    CannotUnlinkLastLogin = "CannotUnlinkLastLogin",
+    NoSuchOidcIdentity = "NoSuchOidcIdentity",
+    OidcDisabled = "OidcDisabled",
+    InvalidAvatar = "InvalidAvatar",
+    AvatarTooLarge = "AvatarTooLarge",
+    InvalidUsername = "InvalidUsername",
+    CannotRemoveLastOwner = "CannotRemoveLastOwner",
+    CannotTransferToSelf = "CannotTransferToSelf",
+    NoSuchTransfer = "NoSuchTransfer",
+    CannotRemoveLastAdmin = "CannotRemoveLastAdmin",
+    NoSuchOrganization = "NoSuchOrganization",
+    NoSuchRevision = "NoSuchRevision",
+    NoSuchPreview = "NoSuchPreview",
+    PortfolioLocked = "PortfolioLocked",
+    InvalidSlug = "InvalidSlug",
+    ProfileTextTooLong = "ProfileTextTooLong",
+    TooManyProfileLinks = "TooManyProfileLinks",
+    InvalidProfileLink = "InvalidProfileLink",
+    InvalidPublishSchedule = "InvalidPublishSchedule",
+    PreviewAlreadyExpired = "PreviewAlreadyExpired",
+    FileShared = "FileShared",
 }
 
 type ApiResponse<T> = { value: T } | { userError: ApiError };